@@ -0,0 +1,46 @@
+//! Instructions per second with and without the decoded instruction cache,
+//! and with the block engine.
+//!
+//!     cargo bench --bench interpreter
+
+use rust_chip8::{Engine, Platform, CPU};
+use std::hint::black_box;
+use std::time::Instant;
+
+const FRAMES: usize = 5_000;
+const CYCLES_PER_FRAME: usize = 1_000;
+
+// A tight loop of arithmetic, index updates and a skip, as game loops are.
+const PROGRAM: [u8; 16] = [
+    0x70, 0x01, // V0 += 1
+    0x81, 0x04, // V1 += V0
+    0x82, 0x13, // V2 ^= V1
+    0xA3, 0x00, // I = 0x300
+    0xF2, 0x1E, // I += V2
+    0x30, 0x00, // skip if V0 == 0
+    0x12, 0x00, // jump 200
+    0x12, 0x00, // jump 200
+];
+
+fn instructions_per_second(engine: Engine, cache: bool) -> f64 {
+    let mut cpu = CPU::with_platform(Platform::Chip8, Platform::Chip8.default_quirks());
+    cpu.set_engine(engine);
+    cpu.set_instruction_cache(cache);
+    cpu.load(PROGRAM.to_vec()).unwrap();
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        black_box(&mut cpu).run_frame(CYCLES_PER_FRAME).unwrap();
+    }
+    (FRAMES * CYCLES_PER_FRAME) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    // warm up
+    instructions_per_second(Engine::Interpreter, true);
+    let uncached = instructions_per_second(Engine::Interpreter, false);
+    let cached = instructions_per_second(Engine::Interpreter, true);
+    let blocks = instructions_per_second(Engine::Blocks, true);
+    println!("decode every fetch: {:>6.1} M instructions/s", uncached / 1e6);
+    println!("decoded cache:      {:>6.1} M instructions/s  {:.2}x", cached / 1e6, cached / uncached);
+    println!("block engine:       {:>6.1} M instructions/s  {:.2}x", blocks / 1e6, blocks / uncached);
+}
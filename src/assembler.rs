@@ -0,0 +1,815 @@
+//! An assembler for Octo source, producing a ROM image, its labels and a
+//! source map.
+//!
+//! Supported: labels (`: name`), `:const`, `:alias`, `:macro`, `:org`,
+//! `:call`, `:byte`, bare numbers as data bytes, every CHIP-8, SUPER-CHIP and
+//! XO-CHIP instruction, `if ... then`, `if ... begin ... else ... end`, and
+//! `loop ... while ... again`. As in Octo, the program starts with a jump to
+//! `main` unless `: main` comes first.
+//!
+//! Instructions are built as [`Instruction`]s and written with
+//! [`Instruction::encode`], so the assembler and the interpreter's decoder
+//! agree on every opcode.
+
+use crate::instruction::Instruction::{self, *};
+use crate::source_map::SourceMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+const START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+// Deepest macro calls may nest, which stops macros that call themselves.
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// An assembled program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// The bytes to load at 0x200.
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, usize>,
+    /// The source line of each instruction, by address.
+    pub lines: BTreeMap<usize, usize>,
+}
+
+/// Why a program could not be assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+impl Assembly {
+    /// The labels as text, one `ADDRESS NAME` per line in address order.
+    pub fn symbols(&self) -> String {
+        let mut labels: Vec<(&usize, &String)> = self.labels.iter().map(|(name, address)| (address, name)).collect();
+        labels.sort();
+        labels.iter().map(|(address, name)| format!("{:#06x} {}\n", address, name)).collect()
+    }
+
+    /// A source map for the program, as assembled from `path`.
+    pub fn source_map(&self, path: &Path) -> SourceMap {
+        let mut map = SourceMap::new();
+        for (address, line) in &self.lines {
+            map.insert(*address, path, *line);
+        }
+        map
+    }
+}
+
+/// Assembles Octo source.
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut assembler = Assembler::new(source);
+    while let Some(token) = assembler.tokens.pop_front() {
+        assembler.line = token.line;
+        assembler.depth = token.depth;
+        assembler.statement(&token.text)?;
+    }
+    assembler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    // how many macro expansions produced it
+    depth: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// An instruction whose address operand is a label that was not defined yet.
+#[derive(Debug, Clone)]
+struct Fixup {
+    address: usize,
+    instruction: fn(u16) -> Instruction,
+    label: String,
+    line: usize,
+}
+
+// A `loop` or `if ... begin` waiting for its end.
+#[derive(Debug, Clone)]
+enum Block {
+    Loop { start: usize, exits: Vec<usize> },
+    If { jump: usize, has_else: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+// A condition for `if` and `while`.
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn not(self) -> Self {
+        match self {
+            Condition::Equal(x, o) => Condition::NotEqual(x, o),
+            Condition::NotEqual(x, o) => Condition::Equal(x, o),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    // The skip that runs the next instruction only when the condition holds.
+    fn skip(self) -> Instruction {
+        match self {
+            Condition::Equal(x, Operand::Byte(nn)) => SkipINEQ(x, nn),
+            Condition::NotEqual(x, Operand::Byte(nn)) => SkipIEQ(x, nn),
+            Condition::Equal(x, Operand::Register(y)) => SkipRNEQ(x, y),
+            Condition::NotEqual(x, Operand::Register(y)) => SkipREQ(x, y),
+            Condition::Key(x) => SkipKeyNEQ(x),
+            Condition::NotKey(x) => SkipKeyEQ(x),
+        }
+    }
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    // line and macro depth of the statement being assembled
+    line: usize,
+    depth: usize,
+    memory: Vec<u8>,
+    pos: usize,
+    end: usize,
+    labels: BTreeMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    lines: BTreeMap<usize, usize>,
+    // whether 0x200 is still kept for a jump to main
+    main_jump: bool,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(n, line)| {
+                let code = line.split('#').next().unwrap_or("");
+                code.split_whitespace().map(move |text| Token { text: text.to_string(), line: n + 1, depth: 0 })
+            })
+            .collect();
+        Assembler {
+            tokens,
+            line: 1,
+            depth: 0,
+            memory: vec![0; MEMORY_SIZE],
+            pos: START + 2,
+            end: START + 2,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            lines: BTreeMap::new(),
+            main_jump: true,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, AssembleError> {
+        Err(AssembleError { line: self.line, message })
+    }
+
+    fn next(&mut self) -> Result<String, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => Ok(token.text),
+            None => self.error("Unexpected end of program".to_string()),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected {} but found {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|t| t.text == text)
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), AssembleError> {
+        match token {
+            ":" => {
+                let name = self.next()?;
+                self.define(name)?;
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.number()?;
+                match usize::try_from(address).ok().filter(|a| *a < MEMORY_SIZE) {
+                    Some(address) => self.pos = address,
+                    None => return self.error(format!("{:#x} is outside memory", address)),
+                }
+            },
+            ":call" => {
+                let target = self.address(Call)?;
+                self.emit(Call(target))?;
+            },
+            ":byte" => {
+                let value = self.byte()?;
+                self.write(&[value])?;
+            },
+            "clear" => self.emit(ClearScreen)?,
+            "return" | ";" => self.emit(Return)?,
+            "exit" => self.emit(Exit)?,
+            "lores" => self.emit(LowRes)?,
+            "hires" => self.emit(HighRes)?,
+            "scroll-left" => self.emit(ScrollLeft)?,
+            "scroll-right" => self.emit(ScrollRight)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(ScrollDown(n))?;
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(ScrollUp(n))?;
+            },
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(SelectPlane(n))?;
+            },
+            "audio" => self.emit(LoadAudio)?,
+            "jump" => {
+                let target = self.address(Jump)?;
+                self.emit(Jump(target))?;
+            },
+            "jump0" => {
+                let target = self.address(JumpOffset)?;
+                self.emit(JumpOffset(target))?;
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Draw(x, y, n))?;
+            },
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(StoreDecimalR(x))?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token == "save" {StoreRange(x, y)} else {LoadRange(x, y)}
+                } else if token == "save" {
+                    Store(x)
+                } else {
+                    Load(x)
+                };
+                self.emit(instruction)?;
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(StoreFlags(x))?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(LoadFlags(x))?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token {
+                    "delay" => SetDelayR(x),
+                    "buzzer" => SetSoundR(x),
+                    _ => SetPitchR(x),
+                })?;
+            },
+            "i" => self.index()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.emit(condition.skip())?,
+                    "begin" => {
+                        let jump = self.skip_jump(condition)?;
+                        self.blocks.push(Block::If { jump, has_else: false });
+                    },
+                    other => return self.error(format!("Expected then or begin but found {}", other)),
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, has_else: false }) => {
+                    let end = self.pos;
+                    self.emit(Jump(0))?;
+                    self.patch(jump, self.pos)?;
+                    self.blocks.push(Block::If { jump: end, has_else: true });
+                },
+                _ => return self.error("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch(jump, self.pos)?,
+                _ => return self.error("end without if ... begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.pos, exits: Vec::new() }),
+            "while" => {
+                let condition = self.condition()?;
+                let jump = self.skip_jump(condition)?;
+                match self.blocks.iter_mut().rev().find(|b| matches!(b, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(jump),
+                    _ => return self.error("while outside a loop".to_string()),
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    if start > 0xFFF {
+                        return self.error(format!("Loop at {:#x} is out of range", start));
+                    }
+                    self.emit(Jump(start as u16))?;
+                    for exit in exits {
+                        self.patch(exit, self.pos)?;
+                    }
+                },
+                _ => return self.error("again without loop".to_string()),
+            },
+            _ => {
+                if let Some(body) = self.expand(token)? {
+                    for token in body.into_iter().rev() {
+                        self.tokens.push_front(token);
+                    }
+                } else if let Some(x) = self.parse_register(token) {
+                    self.register_op(x)?;
+                } else if let Some(value) = self.value(token) {
+                    let value = self.to_byte(value)?;
+                    self.write(&[value])?;
+                } else if is_name(token) {
+                    // a bare label calls it
+                    let target = self.label_address(token, Call)?;
+                    self.emit(Call(target))?;
+                } else {
+                    return self.error(format!("Unknown word {}", token));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: String) -> Result<(), AssembleError> {
+        if !is_name(&name) {
+            return self.error(format!("{} is not a valid label", name));
+        }
+        if self.labels.contains_key(&name) {
+            return self.error(format!("Label {} is defined twice", name));
+        }
+        // main first: no need for the jump, unless a label already points past it
+        if name == "main" && self.main_jump && self.pos == START + 2 && self.end == START + 2 && self.labels.is_empty() {
+            self.main_jump = false;
+            self.pos = START;
+            self.end = START;
+        }
+        self.labels.insert(name, self.pos);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        let mut params = Vec::new();
+        loop {
+            match self.next()? {
+                brace if brace == "{" => break,
+                param => params.push(param),
+            }
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let Some(token) = self.tokens.pop_front() else {
+                return self.error(format!("Macro {} has no closing }}", name));
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // The tokens a macro call stands for, with its arguments substituted.
+    fn expand(&mut self, name: &str) -> Result<Option<Vec<Token>>, AssembleError> {
+        let Some(definition) = self.macros.get(name).cloned() else {
+            return Ok(None);
+        };
+        if self.depth >= MAX_MACRO_DEPTH {
+            return self.error(format!("Macro {} nests more than {} deep", name, MAX_MACRO_DEPTH));
+        }
+        let mut args = HashMap::new();
+        for param in &definition.params {
+            args.insert(param.clone(), self.next()?);
+        }
+        let body = definition.body.into_iter().map(|token| Token {
+            text: args.get(&token.text).cloned().unwrap_or(token.text),
+            line: self.line,
+            depth: self.depth + 1,
+        });
+        Ok(Some(body.collect()))
+    }
+
+    fn index(&mut self) -> Result<(), AssembleError> {
+        match self.next()?.as_str() {
+            ":=" => {
+                let instruction = match self.tokens.front().map(|t| t.text.as_str()) {
+                    Some("hex") => {
+                        self.next()?;
+                        SetXFontR(self.register()?)
+                    },
+                    Some("bighex") => {
+                        self.next()?;
+                        SetXBigFontR(self.register()?)
+                    },
+                    Some("long") => {
+                        self.next()?;
+                        SetXLong(self.address(SetXLong)?)
+                    },
+                    _ => SetX(self.address(SetX)?),
+                };
+                self.emit(instruction)
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(AddXR(x))
+            },
+            other => self.error(format!("Expected := or += after i but found {}", other)),
+        }
+    }
+
+    fn register_op(&mut self, x: u8) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let instruction = match op.as_str() {
+            ":=" => match self.next()?.as_str() {
+                "random" => Random(x, self.byte()?),
+                "delay" => SetRDelay(x),
+                "key" => GetKey(x),
+                operand => match self.operand(operand)? {
+                    Operand::Register(y) => SetRR(x, y),
+                    Operand::Byte(nn) => SetRI(x, nn),
+                },
+            },
+            "+=" | "-=" => {
+                let operand = self.next()?;
+                match (self.operand(&operand)?, op.as_str()) {
+                    (Operand::Register(y), "+=") => AddRR(x, y),
+                    (Operand::Register(y), _) => SubAB(x, y),
+                    (Operand::Byte(nn), "+=") => AddRI(x, nn),
+                    (Operand::Byte(nn), _) => AddRI(x, nn.wrapping_neg()),
+                }
+            },
+            "|=" => OrRR(x, self.register()?),
+            "&=" => AndRR(x, self.register()?),
+            "^=" => XorRR(x, self.register()?),
+            "=-" => SubBA(x, self.register()?),
+            ">>=" => ShiftRightRR(x, self.register()?),
+            "<<=" => ShiftLeftRR(x, self.register()?),
+            other => return self.error(format!("Unknown operator {}", other)),
+        };
+        self.emit(instruction)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.register()?;
+        match self.next()?.as_str() {
+            "==" => {
+                let operand = self.next()?;
+                Ok(Condition::Equal(x, self.operand(&operand)?))
+            },
+            "!=" => {
+                let operand = self.next()?;
+                Ok(Condition::NotEqual(x, self.operand(&operand)?))
+            },
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            other => self.error(format!("Unsupported comparison {}", other)),
+        }
+    }
+
+    // Emits a skip and a jump that is taken when `condition` is false,
+    // returning the jump's address so it can be patched.
+    fn skip_jump(&mut self, condition: Condition) -> Result<usize, AssembleError> {
+        self.emit(condition.not().skip())?;
+        let jump = self.pos;
+        self.emit(Jump(0))?;
+        Ok(jump)
+    }
+
+    fn patch(&mut self, address: usize, target: usize) -> Result<(), AssembleError> {
+        if target > 0xFFF {
+            return self.error(format!("Jump to {:#x} is out of range", target));
+        }
+        let bytes = Jump(target as u16).encode();
+        self.memory[address..address + bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn operand(&mut self, token: &str) -> Result<Operand, AssembleError> {
+        match self.parse_register(token) {
+            Some(y) => Ok(Operand::Register(y)),
+            None => match self.value(token) {
+                Some(value) => Ok(Operand::Byte(self.to_byte(value)?)),
+                None => self.error(format!("Expected a register or number but found {}", token)),
+            },
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.parse_register(&token) {
+            Some(x) => Ok(x),
+            None => self.error(format!("Expected a register but found {}", token)),
+        }
+    }
+
+    fn parse_register(&self, token: &str) -> Option<u8> {
+        if let Some(x) = self.aliases.get(token) {
+            return Some(*x);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        match digit.len() {
+            1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    // A number or constant.
+    fn value(&self, token: &str) -> Option<i64> {
+        parse_number(token).or_else(|| self.constants.get(token).copied())
+    }
+
+    fn number(&mut self) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        match self.value(&token) {
+            Some(value) => Ok(value),
+            None => self.error(format!("Expected a number but found {}", token)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let value = self.number()?;
+        self.to_byte(value)
+    }
+
+    fn to_byte(&self, value: i64) -> Result<u8, AssembleError> {
+        match value {
+            -128..=255 => Ok(value as u8),
+            _ => self.error(format!("{} does not fit in a byte", value)),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        match self.number()? {
+            n @ 0..=15 => Ok(n as u8),
+            n => self.error(format!("{} does not fit in 4 bits", n)),
+        }
+    }
+
+    // The address operand of `instruction`, which is fixed up later if it is
+    // a label that is not defined yet.
+    fn address(&mut self, instruction: fn(u16) -> Instruction) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        // only `i := long` reaches past 0xFFF
+        let max = if instruction(0) == SetXLong(0) {0xFFFF} else {0xFFF};
+        match self.value(&token) {
+            Some(value @ 0..) if value <= max => Ok(value as u16),
+            Some(value) => self.error(format!("{:#x} is out of range", value)),
+            None if is_name(&token) => self.label_address(&token, instruction),
+            None => self.error(format!("Expected an address but found {}", token)),
+        }
+    }
+
+    fn label_address(&mut self, label: &str, instruction: fn(u16) -> Instruction) -> Result<u16, AssembleError> {
+        match self.labels.get(label) {
+            Some(&address) if address > 0xFFF && instruction(0) != SetXLong(0) => {
+                self.error(format!("{} at {:#x} is out of range", label, address))
+            },
+            Some(&address) => Ok(address as u16),
+            None => {
+                self.fixups.push(Fixup { address: self.pos, instruction, label: label.to_string(), line: self.line });
+                Ok(0)
+            },
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), AssembleError> {
+        self.lines.insert(self.pos, self.line);
+        self.write(&instruction.encode())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), AssembleError> {
+        let end = self.pos + bytes.len();
+        if end > MEMORY_SIZE {
+            return self.error("Program does not fit in memory".to_string());
+        }
+        self.memory[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+        self.end = self.end.max(end);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Assembly, AssembleError> {
+        if let Some(block) = self.blocks.last() {
+            let open = if matches!(block, Block::Loop { .. }) {"loop without again"} else {"if ... begin without end"};
+            return self.error(open.to_string());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&target) = self.labels.get(&fixup.label) else {
+                return Err(AssembleError { line: fixup.line, message: format!("Undefined label {}", fixup.label) });
+            };
+            let instruction = (fixup.instruction)(0);
+            if target > 0xFFF && instruction != SetXLong(0) {
+                return Err(AssembleError { line: fixup.line, message: format!("{} at {:#x} is out of range", fixup.label, target) });
+            }
+            let bytes = (fixup.instruction)(target as u16).encode();
+            self.memory[fixup.address..fixup.address + bytes.len()].copy_from_slice(&bytes);
+        }
+        if self.main_jump {
+            let Some(&main) = self.labels.get("main") else {
+                return self.error("The program has no main label".to_string());
+            };
+            self.patch(START, main)?;
+        }
+        Ok(Assembly {
+            rom: self.memory[START..self.end.max(START)].to_vec(),
+            labels: self.labels,
+            lines: self.lines,
+        })
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative {-value} else {value})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source).unwrap_or_else(|err| panic!("{}", err)).rom
+    }
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn assembles_instructions_and_data() {
+        let source = "
+            :alias x v3
+            :const SPEED 2
+            : main
+                clear
+                x := SPEED
+                i := sprite   # forward reference
+                sprite x v4 3
+                v0 -= 1
+                i := long sprite
+                jump main
+            : sprite
+                0b11110000 0x90 255
+        ";
+        assert_eq!(rom(source), [
+            0x00, 0xE0, 0x63, 0x02, 0xA2, 0x10, 0xD3, 0x43, 0x70, 0xFF,
+            0xF0, 0x00, 0x02, 0x10, 0x12, 0x00, 0xF0, 0x90, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_eq!(rom(": draw clear ; : main draw"), [0x12, 0x06, 0x00, 0xE0, 0x00, 0xEE, 0x22, 0x02]);
+        let assembly = assemble(": main :org 0x300 : data 1").unwrap();
+        assert_eq!(assembly.rom.len(), 0x101);
+        assert_eq!(assembly.labels["data"], 0x300);
+        assert_eq!(assembly.symbols(), "0x0200 main\n0x0300 data\n");
+        assert_eq!(rom(": foo : main clear jump foo"), [0x12, 0x02, 0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn assembles_control_flow() {
+        let source = "
+            : main
+                if v0 == 1 then v1 := 2
+                if v0 != v2 begin
+                    v1 := 3
+                else
+                    v1 := 4
+                end
+                loop
+                    v0 += 1
+                    while v0 -key
+                again
+        ";
+        assert_eq!(rom(source), [
+            0x40, 0x01, 0x61, 0x02, // if v0 == 1 then
+            0x90, 0x20, 0x12, 0x0C, // skip if v0 != v2, otherwise jump to else
+            0x61, 0x03, 0x12, 0x0E, // v1 := 3, jump to end
+            0x61, 0x04,             // else: v1 := 4
+            0x70, 0x01,             // loop: v0 += 1
+            0xE0, 0xA1, 0x12, 0x16, // while v0 -key
+            0x12, 0x0E,             // again
+        ]);
+    }
+
+    #[test]
+    fn expands_macros() {
+        let source = ":macro twice op reg { reg op 1 reg op 1 } : main twice += v2";
+        assert_eq!(rom(source), [0x72, 0x01, 0x72, 0x01]);
+        let nested = ":macro one { v0 += 1 } :macro two { one one } : main two";
+        assert_eq!(rom(nested), [0x70, 0x01, 0x70, 0x01]);
+    }
+
+    #[test]
+    fn stops_recursive_macros() {
+        assert_eq!(error(":macro m { m } : main\n m"), "line 2: Macro m nests more than 64 deep");
+        assert_eq!(error(":macro m { m m } : main m"), "line 1: Macro m nests more than 64 deep");
+    }
+
+    #[test]
+    fn records_source_lines() {
+        let assembly = assemble(": main\n  clear\n\n  jump main\n").unwrap();
+        let map = assembly.source_map(Path::new("game.8o"));
+        assert_eq!(map.location(0x202).map(|l| l.line), Some(4));
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        assert_eq!(error(": main\n jump nowhere"), "line 2: Undefined label nowhere");
+        assert_eq!(error(": main v0 := 256"), "line 1: 256 does not fit in a byte");
+        assert_eq!(error(": main sprite v0 v1 16"), "line 1: 16 does not fit in 4 bits");
+        assert_eq!(error(": main\nloop"), "line 2: loop without again");
+        assert_eq!(error("clear"), "line 1: The program has no main label");
+        assert_eq!(error(": main : main"), "line 1: Label main is defined twice");
+        assert_eq!(error(": main v0 <= v1"), "line 1: Unknown operator <=");
+    }
+
+    #[test]
+    fn rejects_addresses_past_0xfff() {
+        assert_eq!(error(": main :org 0x1000 : far clear jump far"), "line 1: far at 0x1000 is out of range");
+        assert_eq!(error(": main :org 0x1000 : far\n far"), "line 2: far at 0x1000 is out of range");
+        assert_eq!(error(": main :org 0x1000\nloop again"), "line 2: Loop at 0x1000 is out of range");
+        assert_eq!(error(": main :org 0xFFC\nif v0 == 1 begin clear\nend"), "line 3: Jump to 0x1002 is out of range");
+        assert_eq!(error("clear :org 0x1000 : main"), "line 1: Jump to 0x1000 is out of range");
+        assert_eq!(rom(": main :org 0xFFE : far i := long far")[0xDFE..], [0xF0, 0x00, 0x0F, 0xFE]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::timing::FRAME_RATE;
+
+/// Sample rate used by the built-in sinks.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// What the sound hardware should be doing for one 60 Hz frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFrame {
+    /// The sound timer is non-zero, so the buzzer is on.
+    pub active: bool,
+    /// The XO-CHIP audio pattern, once a program has loaded one with `F002`.
+    /// When `None` the sink plays its own tone.
+    pub pattern: Option<[u8; 16]>,
+    /// The XO-CHIP pitch register, which sets the pattern playback rate.
+    pub pitch: u8,
+}
+
+impl AudioFrame {
+    /// A frame with the buzzer off.
+    pub fn silent() -> Self {
+        AudioFrame { active: false, pattern: None, pitch: 64 }
+    }
+
+    /// Playback rate of the XO-CHIP pattern in samples per second.
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// Receives the state of the buzzer once per frame from [`CPU::tick_timers`](crate::CPU::tick_timers).
+pub trait AudioSink {
+    /// Called once at the end of every frame, whether or not the buzzer is on.
+    fn frame(&mut self, frame: &AudioFrame);
+
+    /// Flushes anything buffered. Sinks that write files finish them here.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink that discards all audio.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn frame(&mut self, _frame: &AudioFrame) {}
+}
+
+/// Shape of the generated tone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+/// The tone played when the buzzer is on and no XO-CHIP pattern is loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneConfig {
+    /// Frequency in Hz.
+    pub frequency: f32,
+    /// Amplitude from 0.0 to 1.0.
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for ToneConfig {
+    fn default() -> Self {
+        ToneConfig { frequency: 440.0, volume: 0.25, waveform: Waveform::Square }
+    }
+}
+
+/// Turns [`AudioFrame`]s into samples in the range -1.0 to 1.0.
+#[derive(Debug, Clone)]
+pub struct ToneGenerator {
+    config: ToneConfig,
+    sample_rate: u32,
+    // position in the current tone cycle, 0.0 to 1.0
+    phase: f32,
+    // position in the XO-CHIP pattern, in pattern bits
+    pattern_position: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(config: ToneConfig, sample_rate: u32) -> Self {
+        ToneGenerator { config, sample_rate, phase: 0.0, pattern_position: 0.0 }
+    }
+
+    pub fn config(&self) -> ToneConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: ToneConfig) {
+        self.config = config;
+    }
+
+    /// Produces the next sample for the given buzzer state.
+    pub fn next_sample(&mut self, frame: &AudioFrame) -> f32 {
+        if !frame.active {
+            self.phase = 0.0;
+            return 0.0;
+        }
+        let value = match frame.pattern {
+            Some(pattern) => {
+                let bit = self.pattern_position as usize % 128;
+                self.pattern_position = (self.pattern_position + frame.pattern_rate() / self.sample_rate as f32) % 128.0;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) > 0 {1.0} else {-1.0}
+            },
+            None => {
+                let value = match self.config.waveform {
+                    Waveform::Square => if self.phase < 0.5 {1.0} else {-1.0},
+                    Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                    Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+                    Waveform::Sine => (2.0 * PI * self.phase).sin(),
+                };
+                self.phase = (self.phase + self.config.frequency / self.sample_rate as f32) % 1.0;
+                value
+            },
+        };
+        value * self.config.volume
+    }
+
+    /// Appends one frame's worth of samples to `out`.
+    pub fn render_frame(&mut self, frame: &AudioFrame, out: &mut Vec<f32>) {
+        let samples = self.sample_rate / FRAME_RATE;
+        out.extend((0..samples).map(|_| self.next_sample(frame)));
+    }
+}
+
+/// A sink that renders the buzzer offline into a 16-bit mono WAV file.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    generator: ToneGenerator,
+    buffer: Vec<f32>,
+    data_bytes: u32,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl WavSink<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P, config: ToneConfig) -> io::Result<Self> {
+        WavSink::new(BufWriter::new(File::create(path)?), config)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    /// Writes a WAV header to `writer`; the sizes in it are filled in by [`AudioSink::finish`].
+    pub fn new(mut writer: W, config: ToneConfig) -> io::Result<Self> {
+        write_wav_header(&mut writer, 0)?;
+        Ok(WavSink {
+            writer,
+            generator: ToneGenerator::new(config, SAMPLE_RATE),
+            buffer: Vec::new(),
+            data_bytes: 0,
+            error: None,
+            finished: false,
+        })
+    }
+
+    fn write_samples(&mut self) -> io::Result<()> {
+        for sample in &self.buffer {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.data_bytes += self.buffer.len() as u32 * 2;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn frame(&mut self, frame: &AudioFrame) {
+        if self.error.is_some() || self.finished {
+            return;
+        }
+        self.buffer.clear();
+        self.generator.render_frame(frame, &mut self.buffer);
+        if let Err(err) = self.write_samples() {
+            self.error = Some(err);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.writer, self.data_bytes)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn write_wav_header<W: Write>(w: &mut W, data_bytes: u32) -> io::Result<()> {
+    let channels: u16 = 1;
+    let bits: u16 = 16;
+    let block_align = channels * bits / 8;
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_bytes).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?; // PCM
+    w.write_all(&channels.to_le_bytes())?;
+    w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    w.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&bits.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_bytes.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::CPU;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn wav_sink_records_the_buzzer() {
+        // 200: V0 = 3, 202: sound timer = V0, 204: jump 204
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(vec![0x60, 0x03, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let path = std::env::temp_dir().join(format!("rust_chip8-{}.wav", std::process::id()));
+        // a period of exactly 100 samples
+        let tone = ToneConfig { frequency: 441.0, volume: 0.5, waveform: Waveform::Triangle };
+        cpu.set_audio_sink(Box::new(WavSink::create(&path, tone).unwrap()));
+        for _ in 0..6 {
+            cpu.run_frame(10).unwrap();
+        }
+        cpu.audio_sink_mut().finish().unwrap();
+        let wav = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let frame_samples = (SAMPLE_RATE / FRAME_RATE) as usize;
+        let data_bytes = 6 * frame_samples as u32 * 2;
+        let u16_at = |i: usize| u16::from_le_bytes([wav[i], wav[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([wav[i], wav[i + 1], wav[i + 2], wav[i + 3]]);
+        assert_eq!((&wav[..4], u32_at(4), &wav[8..16]), (&b"RIFF"[..], 36 + data_bytes, &b"WAVEfmt "[..]));
+        assert_eq!((u16_at(20), u16_at(22), u32_at(24), u16_at(34)), (1, 1, SAMPLE_RATE, 16));
+        assert_eq!((&wav[36..40], u32_at(40)), (&b"data"[..], data_bytes));
+        assert_eq!(wav.len(), 44 + data_bytes as usize);
+
+        // the timer is set in the first frame and sounds for three
+        let samples: Vec<i16> = wav[44..].chunks(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect();
+        for (i, sample) in samples.iter().enumerate().take(3 * frame_samples) {
+            let phase = (i % 100) as f32 / 100.0;
+            let expected = (1.0 - 4.0 * (phase - 0.5).abs()) * 0.5 * i16::MAX as f32;
+            assert!((*sample as f32 - expected).abs() < 4.0, "sample {} is {}, expected {}", i, sample, expected);
+        }
+        assert!(samples[3 * frame_samples..].iter().all(|s| *s == 0));
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use rust_chip8::{AudioFrame, AudioSink, ToneConfig, ToneGenerator};
+
+/// Plays the buzzer on the default output device.
+///
+/// The CPU hands over the buzzer state once a frame; the audio callback keeps
+/// generating samples from the latest state between frames.
+pub struct Beeper {
+    state: Arc<Mutex<AudioFrame>>,
+    _stream: cpal::Stream,
+}
+
+impl Beeper {
+    pub fn open(tone: ToneConfig) -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?;
+        let state = Arc::new(Mutex::new(AudioFrame::silent()));
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), tone, state.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), tone, state.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), tone, state.clone()),
+            format => Err(format!("unsupported sample format {}", format)),
+        }?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok(Beeper { state, _stream: stream })
+    }
+}
+
+impl AudioSink for Beeper {
+    fn frame(&mut self, frame: &AudioFrame) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = *frame;
+        }
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    tone: ToneConfig,
+    state: Arc<Mutex<AudioFrame>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut generator = ToneGenerator::new(tone, config.sample_rate.0);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let frame = state.lock().map(|s| *s).unwrap_or_else(|_| AudioFrame::silent());
+            for out in data.chunks_mut(channels) {
+                let sample = T::from_sample(generator.next_sample(&frame));
+                out.iter_mut().for_each(|o| *o = sample);
+            }
+        },
+        |err| println!("Audio error: {}", err),
+        None,
+    ).map_err(|e| e.to_string())
+}
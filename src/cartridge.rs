@@ -0,0 +1,209 @@
+//! Octo cartridges: GIF images with a program's source and its run options
+//! hidden in the pixels.
+//!
+//! Each pixel's palette index carries two bits in its low bits, most
+//! significant pair first, so four pixels make a byte. The bytes of every
+//! frame, in order, are a 32-bit big-endian length and then that many bytes
+//! of UTF-8 JSON:
+//!
+//! ```text
+//! {"program": ": main ...", "options": {"tickrate": 20, "fillColor": "#FFCC00", ...}}
+//! ```
+//!
+//! The program is Octo source, which is assembled when the cartridge is read.
+
+use crate::assembler::{assemble, AssembleError};
+use crate::palette::{Color, Palette};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use serde_json::Value;
+use std::fmt;
+
+const MAGIC: &[u8] = b"GIF8";
+// Octo's largest program for each platform; anything bigger is XO-CHIP.
+const CHIP8_MAX_SIZE: u64 = 3232;
+const SCHIP_MAX_SIZE: u64 = 3584;
+// Octo's tick rate when a cartridge does not give one.
+const DEFAULT_TICKRATE: usize = 20;
+
+/// A program read from a cartridge, with the settings it asks to be run with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    /// The Octo source in the cartridge.
+    pub source: String,
+    /// The assembled program, to load at 0x200.
+    pub rom: Vec<u8>,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// Octo's tick rate: instructions run per 60 Hz frame.
+    pub cycles_per_frame: usize,
+    pub palette: Palette,
+}
+
+/// Why a cartridge could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The file is not a GIF that can be decoded.
+    Gif(String),
+    /// The pixels end before the length they start with.
+    Truncated,
+    /// The hidden data is not the JSON Octo writes.
+    Json(String),
+    /// The program does not assemble.
+    Assemble(AssembleError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Gif(err) => write!(f, "not a readable GIF: {}", err),
+            CartridgeError::Truncated => write!(f, "cartridge data is cut short"),
+            CartridgeError::Json(err) => write!(f, "cartridge data is not valid: {}", err),
+            CartridgeError::Assemble(err) => write!(f, "cartridge program does not assemble: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl Cartridge {
+    /// Whether `bytes` look like a GIF, and so might be a cartridge rather than a ROM.
+    pub fn is_cartridge(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Reads a cartridge and assembles its program.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let data = hidden_bytes(bytes)?;
+        let (len, rest) = data.split_first_chunk::<4>().ok_or(CartridgeError::Truncated)?;
+        let json = rest.get(..u32::from_be_bytes(*len) as usize).ok_or(CartridgeError::Truncated)?;
+        let json: Value = serde_json::from_slice(json).map_err(|err| CartridgeError::Json(err.to_string()))?;
+        let source = json["program"]
+            .as_str()
+            .ok_or_else(|| CartridgeError::Json("no program".to_string()))?
+            .to_string();
+        let rom = assemble(&source).map_err(CartridgeError::Assemble)?.rom;
+        let options = &json["options"];
+
+        let platform = match options["maxSize"].as_u64() {
+            Some(size) if size <= CHIP8_MAX_SIZE => Platform::Chip8,
+            Some(size) if size <= SCHIP_MAX_SIZE => Platform::SuperChip,
+            _ => Platform::XoChip,
+        };
+        // Octo's quirk flags are set when an interpreter departs from the VIP
+        let mut quirks = platform.default_quirks();
+        let flag = |name: &str| options[name].as_bool();
+        if let Some(on) = flag("shiftQuirks") {
+            quirks.shift_uses_vy = !on;
+        }
+        if let Some(on) = flag("loadStoreQuirks") {
+            quirks.memory_increments_i = !on;
+        }
+        if let Some(on) = flag("jumpQuirks") {
+            quirks.jump_uses_vx = on;
+        }
+        if let Some(on) = flag("logicQuirks") {
+            quirks.logic_resets_vf = on;
+        }
+        if let Some(on) = flag("clipQuirks") {
+            quirks.wrap_sprites = !on;
+        }
+        if let Some(on) = flag("vBlankQuirks") {
+            quirks.display_wait = on;
+        }
+
+        let mut palette = Palette::default();
+        let color = |name: &str| options[name].as_str().and_then(|c| c.parse::<Color>().ok());
+        for (name, slot) in [
+            ("backgroundColor", &mut palette.background),
+            ("fillColor", &mut palette.foreground),
+            ("fillColor2", &mut palette.plane2),
+            ("blendColor", &mut palette.blend),
+        ] {
+            if let Some(color) = color(name) {
+                *slot = color;
+            }
+        }
+
+        let cycles_per_frame = options["tickrate"].as_u64().map_or(DEFAULT_TICKRATE, |t| t as usize);
+        Ok(Cartridge { source, rom, platform, quirks, cycles_per_frame, palette })
+    }
+}
+
+// The two low bits of every pixel, packed into bytes.
+fn hidden_bytes(gif: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let error = |err: gif::DecodingError| CartridgeError::Gif(err.to_string());
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(error)?;
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(error)? {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+    Ok(pixels
+        .chunks_exact(4)
+        .map(|p| p.iter().fold(0, |byte, pixel| byte << 2 | pixel & 0x3))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hides `json` in a GIF the way Octo does, over a blank label.
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = data.iter().flat_map(|b| [b >> 6, b >> 4 & 3, b >> 2 & 3, b & 3]).collect();
+        pixels.resize(pixels.len().div_ceil(128 * 64) * 128 * 64, 0);
+
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 16, i * 16, i * 16]).collect();
+        let mut gif = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut gif, 128, 64, &palette).unwrap();
+        for frame in pixels.chunks(128 * 64) {
+            encoder.write_frame(&gif::Frame::from_indexed_pixels(128, 64, frame.to_vec(), None)).unwrap();
+        }
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn reads_program_and_options() {
+        // long enough to need more than one frame
+        let comment = "#".repeat(3000);
+        let json = format!(
+            r##"{{"program": "{}\n: main\n  v0 := 1\n  loop again", "options": {{"tickrate": 200, "fillColor": "#FFCC00", "backgroundColor": "#996600", "maxSize": 3584, "shiftQuirks": true, "clipQuirks": false, "vBlankQuirks": false}}}}"##,
+            comment
+        );
+        let bytes = cartridge(&json);
+        assert!(Cartridge::is_cartridge(&bytes));
+
+        let cart = Cartridge::parse(&bytes).unwrap();
+        assert_eq!(cart.rom, vec![0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(cart.platform, Platform::SuperChip);
+        assert_eq!(cart.cycles_per_frame, 200);
+        assert_eq!(cart.palette.foreground, Color(0xFF, 0xCC, 0x00));
+        assert_eq!(cart.palette.background, Color(0x99, 0x66, 0x00));
+        assert!(!cart.quirks.shift_uses_vy && cart.quirks.wrap_sprites && !cart.quirks.display_wait);
+    }
+
+    #[test]
+    fn loads_into_the_cpu() {
+        let bytes = cartridge(r#"{"program": ": main\n  v0 := 1", "options": {"jumpQuirks": true, "tickrate": 7}}"#);
+        let mut cpu = crate::CPU::with_platform(Platform::XoChip, Quirks::vip());
+        cpu.load(bytes).unwrap();
+        assert_eq!(&cpu.memory()[0x200..0x202], &[0x60, 0x01]);
+        assert_eq!(cpu.rom_hash(), crate::RomHash::of(&[0x60, 0x01]));
+        assert!(cpu.quirks().jump_uses_vx);
+        assert_eq!(cpu.cartridge().map(|c| c.cycles_per_frame), Some(7));
+    }
+
+    #[test]
+    fn rejects_broken_cartridges() {
+        assert!(matches!(Cartridge::parse(b"GIF89a"), Err(CartridgeError::Gif(_))));
+        let mut bytes = cartridge("{}");
+        assert!(matches!(Cartridge::parse(&bytes), Err(CartridgeError::Json(_))));
+        bytes = cartridge(r#"{"program": "v0 := nowhere"}"#);
+        assert!(matches!(Cartridge::parse(&bytes), Err(CartridgeError::Assemble(_))));
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+
+use std::str::FromStr;
+
+use clap::{Parser, ValueEnum};
+use rust_chip8::{palette::Color, Engine, InputEvent, Palette, Platform, Quirks, ToneConfig, Waveform};
+
+/// A CHIP-8, SUPER-CHIP and XO-CHIP emulator.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// ROM or Octo cartridge GIF to run, or Octo source with --assemble.
+    pub rom: PathBuf,
+
+    /// Instructions executed per 60 Hz frame. Defaults to the ROM
+    /// database's speed for the ROM, or 11.
+    #[arg(short = 'i', long)]
+    pub cycles_per_frame: Option<usize>,
+
+    /// Window pixels per CHIP-8 pixel in low resolution.
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(2..=40))]
+    pub scale: u32,
+
+    /// Color of lit pixels, as rrggbb. Defaults to the ROM's colors, or white.
+    #[arg(long)]
+    pub foreground: Option<Color>,
+
+    /// Color of unlit pixels, as rrggbb. Defaults to the ROM's colors, or black.
+    #[arg(long)]
+    pub background: Option<Color>,
+
+    /// Machine to emulate. Defaults to the one the ROM database gives for
+    /// the ROM, or chip8.
+    #[arg(short, long, value_enum)]
+    pub platform: Option<PlatformArg>,
+
+    /// Quirks profile. Defaults to the ROM database's quirks for the ROM, or
+    /// the profile that matches the platform.
+    #[arg(short, long, value_enum)]
+    pub quirks: Option<QuirksArg>,
+
+    /// ROM database to use over the bundled one, in the community CHIP-8
+    /// database's programs.json format. Defaults to
+    /// ~/.config/rust_chip8/programs.json if it exists.
+    #[arg(long, value_name = "FILE")]
+    pub database: Option<PathBuf>,
+
+    /// Frequency of the buzzer in Hz.
+    #[arg(long, value_name = "HZ", default_value_t = 440.0, value_parser = parse_frequency)]
+    pub tone_hz: f32,
+
+    /// Loudness of the buzzer, from 0 to 1.
+    #[arg(long, default_value_t = 0.25, value_parser = parse_volume)]
+    pub volume: f32,
+
+    /// Shape of the buzzer's tone. XO-CHIP audio patterns play as they are.
+    #[arg(long, value_enum, default_value_t = WaveformArg::Square)]
+    pub waveform: WaveformArg,
+
+    /// How instructions are executed. The block engine is faster for long
+    /// runs and behaves the same.
+    #[arg(long, value_enum, default_value_t = EngineArg::Interpreter)]
+    pub engine: EngineArg,
+
+    /// Start with emulation paused; press Space to run.
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Seconds of play kept for rewinding with Backspace.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub rewind_seconds: u32,
+
+    /// Memory the rewind history may use, in MiB. The oldest frames are
+    /// dropped to stay within it.
+    #[arg(long, value_name = "MIB", default_value_t = 16)]
+    pub rewind_budget: usize,
+
+    /// Start the command-line debugger on the terminal, stopped before the
+    /// first instruction. Type help at its prompt for the commands.
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Listen for GDB on this port on localhost, stopped before the first
+    /// instruction until GDB attaches and continues.
+    #[arg(long, value_name = "PORT", conflicts_with = "debug")]
+    pub gdb: Option<u16>,
+
+    /// Wait for an editor to connect with the Debug Adapter Protocol on this
+    /// port on localhost, then run under its control.
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["debug", "gdb"])]
+    pub dap: Option<u16>,
+
+    /// Print the ROM as Octo source instead of running it.
+    #[arg(long)]
+    pub disassemble: bool,
+
+    /// Assemble the ROM argument, an Octo source file, into FILE instead of
+    /// running it. Labels go in a .sym file and the source map in a .map
+    /// file next to it.
+    #[arg(long, value_name = "FILE", conflicts_with = "disassemble")]
+    pub assemble: Option<PathBuf>,
+
+    /// Record keypad input to a movie file, saved on exit.
+    #[arg(long, value_name = "FILE", conflicts_with = "play")]
+    pub record: Option<PathBuf>,
+
+    /// Play back a movie recorded with --record.
+    #[arg(long, value_name = "FILE")]
+    pub play: Option<PathBuf>,
+
+    /// Run without a window and write screenshots as PNG files.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Headless: number of frames to run.
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    pub frames: u64,
+
+    /// Headless: keys to hold, as FRAME:KEYS pairs separated by commas.
+    /// KEYS are hex digits held from that frame on, or - to release all.
+    /// For example 60:5,90:-,120:4a
+    #[arg(long, value_name = "SCRIPT", requires = "headless")]
+    pub keys: Option<KeyScript>,
+
+    /// Headless: frames to take a screenshot after, separated by commas.
+    #[arg(long, value_name = "FRAMES", value_delimiter = ',', requires = "headless")]
+    pub screenshot_at: Vec<u64>,
+
+    /// Headless: where to write the screenshot taken on exit.
+    /// Defaults to the ROM name with a .png extension in the output directory.
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    pub screenshot: Option<PathBuf>,
+
+    /// Headless: directory for screenshots.
+    #[arg(long, value_name = "DIR", default_value = ".", requires = "headless")]
+    pub output_dir: PathBuf,
+
+    /// Headless: record the buzzer to a WAV file.
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    pub wav: Option<PathBuf>,
+}
+
+/// Scripted keypad input for headless runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScript(pub Vec<InputEvent>);
+
+impl FromStr for KeyScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (frame, keys) = entry.split_once(':')
+                .ok_or_else(|| format!("'{}' should be FRAME:KEYS", entry))?;
+            let frame = frame.parse().map_err(|_| format!("'{}' is not a frame number", frame))?;
+            let mut bits = 0;
+            if keys != "-" {
+                for key in keys.chars() {
+                    let hex = key.to_digit(16).ok_or_else(|| format!("'{}' is not a key (0-f)", key))?;
+                    bits |= 1 << hex;
+                }
+            }
+            events.push(InputEvent { frame, keys: bits });
+        }
+        events.sort_by_key(|e| e.frame);
+        Ok(KeyScript(events))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PlatformArg {
+    Chip8,
+    Schip,
+    Xochip,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum EngineArg {
+    /// Decode and execute one instruction at a time.
+    Interpreter,
+    /// Translate runs of instructions up to each branch into cached closures.
+    Blocks,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum WaveformArg {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum QuirksArg {
+    /// COSMAC VIP.
+    Vip,
+    /// SUPER-CHIP 1.1.
+    Schip,
+    /// XO-CHIP as in Octo.
+    Xochip,
+    /// This emulator's behaviour before quirks were configurable.
+    Legacy,
+}
+
+impl Args {
+    /// The platform given on the command line.
+    pub fn platform(&self) -> Option<Platform> {
+        self.platform.map(|platform| match platform {
+            PlatformArg::Chip8 => Platform::Chip8,
+            PlatformArg::Schip => Platform::SuperChip,
+            PlatformArg::Xochip => Platform::XoChip,
+        })
+    }
+
+    /// The quirks profile given on the command line.
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.quirks.map(|quirks| match quirks {
+            QuirksArg::Vip => Quirks::vip(),
+            QuirksArg::Schip => Quirks::schip(),
+            QuirksArg::Xochip => Quirks::xochip(),
+            QuirksArg::Legacy => Quirks::default(),
+        })
+    }
+
+    pub fn engine(&self) -> Engine {
+        match self.engine {
+            EngineArg::Interpreter => Engine::Interpreter,
+            EngineArg::Blocks => Engine::Blocks,
+        }
+    }
+
+    /// The buzzer's tone.
+    pub fn tone(&self) -> ToneConfig {
+        let waveform = match self.waveform {
+            WaveformArg::Square => Waveform::Square,
+            WaveformArg::Triangle => Waveform::Triangle,
+            WaveformArg::Sawtooth => Waveform::Sawtooth,
+            WaveformArg::Sine => Waveform::Sine,
+        };
+        ToneConfig { frequency: self.tone_hz, volume: self.volume, waveform }
+    }
+
+    /// `palette` with any colors given on the command line.
+    pub fn palette(&self, palette: Palette) -> Palette {
+        Palette {
+            foreground: self.foreground.unwrap_or(palette.foreground),
+            background: self.background.unwrap_or(palette.background),
+            ..palette
+        }
+    }
+}
+
+fn parse_volume(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(volume) if (0.0..=1.0).contains(&volume) => Ok(volume),
+        _ => Err(format!("'{}' is not a volume from 0 to 1", s)),
+    }
+}
+
+fn parse_frequency(s: &str) -> Result<f32, String> {
+    match s.parse() {
+        Ok(hz) if hz > 0.0 && hz < 20000.0 => Ok(hz),
+        _ => Err(format!("'{}' is not a frequency in Hz below 20000", s)),
+    }
+}
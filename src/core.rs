@@ -1,53 +1,166 @@
-use crate::instruction::{Instruction, Instruction::*, self};
-use rand::Rng;
+use crate::audio::{AudioFrame, AudioSink, NullSink};
+use crate::cartridge::Cartridge;
+use crate::error::{ExecError, LoadError, StepOutcome};
+use crate::instruction::{Instruction, Instruction::*};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rom::RomHash;
+use crate::savestate::{Snapshot, StateError};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::rc::Rc;
 
-const HIGH_MASK: u8 = 0xF0;
-const LOW_MASK: u8 = 0x0F;
+mod blocks;
+
+pub use self::blocks::Engine;
+use self::blocks::Block;
+
+/// Height of the display in pixels.
 pub const DISPLAY_HEIGHT: usize = 32;
+/// Width of the display in pixels.
 pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_BUFFER: usize = DISPLAY_HEIGHT * DISPLAY_WIDTH;
+/// Height of the SUPER-CHIP high resolution display in pixels.
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+/// Width of the SUPER-CHIP high resolution display in pixels.
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+/// Size of the display buffer, one byte per pixel, large enough for high resolution mode.
+pub const DISPLAY_BUFFER: usize = HIRES_DISPLAY_HEIGHT * HIRES_DISPLAY_WIDTH;
+pub(crate) const STACK_SIZE: usize = 48;
+const FONT_ADDRESS: usize = 0x50;
+const BIG_FONT_ADDRESS: usize = 0xA0;
+
+/// How an instruction touched memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// Instruction bytes read by fetch.
+    Fetch,
+    /// Data read by `Load`, a sprite draw or an audio pattern load.
+    Read,
+    /// Data written by `Store` or `StoreDecimalR`.
+    Write,
+}
 
+/// A run of bytes touched by one instruction, see [`CPU::accesses`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: usize,
+    pub len: usize,
+}
+
+/// The CHIP-8 interpreter state: memory, registers, timers, display and keypad.
+///
+/// The CPU knows nothing about windows or wall-clock time. A frontend feeds it
+/// key state, calls [`CPU::run_frame`] sixty times a second and draws
+/// [`CPU::framebuffer`].
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
     pc: usize,
-    stack: [usize; 48],
+    // address of the instruction being executed, for error reporting
+    instruction_pc: usize,
+    stack: [usize; STACK_SIZE],
     sp: usize,
     index_register: u16,
     pub delay_timer: u8,
     pub sound_timer: u8,
     general_registers: [u8; 16],
     pub display: [u8; DISPLAY_BUFFER],
-    pub keys: [u8; 16]
+    pub keys: [u8; 16],
+    quirks: Quirks,
+    platform: Platform,
+    hires: bool,
+    // RPL user flags saved and restored by FX75/FX85
+    flags: [u8; 16],
+    halted: bool,
+    frame: u64,
+    // XO-CHIP bitplanes selected for drawing, clearing and scrolling
+    plane_mask: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    audio: Box<dyn AudioSink>,
+    rom_hash: RomHash,
+    cartridge: Option<Cartridge>,
+    rng: ChaCha8Rng,
+    rng_seed: u64,
+    // set after a draw when the display wait quirk is on, cleared by the next timer tick
+    waiting_for_vblank: bool,
+    // memory touched by the last instruction, recorded only while tracing
+    trace_accesses: bool,
+    accesses: Vec<MemoryAccess>,
+    // instructions already decoded, by address, cleared where memory is written
+    decoded: Vec<Option<Instruction>>,
+    cache_instructions: bool,
+    engine: Engine,
+    // translated blocks by start address, allocated when the block engine first runs
+    blocks: Vec<Option<Rc<Block>>>,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
 }
 
 impl CPU {
-    pub fn new() -> Self {
+    /// Creates a CHIP-8 machine with cleared memory and the built-in font loaded,
+    /// interpreting ambiguous instructions according to `quirks`.
+    pub fn new(quirks: Quirks) -> Self {
+        Self::with_platform(Platform::Chip8, quirks)
+    }
+
+    /// Creates a machine for `platform`, interpreting ambiguous instructions
+    /// according to `quirks`.
+    pub fn with_platform(platform: Platform, quirks: Quirks) -> Self {
         // Load interpreter
 
         // Load font
         let mut c = CPU {
-            memory: [0; 4096],
+            memory: vec![0; platform.memory_size()],
             pc: 0, 
-            stack: [0; 48],
+            instruction_pc: 0,
+            stack: [0; STACK_SIZE],
             sp: 0,
             index_register: 0,
             delay_timer: 0,
             sound_timer: 0,
             general_registers: [0; 16],
             display: [0; DISPLAY_BUFFER],
-            keys: [0; 16]
+            keys: [0; 16],
+            quirks,
+            platform,
+            hires: false,
+            flags: [0; 16],
+            halted: false,
+            frame: 0,
+            plane_mask: 1,
+            audio_pattern: None,
+            pitch: 64,
+            audio: Box::new(NullSink),
+            rom_hash: RomHash::default(),
+            cartridge: None,
+            rng: ChaCha8Rng::seed_from_u64(0),
+            rng_seed: 0,
+            waiting_for_vblank: false,
+            trace_accesses: false,
+            accesses: Vec::new(),
+            decoded: vec![None; platform.memory_size()],
+            cache_instructions: true,
+            engine: Engine::default(),
+            blocks: Vec::new(),
         };
 
         // set font
         c.set_font();
+        c.seed_rng(rand::random());
         c
     }
 
     fn set_font(&mut self) {
-        self.memory[080..085].copy_from_slice(&[0xF0, 0x90, 0x90, 0x90, 0xF0]); // 0
-        self.memory[085..090].copy_from_slice(&[0x20, 0x60, 0x20, 0x20, 0x70]); // 1
-        self.memory[090..095].copy_from_slice(&[0xF0, 0x10, 0xF0, 0x80, 0xF0]); // 2
-        self.memory[095..100].copy_from_slice(&[0xF0, 0x10, 0xF0, 0x10, 0xF0]); // 3
+        self.memory[80..85].copy_from_slice(&[0xF0, 0x90, 0x90, 0x90, 0xF0]); // 0
+        self.memory[85..90].copy_from_slice(&[0x20, 0x60, 0x20, 0x20, 0x70]); // 1
+        self.memory[90..95].copy_from_slice(&[0xF0, 0x10, 0xF0, 0x80, 0xF0]); // 2
+        self.memory[95..100].copy_from_slice(&[0xF0, 0x10, 0xF0, 0x10, 0xF0]); // 3
         self.memory[100..105].copy_from_slice(&[0x90, 0x90, 0xF0, 0x10, 0x10]); // 4
         self.memory[105..110].copy_from_slice(&[0xF0, 0x80, 0xF0, 0x10, 0xF0]); // 5
         self.memory[110..115].copy_from_slice(&[0xF0, 0x80, 0xF0, 0x90, 0xF0]); // 6
@@ -60,195 +173,500 @@ impl CPU {
         self.memory[145..150].copy_from_slice(&[0xE0, 0x90, 0x90, 0x90, 0xE0]); // D
         self.memory[150..155].copy_from_slice(&[0xF0, 0x80, 0xF0, 0x80, 0xF0]); // E
         self.memory[155..160].copy_from_slice(&[0xF0, 0x80, 0xF0, 0x80, 0x80]); // F
+
+        // SUPER-CHIP 8x10 font
+        self.memory[160..170].copy_from_slice(&[0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF]); // 0
+        self.memory[170..180].copy_from_slice(&[0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF]); // 1
+        self.memory[180..190].copy_from_slice(&[0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF]); // 2
+        self.memory[190..200].copy_from_slice(&[0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF]); // 3
+        self.memory[200..210].copy_from_slice(&[0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03]); // 4
+        self.memory[210..220].copy_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF]); // 5
+        self.memory[220..230].copy_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF]); // 6
+        self.memory[230..240].copy_from_slice(&[0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18]); // 7
+        self.memory[240..250].copy_from_slice(&[0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF]); // 8
+        self.memory[250..260].copy_from_slice(&[0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF]); // 9
+        self.memory[260..270].copy_from_slice(&[0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3]); // A
+        self.memory[270..280].copy_from_slice(&[0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC]); // B
+        self.memory[280..290].copy_from_slice(&[0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C]); // C
+        self.memory[290..300].copy_from_slice(&[0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC]); // D
+        self.memory[300..310].copy_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF]); // E
+        self.memory[310..320].copy_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0]); // F
     }
 
     fn address_for_font(&self, char: u8) -> usize {
-        0x50 + char as usize * 5
+        FONT_ADDRESS + char as usize * 5
+    }
+
+    fn address_for_big_font(&self, char: u8) -> usize {
+        BIG_FONT_ADDRESS + char as usize * 10
     }
 
-    pub fn load(&mut self, prog: Vec<u8>) {
-        self.memory[0x200..0x200+prog.len()].copy_from_slice(&prog.as_slice());
-        println!("Loaded {} bytes into memory", prog.len());
+    /// Copies a program into memory at 0x200 and points the program counter at it.
+    ///
+    /// An Octo cartridge GIF is read and its program loaded instead, and its
+    /// quirks are applied. Its speed and colors are left to the frontend,
+    /// through [`CPU::cartridge`].
+    pub fn load(&mut self, prog: Vec<u8>) -> Result<(), LoadError> {
+        if Cartridge::is_cartridge(&prog) {
+            let cartridge = Cartridge::parse(&prog).map_err(LoadError::Cartridge)?;
+            self.load(cartridge.rom.clone())?;
+            self.quirks = cartridge.quirks;
+            self.cartridge = Some(cartridge);
+            return Ok(());
+        }
+        let max = self.memory.len() - 0x200;
+        if prog.len() > max {
+            return Err(LoadError::TooLarge { size: prog.len(), max });
+        }
+        self.memory[0x200..0x200+prog.len()].copy_from_slice(prog.as_slice());
+        self.forget_instructions(0, self.memory.len());
         self.pc = 0x200;
-        println!("PC set to 0x200");
+        self.rom_hash = RomHash::of(&prog);
+        Ok(())
+    }
+
+    /// Powers the machine back on with a new program, keeping its platform,
+    /// quirks, engine, instruction cache setting, access tracing and audio
+    /// sink. The machine is left as it was if the program cannot be loaded.
+    pub fn reload(&mut self, prog: Vec<u8>) -> Result<(), LoadError> {
+        let mut fresh = CPU::with_platform(self.platform, self.quirks);
+        fresh.engine = self.engine;
+        fresh.cache_instructions = self.cache_instructions;
+        fresh.trace_accesses = self.trace_accesses;
+        fresh.load(prog)?;
+        fresh.audio = std::mem::replace(&mut self.audio, Box::new(NullSink));
+        *self = fresh;
+        Ok(())
+    }
+
+    /// The cartridge the program was loaded from, if it came from one.
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    /// SHA-1 of the loaded program.
+    pub fn rom_hash(&self) -> RomHash {
+        self.rom_hash
+    }
+
+    /// Restarts the random number generator used by `CXNN` from `seed`.
+    /// Machines are seeded randomly; set a seed to make a run reproducible.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.rng_seed = seed;
+    }
+
+    /// The seed the random number generator was last started from.
+    pub fn rng_seed(&self) -> u64 {
+        self.rng_seed
     }
 
-    pub fn step(&mut self) {
-        let raw = self.fetch();
-        let instruction = self.decode(raw);
-        self.execute(instruction);
+    /// Copies the whole machine state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            platform: self.platform,
+            rom_hash: self.rom_hash,
+            memory: self.memory.clone(),
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            index_register: self.index_register,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            general_registers: self.general_registers,
+            display: self.display,
+            keys: self.keys,
+            hires: self.hires,
+            flags: self.flags,
+            halted: self.halted,
+            frame: self.frame,
+            plane_mask: self.plane_mask,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            waiting_for_vblank: self.waiting_for_vblank,
+            rng_seed: self.rng_seed,
+            rng_position: self.rng.get_word_pos(),
+        }
+    }
+
+    /// Puts the machine back into the state captured by `snapshot`.
+    ///
+    /// Fails, leaving the machine untouched, if the snapshot was taken with a
+    /// different ROM or platform.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), StateError> {
+        if snapshot.rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found: snapshot.rom_hash });
+        }
+        if snapshot.platform != self.platform {
+            return Err(StateError::PlatformMismatch { expected: self.platform, found: snapshot.platform });
+        }
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.forget_instructions(0, self.memory.len());
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack;
+        self.sp = snapshot.sp;
+        self.index_register = snapshot.index_register;
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.general_registers = snapshot.general_registers;
+        self.display = snapshot.display;
+        self.keys = snapshot.keys;
+        self.hires = snapshot.hires;
+        self.flags = snapshot.flags;
+        self.halted = snapshot.halted;
+        self.frame = snapshot.frame;
+        self.plane_mask = snapshot.plane_mask;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.waiting_for_vblank = snapshot.waiting_for_vblank;
+        self.seed_rng(snapshot.rng_seed);
+        self.rng.set_word_pos(snapshot.rng_position);
+        Ok(())
+    }
+
+    /// Serializes the machine into a versioned save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    /// Restores a save state written by [`CPU::save_state`].
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let snapshot = Snapshot::from_bytes(bytes)?;
+        self.restore(&snapshot)
+    }
+
+    /// The quirks profile in use.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Switches the quirks profile. Takes effect from the next instruction.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// The platform being emulated.
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Whether the SUPER-CHIP 128x64 high resolution mode is active.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Width of the display in the current resolution.
+    pub fn display_width(&self) -> usize {
+        if self.hires {HIRES_DISPLAY_WIDTH} else {DISPLAY_WIDTH}
+    }
+
+    /// Height of the display in the current resolution.
+    pub fn display_height(&self) -> usize {
+        if self.hires {HIRES_DISPLAY_HEIGHT} else {DISPLAY_HEIGHT}
+    }
+
+    /// The XO-CHIP audio pattern buffer: 128 one-bit samples, most significant
+    /// bit first. `None` until the program loads one.
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    /// The buzzer state for the current frame.
+    pub fn audio_frame(&self) -> AudioFrame {
+        AudioFrame {
+            active: self.sound_timer > 0,
+            pattern: self.audio_pattern,
+            pitch: self.pitch,
+        }
     }
 
-    fn fetch(&mut self) -> [u8; 2] {
+    /// Sends the buzzer state to `sink` at the end of every frame, replacing
+    /// the previous sink. Machines start with a [`NullSink`].
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) -> Box<dyn AudioSink> {
+        std::mem::replace(&mut self.audio, sink)
+    }
+
+    /// The sink receiving the buzzer state.
+    pub fn audio_sink_mut(&mut self) -> &mut dyn AudioSink {
+        self.audio.as_mut()
+    }
+
+    /// The XO-CHIP pitch register. The pattern plays back at
+    /// `4000 * 2^((pitch - 64) / 48)` samples per second.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The value of register `VX`, for `x` in 0x0-0xF.
+    pub fn register(&self, x: usize) -> u8 {
+        self.general_registers[x & 0xF]
+    }
+
+    /// The index register I.
+    pub fn index(&self) -> u16 {
+        self.index_register
+    }
+
+    /// The return addresses on the stack, oldest first.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    /// All of memory, including the interpreter area and font.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Sets register `VX`, for debuggers.
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.general_registers[x & 0xF] = value;
+    }
+
+    /// Sets the index register I, for debuggers.
+    pub fn set_index(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    /// Moves the program counter, for debuggers.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Overwrites memory from `address`, for debuggers. Returns `false`, and
+    /// writes nothing, if the bytes do not fit in memory.
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) -> bool {
+        match self.memory.get_mut(address..address + bytes.len()) {
+            Some(memory) => {
+                memory.copy_from_slice(bytes);
+                self.forget_instructions(address, bytes.len());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Whether the program has executed the SUPER-CHIP `exit` instruction.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Turns the decoded instruction cache on or off. It is on by default;
+    /// turning it off decodes every instruction as it is fetched, which is
+    /// slower but makes no other difference.
+    pub fn set_instruction_cache(&mut self, on: bool) {
+        self.cache_instructions = on;
+        self.forget_instructions(0, self.memory.len());
+    }
+
+    /// Starts or stops recording the memory each instruction touches, for
+    /// watchpoints. Off by default, when it costs one check per access.
+    pub fn set_access_tracing(&mut self, on: bool) {
+        self.trace_accesses = on;
+        self.accesses.clear();
+    }
+
+    /// The memory accesses made by the last instruction run by [`CPU::step`],
+    /// while tracing is on.
+    pub fn accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    fn trace(&mut self, kind: AccessKind, address: usize, len: usize) {
+        if self.trace_accesses {
+            self.accesses.push(MemoryAccess { kind, address, len });
+        }
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    ///
+    /// On error the program counter is left on the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, ExecError> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForVBlank);
+        }
+        self.instruction_pc = self.pc;
+        if self.trace_accesses {
+            self.accesses.clear();
+        }
+        let result = self.fetch().and_then(|instruction| self.execute(instruction));
+        if result.is_err() {
+            self.pc = self.instruction_pc;
+        }
+        result
+    }
+
+    /// Executes up to `cycles` instructions and then ticks both timers once.
+    ///
+    /// Calling this sixty times a second runs the machine at
+    /// `cycles * 60` instructions per second. The frame ends early when the
+    /// program waits for the display or halts. If an instruction faults the
+    /// frame stops there and the timers are not ticked.
+    pub fn run_frame(&mut self, cycles: usize) -> Result<(), ExecError> {
+        // watchpoints need every access, which only the interpreter records
+        if self.engine == Engine::Blocks && !self.trace_accesses {
+            self.run_blocks(cycles)?;
+        } else {
+            for _ in 0..cycles {
+                match self.step()? {
+                    StepOutcome::WaitingForVBlank | StepOutcome::Halted => break,
+                    _ => {}
+                }
+            }
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Number of frames completed since the machine was created.
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    /// Ends the current frame: decrements the delay and sound timers, as the
+    /// 60 Hz timer interrupt would, and releases a pending display wait.
+    pub fn tick_timers(&mut self) {
+        self.frame += 1;
+        self.waiting_for_vblank = false;
+        let audio_frame = self.audio_frame();
+        self.audio.frame(&audio_frame);
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// The display as one byte per pixel, row-major, [`CPU::display_width`]
+    /// pixels wide. A non-zero byte is a lit pixel; on XO-CHIP bit 0 is the
+    /// first bitplane and bit 1 the second, giving four colors.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.display[..self.display_width() * self.display_height()]
+    }
+
+    /// Marks a keypad key (0x0-0xF) as pressed or released.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keys[(key & 0xF) as usize] = if pressed {1} else {0};
+    }
+
+    /// Returns whether a keypad key (0x0-0xF) is currently held.
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize] > 0
+    }
+
+    /// Returns whether the buzzer should currently be sounding.
+    /// [`CPU::set_audio_sink`] is the way to actually hear it.
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    fn fetch(&mut self) -> Result<Instruction, ExecError> {
+        let pc = self.pc;
+        if let Some(instruction) = self.decoded.get(pc).copied().flatten() {
+            let len = if let SetXLong(_) = instruction {4} else {2};
+            if self.trace_accesses {
+                for word in (pc..pc + len).step_by(2) {
+                    self.trace(AccessKind::Fetch, word, 2);
+                }
+            }
+            self.pc += len;
+            return Ok(instruction);
+        }
+        self.check_memory(self.pc, 2)?;
+        self.trace(AccessKind::Fetch, self.pc, 2);
         let raw = [
             self.memory[self.pc],
             self.memory[self.pc + 1]
         ];
         self.pc += 2;
-        raw
+        let mut instruction = self.decode(raw);
+        // the address for a long index load is in the following word
+        if let SetXLong(_) = instruction {
+            self.check_memory(self.pc, 2)?;
+            self.trace(AccessKind::Fetch, self.pc, 2);
+            instruction = SetXLong(((self.memory[self.pc] as u16) << 8) | self.memory[self.pc + 1] as u16);
+            self.pc += 2;
+        }
+        if self.cache_instructions {
+            self.decoded[pc] = Some(instruction);
+        }
+        Ok(instruction)
     }
 
-    fn decode(&self, raw: [u8; 2]) -> Instruction {
-        let opcode = (raw[0] & HIGH_MASK) >> 4;
-        let register_a = raw[0] & LOW_MASK;
-        let register_b = (raw[1] & HIGH_MASK) >> 4;
-        let N = raw[1] & LOW_MASK;
-        let NN = raw[1];
-        let NNN = (((raw[0] & LOW_MASK) as u16) << 8) | raw[1] as u16;
-
-        match opcode {
-            0x00 => {
-                match raw[1] {
-                    0xE0 => {
-                        ClearScreen
-                    },
-                    0xEE => {
-                        Return
-                    },
-                    0x00 => {
-                        NOP
-                    },
-                    _ => {
-                        Data(raw[0],raw[1])
-                    }
-                }
-            },
-            0x01 => {
-                Jump(NNN)
-            },
-            0x02 => {
-                Call(NNN)
-            },
-            // Skips
-            0x03 => {
-                SkipIEQ(register_a, NN)
-            },
-            0x04 => {
-                SkipINEQ(register_a, NN)
-            },
-            0x05 => {
-                SkipREQ(register_a, register_b)
-            },
-            0x09 => {
-                SkipRNEQ(register_a, register_b)
-            },
-            0x06 => {
-                SetRI(register_a, NN)
-            },
-            0x07 => {
-                AddRI(register_a, NN)
-            },
-            0x08 => {
-                match N {
-                    0x00 => {
-                        SetRR(register_a, register_b)
-                    },
-                    0x01 => {
-                        OrRR(register_a, register_b)
-                    },
-                    0x02 => {
-                        AndRR(register_a, register_b)
-                    },
-                    0x03 => {
-                        XorRR(register_a, register_b)
-                    },
-                    0x04 => {
-                        AddRR(register_a, register_b)
-                    },
-                    0x05 => {
-                        SubAB(register_a, register_b)
-                    },
-                    0x06 => {
-                        ShiftRightRR(register_a, register_b)
-                    },
-                    0x07 => {
-                        SubBA(register_a, register_b)
-                    },
-                    0x0E => {
-                        ShiftLeftRR(register_a, register_b)
-                    },
-                    _ => {
-                        Data(raw[0], raw[1])
-                    }
-                }
+    // Drops decoded instructions and translated blocks that overlap `len`
+    // bytes written at `address`, including ones that start before it.
+    fn forget_instructions(&mut self, address: usize, len: usize) {
+        let end = (address + len).min(self.decoded.len());
+        for entry in &mut self.decoded[address.saturating_sub(3).min(end)..end] {
+            *entry = None;
+        }
+        self.forget_blocks(address, len);
+    }
 
-            }
-            0x0A => {
-                SetX(NNN)
-            },
-            0x0B => {
-                JumpOffset(NNN)
-            }
-            0x0C => {
-                Random(register_a, NN)
-            },
-            0x0D => {
-                Draw(register_a, register_b, N)
-            },
-            0x0E => {
-                match NN {
-                    0x9E => {
-                        SkipKeyEQ(register_a)
-                    },
-                    0xA1 => {
-                        SkipKeyNEQ(register_a)
-                    },
-                    _ => {
-                        Data(raw[0], raw[1])
-                    }
-                }
-            },
-            0x0F => {
-                match NN {
-                    0x07 => {
-                        SetRDelay(register_a)
-                    },
-                    0x15 => {
-                        SetDelayR(register_a)
-                    },
-                    0x18 => {
-                        SetSoundR(register_a)
-                    },
-                    0x1E => {
-                        AddXR(register_a)
-                    },
-                    0x0A => {
-                        GetKey(register_a)
-                    },
-                    0x29 => {
-                        SetXFontR(register_a)
-                    },
-                    0x33 => {
-                        StoreDecimalR(register_a)
-                    },
-                    0x55 => {
-                        Store(register_a)
-                    },
-                    0x65 => {
-                        Load(register_b)
-                    }
-                    _ => {
-                        Data(raw[0], raw[1])
-                    }
-                }
-            }
-            _ => {
-                Data(raw[0], raw[1])
-            }
+    /// Decodes the instruction at `address` without executing it, returning it
+    /// with its length in bytes. Returns `None` past the end of memory.
+    pub fn decode_at(&self, address: usize) -> Option<(Instruction, usize)> {
+        Instruction::decode(self.memory.get(address..)?, self.platform)
+    }
+
+    // Skips the next instruction, which on XO-CHIP may be four bytes long.
+    fn skip(&mut self) {
+        let long = self.platform.has_xo_instructions()
+            && self.memory.get(self.pc) == Some(&0xF0)
+            && self.memory.get(self.pc + 1) == Some(&0x00);
+        self.pc += if long {4} else {2};
+    }
+
+    // Fails unless `len` bytes starting at `address` are all inside memory.
+    fn check_memory(&self, address: usize, len: usize) -> Result<(), ExecError> {
+        if address + len > self.memory.len() {
+            let first_bad = address.max(self.memory.len());
+            return Err(ExecError::MemoryOutOfBounds { pc: self.instruction_pc, address: first_bad });
         }
+        Ok(())
     }
 
-    fn execute(&mut self, instruction: Instruction) {
+    fn decode(&self, raw: [u8; 2]) -> Instruction {
+        Instruction::decode_word(raw, self.platform)
+    }
 
-        //println!("{:?}", instruction);
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, ExecError> {
         match instruction {
             NOP => {
 
             },
             ClearScreen => {
-                for i in 0..DISPLAY_BUFFER {
-                    self.display[i] = 0;
-                }
+                let keep = !self.plane_mask;
+                self.display.iter_mut().for_each(|p| *p &= keep);
+            },
+            ScrollDown(n) => {
+                self.scroll_vertical(n as usize, true);
+            },
+            ScrollUp(n) => {
+                self.scroll_vertical(n as usize, false);
+            },
+            ScrollRight => {
+                self.scroll_horizontal(4, true);
+            },
+            ScrollLeft => {
+                self.scroll_horizontal(4, false);
+            },
+            Exit => {
+                self.halted = true;
+                self.pc = self.instruction_pc;
+                return Ok(StepOutcome::Halted);
+            },
+            LowRes => {
+                self.hires = false;
+                self.display.fill(0);
+            },
+            HighRes => {
+                self.hires = true;
+                self.display.fill(0);
             },
             Jump(a) => {
                 self.pc = a as usize;
@@ -267,108 +685,112 @@ impl CPU {
                 self.index_register = n;
             },
             Draw(a, b, n) => {
-                self.draw(a, b, n);
+                self.draw(a, b, n)?;
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
+                }
             },
             Call(n) => {
+                if self.sp >= STACK_SIZE {
+                    return Err(ExecError::StackOverflow { pc: self.instruction_pc });
+                }
                 self.stack[self.sp] = self.pc;
                 self.sp += 1;
                 self.pc = n as usize;
             },
             Return => {
+                if self.sp == 0 {
+                    return Err(ExecError::StackUnderflow { pc: self.instruction_pc });
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp];
             },
             SkipIEQ(r, n) => {
                 if self.general_registers[r as usize] == n {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             SkipINEQ(r, n) => {
                 if self.general_registers[r as usize] != n {
-                    self.pc += 2;
+                    self.skip();
                 } 
             },
             SkipREQ(a, b) => {
                 if self.general_registers[a as usize] == self.general_registers[b as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             SkipRNEQ(a, b) => {
                 if self.general_registers[a as usize] != self.general_registers[b as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             SetRR(a, b) => {
                 self.general_registers[a as usize] = self.general_registers[b as usize];
             },
             OrRR(a, b) => {
-                self.general_registers[a as usize] = self.general_registers[a as usize] | self.general_registers[b as usize];
+                self.general_registers[a as usize] |= self.general_registers[b as usize];
+                if self.quirks.logic_resets_vf {
+                    self.general_registers[0xF] = 0;
+                }
             },
             AndRR(a, b) => {
-                self.general_registers[a as usize] = self.general_registers[a as usize] & self.general_registers[b as usize];
+                self.general_registers[a as usize] &= self.general_registers[b as usize];
+                if self.quirks.logic_resets_vf {
+                    self.general_registers[0xF] = 0;
+                }
             },
             XorRR(a, b) => {
-                self.general_registers[a as usize] = self.general_registers[a as usize] ^ self.general_registers[b as usize];
+                self.general_registers[a as usize] ^= self.general_registers[b as usize];
+                if self.quirks.logic_resets_vf {
+                    self.general_registers[0xF] = 0;
+                }
             },
             AddRR(a, b) => {
-                let mut t = self.general_registers[a as usize] as u16 + self.general_registers[b as usize] as u16;
-                if t > 0xFF {
-                    self.general_registers[0xF] = 1;
-                    t = t - 0x100;
-                }
-                self.general_registers[a as usize] = t as u8;
+                let (v, carry) = self.general_registers[a as usize].overflowing_add(self.general_registers[b as usize]);
+                self.general_registers[a as usize] = v;
+                self.general_registers[0xF] = carry as u8;
             },
             SubAB(a, b) => {
-                let r = a;
-                let mut a = self.general_registers[a as usize] as i16;
-                let b = self.general_registers[b as usize] as i16;
-                if a > b {
-                    self.general_registers[0xF] = 1;
-                } else {
-                    self.general_registers[0xF] = 0;
-                    a += 256;
-                }
-                self.general_registers[r as usize] = (a - b) as u8;
+                let (v, borrow) = self.general_registers[a as usize].overflowing_sub(self.general_registers[b as usize]);
+                self.general_registers[a as usize] = v;
+                self.general_registers[0xF] = !borrow as u8;
             },
             SubBA(a, b) => {
-                let r = a;
-                let a = self.general_registers[a as usize] as i16;
-                let mut b = self.general_registers[b as usize] as i16;
-                if b > a {
-                    self.general_registers[0xF] = 1;
-                } else {
-                    self.general_registers[0xF] = 0;
-                    b += 256;
-                }
-                self.general_registers[r as usize] = (b - a) as u8;
+                let (v, borrow) = self.general_registers[b as usize].overflowing_sub(self.general_registers[a as usize]);
+                self.general_registers[a as usize] = v;
+                self.general_registers[0xF] = !borrow as u8;
             },
-            ShiftRightRR(a, _) => {
-                let outbit = self.general_registers[a as usize] & 0x01;
-                self.general_registers[0xF] = if outbit > 0 {1} else {0};
-                self.general_registers[a as usize] = self.general_registers[a as usize] >> 1;
+            ShiftRightRR(a, b) => {
+                let source = if self.quirks.shift_uses_vy {b} else {a};
+                let v = self.general_registers[source as usize];
+                self.general_registers[a as usize] = v >> 1;
+                self.general_registers[0xF] = v & 0x01;
             },
-            ShiftLeftRR(a, _) => {
-                let outbit = self.general_registers[a as usize] & 0x80;
-                self.general_registers[0xF] = if outbit > 0 {1} else {0};
-                self.general_registers[a as usize] = self.general_registers[a as usize] << 1;
+            ShiftLeftRR(a, b) => {
+                let source = if self.quirks.shift_uses_vy {b} else {a};
+                let v = self.general_registers[source as usize];
+                self.general_registers[a as usize] = v << 1;
+                self.general_registers[0xF] = (v & 0x80) >> 7;
             },
             JumpOffset(offset) => {
-                self.pc = self.general_registers[0] as usize + offset as usize;
+                let register = if self.quirks.jump_uses_vx {(offset >> 8) as usize} else {0};
+                self.pc = self.general_registers[register] as usize + offset as usize;
             },
             Random(register_a, n) => {
-                let k =  rand::thread_rng().gen_range(0..0xFF) & n;
+                let k = self.rng.gen::<u8>() & n;
                 self.general_registers[register_a as usize] = k;
             },
             SkipKeyEQ(a) => {
                 let v = self.general_registers[a as usize] & 0xF;
                 if self.keys[v as usize] > 0 {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             SkipKeyNEQ(a) => {
                 let v = self.general_registers[a as usize] & 0xF;
                 if self.keys[v as usize] == 0 {
-                    self.pc += 2;
+                    self.skip();
                 }
             },
             SetRDelay(a) => {
@@ -381,8 +803,8 @@ impl CPU {
                 self.sound_timer = self.general_registers[a as usize];
             },
             AddXR(a) => {
-                self.index_register += self.general_registers[a as usize] as u16;
-                if self.index_register > 0xFFF {
+                self.index_register = self.index_register.wrapping_add(self.general_registers[a as usize] as u16);
+                if self.quirks.index_overflow_sets_vf && self.index_register > 0xFFF {
                     self.general_registers[0xF] = 1;
                 }
             },
@@ -391,81 +813,206 @@ impl CPU {
                 self.index_register = self.address_for_font(hex) as u16;
                 
             },
+            SetXBigFontR(a) => {
+                let hex = self.general_registers[a as usize] & 0xF;
+                self.index_register = self.address_for_big_font(hex) as u16;
+            },
+            SetXLong(n) => {
+                self.index_register = n;
+            },
+            SelectPlane(n) => {
+                self.plane_mask = n & 0x3;
+            },
+            StoreRange(a, b) => {
+                let i = self.index_register as usize;
+                let count = a.abs_diff(b) as usize + 1;
+                self.check_memory(i, count)?;
+                self.trace(AccessKind::Write, i, count);
+                self.forget_instructions(i, count);
+                for k in 0..count {
+                    let r = if a <= b {a as usize + k} else {a as usize - k};
+                    self.memory[i + k] = self.general_registers[r];
+                }
+            },
+            LoadRange(a, b) => {
+                let i = self.index_register as usize;
+                let count = a.abs_diff(b) as usize + 1;
+                self.check_memory(i, count)?;
+                self.trace(AccessKind::Read, i, count);
+                for k in 0..count {
+                    let r = if a <= b {a as usize + k} else {a as usize - k};
+                    self.general_registers[r] = self.memory[i + k];
+                }
+            },
+            SetPitchR(a) => {
+                self.pitch = self.general_registers[a as usize];
+            },
+            LoadAudio => {
+                let i = self.index_register as usize;
+                self.check_memory(i, 16)?;
+                self.trace(AccessKind::Read, i, 16);
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[i..i + 16]);
+                self.audio_pattern = Some(pattern);
+            },
+            StoreFlags(a) => {
+                let n = a as usize + 1;
+                self.flags[..n].copy_from_slice(&self.general_registers[..n]);
+            },
+            LoadFlags(a) => {
+                let n = a as usize + 1;
+                self.general_registers[..n].copy_from_slice(&self.flags[..n]);
+            },
             StoreDecimalR(a) => {
                 let v = self.general_registers[a as usize];
-                self.memory[self.index_register as usize] = v / 100;
-                self.memory[(self.index_register + 1) as usize] = (v / 10) % 10;
-                self.memory[(self.index_register + 2) as usize] = v % 10;
+                let i = self.index_register as usize;
+                self.check_memory(i, 3)?;
+                self.trace(AccessKind::Write, i, 3);
+                self.forget_instructions(i, 3);
+                self.memory[i] = v / 100;
+                self.memory[i + 1] = (v / 10) % 10;
+                self.memory[i + 2] = v % 10;
             }
             GetKey(a) => {
-                let mut hex = 0;
-                for key in self.keys {
-                    if key > 0 {
-                        self.general_registers[a as usize] = hex;
-                        return;
+                for (hex, key) in self.keys.iter().enumerate() {
+                    if *key > 0 {
+                        self.general_registers[a as usize] = hex as u8;
+                        return Ok(StepOutcome::Executed);
                     }
-                    hex += 1;
                 }
                 // decrement pc if we haven't encountered a key press
                 self.pc -=2;
+                return Ok(StepOutcome::WaitingForKey);
             },
             Store(a) => {
-                for r in 0..a+1 {
-                   self.memory[(self.index_register + r as u16) as usize] = self.general_registers[r as usize]; 
+                let i = self.index_register as usize;
+                self.check_memory(i, a as usize + 1)?;
+                self.trace(AccessKind::Write, i, a as usize + 1);
+                self.forget_instructions(i, a as usize + 1);
+                for r in 0..a as usize + 1 {
+                   self.memory[i + r] = self.general_registers[r]; 
+                }
+                if self.quirks.memory_increments_i {
+                    self.index_register = self.index_register.wrapping_add(a as u16 + 1);
                 }
             },
             Load(a) => {
-                for r in 0..a+1 {
-                    self.general_registers[r as usize] = self.memory[(self.index_register + r as u16) as usize];
+                let i = self.index_register as usize;
+                self.check_memory(i, a as usize + 1)?;
+                self.trace(AccessKind::Read, i, a as usize + 1);
+                for r in 0..a as usize + 1 {
+                    self.general_registers[r] = self.memory[i + r];
+                }
+                if self.quirks.memory_increments_i {
+                    self.index_register = self.index_register.wrapping_add(a as u16 + 1);
                 }
             }
             Data(a, b) => {
-                panic!("Should not be executing this! Line: {} {} {}", self.pc, a, b)
+                let raw = ((a as u16) << 8) | b as u16;
+                return Err(ExecError::UnknownOpcode { pc: self.instruction_pc, raw });
             },
         }
+        Ok(StepOutcome::Executed)
     }
 
 
-    fn draw(&mut self, a: u8, b: u8, n: u8) {
-        let x = self.general_registers[a as usize] as usize % DISPLAY_WIDTH;
-        let mut y = self.general_registers[b as usize] as usize % DISPLAY_HEIGHT;
+    fn draw(&mut self, a: u8, b: u8, n: u8) -> Result<(), ExecError> {
+        let width = self.display_width();
+        let height = self.display_height();
+        let x = self.general_registers[a as usize] as usize % width;
+        let y = self.general_registers[b as usize] as usize % height;
+        // DXY0 draws a 16x16 sprite on SUPER-CHIP
+        let (rows, columns) = if n == 0 && self.platform.has_schip_instructions() {
+            (16, 16)
+        } else {
+            (n as usize, 8)
+        };
+        let bytes_per_row = columns / 8;
+        let sprite_len = rows * bytes_per_row;
+        // each selected plane takes the next sprite's worth of data
+        let mut sprite = self.index_register as usize;
+        let total_len = sprite_len * self.plane_mask.count_ones() as usize;
+        self.check_memory(sprite, total_len)?;
+        self.trace(AccessKind::Read, sprite, total_len);
+        let planes = [1u8, 2].into_iter().filter(|p| self.plane_mask & p > 0);
         self.general_registers[0xF] = 0;
-        for row in 0..n as usize {
-            let mut mx = x;
-            let sprite_row = self.memory[self.index_register as usize + row];
-            for i in 0..8 {
-                let bit = sprite_row & (1 << (7 - i));
-                if bit > 0 {
-                    let c = y*DISPLAY_WIDTH + mx;
-                    if self.display[c] == 1 {
-                        self.general_registers[0xF] = 1;
-                        self.display[c] = 0;
-                    } else {
-                        self.display[c] = 1;
+        let wrap = self.quirks.wrap_sprites;
+        for plane in planes {
+            for row in 0..rows {
+                let mut py = y + row;
+                if py >= height {
+                    if !wrap {
+                        break
                     }
+                    py %= height;
                 }
-                if mx == DISPLAY_WIDTH - 1 {
-                    break
+                let sprite_row = if bytes_per_row == 2 {
+                    ((self.memory[sprite + row * 2] as u16) << 8) | self.memory[sprite + row * 2 + 1] as u16
+                } else {
+                    (self.memory[sprite + row] as u16) << 8
+                };
+                for i in 0..columns {
+                    let mut px = x + i;
+                    if px >= width {
+                        if !wrap {
+                            break
+                        }
+                        px %= width;
+                    }
+                    let bit = sprite_row & (0x8000 >> i);
+                    if bit > 0 {
+                        let c = py*width + px;
+                        if self.display[c] & plane > 0 {
+                            self.general_registers[0xF] = 1;
+                        }
+                        self.display[c] ^= plane;
+                    }
                 }
-                mx += 1;
             }
-            if y == DISPLAY_HEIGHT -1 {
-                break
+            sprite += sprite_len;
+        }
+        Ok(())
+    }
+
+    fn scroll_vertical(&mut self, n: usize, down: bool) {
+        let width = self.display_width();
+        let height = self.display_height();
+        let mask = self.plane_mask;
+        let rows: Vec<usize> = if down {(0..height).rev().collect()} else {(0..height).collect()};
+        for y in rows {
+            let source = if down {y.checked_sub(n)} else {Some(y + n).filter(|s| *s < height)};
+            for x in 0..width {
+                let moved = source.map_or(0, |s| self.display[s*width + x] & mask);
+                let c = y*width + x;
+                self.display[c] = (self.display[c] & !mask) | moved;
             }
-            y += 1;
         }
     }
 
+    fn scroll_horizontal(&mut self, n: usize, right: bool) {
+        let width = self.display_width();
+        let height = self.display_height();
+        let mask = self.plane_mask;
+        for y in 0..height {
+            let row = &mut self.display[y*width..(y + 1)*width];
+            let xs: Vec<usize> = if right {(0..width).rev().collect()} else {(0..width).collect()};
+            for x in xs {
+                let source = if right {x.checked_sub(n)} else {Some(x + n).filter(|s| *s < width)};
+                let moved = source.map_or(0, |s| row[s] & mask);
+                row[x] = (row[x] & !mask) | moved;
+            }
+        }
+    }
+
+    /// Prints the instruction at the program counter.
     pub fn dump_current(&self) {
-        let instr = self.decode(
-            [
-                self.memory[self.pc],
-                self.memory[self.pc + 1]
-            ]
-        );
-        println!("{:#08x}:\t{:?}", self.pc, instr);
+        match self.decode_at(self.pc) {
+            Some((instr, _)) => println!("{:#08x}:\t{}", self.pc, instr),
+            None => println!("{:#08x}:\t<out of bounds>", self.pc),
+        }
     }
 
+    /// Prints V0-VF and I.
     pub fn dump_registers(&self) {
         for r in 0..16 {
             println!("{}: {}", r, self.general_registers[r]);
@@ -473,16 +1020,33 @@ impl CPU {
         println!("i: {}", self.index_register);
     }
     
+    /// Prints every non-zero word of memory decoded as an instruction.
     pub fn dump_memory_instr(&self) {
-        for i in 0..4096/2 {
+        for i in 0..self.memory.len()/2 {
             let instr = self.decode([
                 self.memory[i*2],
                 self.memory[i*2+1]
             ]);
             match instr {
                 NOP => {},
-                _ => {println!("{:#08x}:\t{:?}", i*2, instr)}
+                _ => {println!("{:#08x}:\t{}", i*2, instr)}
             } 
         }
     }
-}
\ No newline at end of file
+}
+
+/// Direct changes to the stack and display for unit tests.
+#[cfg(test)]
+impl CPU {
+    pub(crate) fn push_stack(&mut self, address: usize) {
+        self.stack[self.sp] = address;
+        self.sp += 1;
+    }
+
+    pub(crate) fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.display[y * self.display_width() + x]
+    }
+}
+
+#[cfg(test)]
+mod tests;
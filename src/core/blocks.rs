@@ -0,0 +1,181 @@
+//! The block engine: runs of instructions up to the next branch are decoded
+//! once into a list of closures, which [`CPU::run_frame`] then calls one
+//! after another. Instructions without a closure of their own go through
+//! [`CPU::execute`], and anything that cannot be translated is left to
+//! [`CPU::step`], so the two engines behave identically.
+//!
+//! A block ends after any instruction that may go somewhere other than the
+//! next one, or that writes memory. Writes drop the blocks they overlap, so
+//! self-modifying code is translated again before it runs.
+
+use super::*;
+use std::rc::Rc;
+
+// Longest run of instructions in one block, and the bytes it may span.
+const MAX_BLOCK_LEN: usize = 64;
+const MAX_BLOCK_BYTES: usize = MAX_BLOCK_LEN * 4;
+
+/// How [`CPU::run_frame`] executes instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Fetch, decode and execute one instruction at a time.
+    #[default]
+    Interpreter,
+    /// Translate straight-line runs of instructions into cached closures.
+    /// Faster for long batch runs; single steps still use the interpreter.
+    Blocks,
+}
+
+type Op = Box<dyn Fn(&mut CPU)>;
+
+enum Action {
+    // register and timer moves that cannot fault and ignore the quirks
+    Run(Op),
+    Execute(Instruction),
+}
+
+struct Entry {
+    address: usize,
+    next: usize,
+    action: Action,
+}
+
+/// A translated run of instructions, covering `start..end` in memory.
+pub(super) struct Block {
+    end: usize,
+    entries: Vec<Entry>,
+}
+
+impl CPU {
+    /// Chooses how [`CPU::run_frame`] executes instructions. The machine
+    /// state is the same either way.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.blocks = Vec::new();
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    // Runs up to `cycles` instructions, stopping where the interpreter would.
+    pub(super) fn run_blocks(&mut self, cycles: usize) -> Result<(), ExecError> {
+        if self.blocks.is_empty() {
+            self.blocks = vec![None; self.memory.len()];
+        }
+        let mut remaining = cycles;
+        'frame: while remaining > 0 {
+            if self.halted || self.waiting_for_vblank {
+                break;
+            }
+            let Some(block) = self.block_at(self.pc) else {
+                // nothing to translate, as past the end of memory, so let step report it
+                remaining -= 1;
+                match self.step()? {
+                    StepOutcome::WaitingForVBlank | StepOutcome::Halted => break,
+                    _ => continue,
+                }
+            };
+            for entry in &block.entries {
+                if remaining == 0 {
+                    self.pc = entry.address;
+                    break 'frame;
+                }
+                remaining -= 1;
+                match &entry.action {
+                    Action::Run(op) => {
+                        op(self);
+                        self.pc = entry.next;
+                    },
+                    Action::Execute(instruction) => {
+                        self.instruction_pc = entry.address;
+                        self.pc = entry.next;
+                        match self.execute(*instruction) {
+                            Ok(StepOutcome::WaitingForVBlank | StepOutcome::Halted) => break 'frame,
+                            Ok(_) => {},
+                            Err(err) => {
+                                self.pc = self.instruction_pc;
+                                return Err(err);
+                            },
+                        }
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Drops the blocks that overlap `len` bytes written at `address`.
+    pub(super) fn forget_blocks(&mut self, address: usize, len: usize) {
+        let end = (address + len).min(self.blocks.len());
+        for start in address.saturating_sub(MAX_BLOCK_BYTES).min(end)..end {
+            if self.blocks[start].as_ref().is_some_and(|block| block.end > address) {
+                self.blocks[start] = None;
+            }
+        }
+    }
+
+    fn block_at(&mut self, address: usize) -> Option<Rc<Block>> {
+        if let Some(block) = self.blocks.get(address)? {
+            return Some(Rc::clone(block));
+        }
+        let block = Rc::new(self.translate(address)?);
+        self.blocks[address] = Some(Rc::clone(&block));
+        Some(block)
+    }
+
+    fn translate(&self, start: usize) -> Option<Block> {
+        let mut entries = Vec::new();
+        let mut address = start;
+        while entries.len() < MAX_BLOCK_LEN {
+            let Some((instruction, len)) = self.decode_at(address) else {
+                break;
+            };
+            let next = address + len;
+            entries.push(Entry { address, next, action: action(instruction) });
+            address = next;
+            if ends_block(instruction) {
+                break;
+            }
+        }
+        (!entries.is_empty()).then_some(Block { end: address, entries })
+    }
+}
+
+fn ends_block(instruction: Instruction) -> bool {
+    instruction.is_branch()
+        || matches!(instruction, GetKey(_) | Draw(..) | Store(_) | StoreRange(..) | StoreDecimalR(_) | Data(..))
+}
+
+fn action(instruction: Instruction) -> Action {
+    let op: Op = match instruction {
+        NOP => Box::new(|_| {}),
+        SetRI(x, n) => Box::new(move |cpu| cpu.general_registers[x as usize] = n),
+        AddRI(x, n) => Box::new(move |cpu| {
+            let v = &mut cpu.general_registers[x as usize];
+            *v = v.wrapping_add(n);
+        }),
+        SetRR(x, y) => Box::new(move |cpu| cpu.general_registers[x as usize] = cpu.general_registers[y as usize]),
+        AddRR(x, y) => Box::new(move |cpu| {
+            let (v, carry) = cpu.general_registers[x as usize].overflowing_add(cpu.general_registers[y as usize]);
+            cpu.general_registers[x as usize] = v;
+            cpu.general_registers[0xF] = carry as u8;
+        }),
+        SubAB(x, y) => Box::new(move |cpu| {
+            let (v, borrow) = cpu.general_registers[x as usize].overflowing_sub(cpu.general_registers[y as usize]);
+            cpu.general_registers[x as usize] = v;
+            cpu.general_registers[0xF] = !borrow as u8;
+        }),
+        SubBA(x, y) => Box::new(move |cpu| {
+            let (v, borrow) = cpu.general_registers[y as usize].overflowing_sub(cpu.general_registers[x as usize]);
+            cpu.general_registers[x as usize] = v;
+            cpu.general_registers[0xF] = !borrow as u8;
+        }),
+        SetX(n) | SetXLong(n) => Box::new(move |cpu| cpu.index_register = n),
+        SetRDelay(x) => Box::new(move |cpu| cpu.general_registers[x as usize] = cpu.delay_timer),
+        SetDelayR(x) => Box::new(move |cpu| cpu.delay_timer = cpu.general_registers[x as usize]),
+        SetSoundR(x) => Box::new(move |cpu| cpu.sound_timer = cpu.general_registers[x as usize]),
+        _ => return Action::Execute(instruction),
+    };
+    Action::Run(op)
+}
@@ -0,0 +1,757 @@
+use super::*;
+
+// A machine that has just fetched the instruction at 0x200.
+fn machine(quirks: Quirks) -> CPU {
+    let mut cpu = CPU::new(quirks);
+    cpu.seed_rng(0);
+    cpu.set_pc(0x202);
+    cpu
+}
+
+fn legacy() -> CPU {
+    machine(Quirks::default())
+}
+
+fn on(platform: Platform) -> CPU {
+    let mut cpu = CPU::with_platform(platform, platform.default_quirks());
+    cpu.set_pc(0x202);
+    cpu
+}
+
+fn exec(cpu: &mut CPU, instruction: Instruction) -> StepOutcome {
+    cpu.execute(instruction).expect("instruction faulted")
+}
+
+// Places `code` at 0x200 and runs one instruction through fetch and decode.
+fn step_code(cpu: &mut CPU, code: &[u8]) -> Result<StepOutcome, ExecError> {
+    cpu.write_memory(0x200, code);
+    cpu.set_pc(0x200);
+    cpu.step()
+}
+
+fn set_pixels(cpu: &mut CPU, pixels: &[(usize, usize)]) {
+    let width = cpu.display_width();
+    for &(x, y) in pixels {
+        cpu.display[y * width + x] = 1;
+    }
+}
+
+fn lit_pixels(cpu: &CPU) -> Vec<(usize, usize)> {
+    let width = cpu.display_width();
+    cpu.framebuffer().iter().enumerate().filter(|(_, p)| **p > 0).map(|(c, _)| (c % width, c / width)).collect()
+}
+
+#[test]
+fn nop_changes_nothing() {
+    let mut cpu = legacy();
+    exec(&mut cpu, NOP);
+    assert_eq!(cpu.pc(), 0x202);
+    assert!(lit_pixels(&cpu).is_empty());
+}
+
+#[test]
+fn clear_screen() {
+    let mut cpu = legacy();
+    set_pixels(&mut cpu, &[(0, 0), (63, 31)]);
+    exec(&mut cpu, ClearScreen);
+    assert!(lit_pixels(&cpu).is_empty());
+}
+
+#[test]
+fn clear_screen_keeps_unselected_planes() {
+    let mut cpu = on(Platform::XoChip);
+    cpu.display[0] = 3;
+    exec(&mut cpu, SelectPlane(2));
+    exec(&mut cpu, ClearScreen);
+    assert_eq!(cpu.pixel(0, 0), 1);
+}
+
+#[test]
+fn scroll_down() {
+    let mut cpu = on(Platform::SuperChip);
+    set_pixels(&mut cpu, &[(5, 0), (5, 30)]);
+    exec(&mut cpu, ScrollDown(2));
+    assert_eq!(lit_pixels(&cpu), vec![(5, 2)]);
+}
+
+#[test]
+fn scroll_up() {
+    let mut cpu = on(Platform::XoChip);
+    set_pixels(&mut cpu, &[(5, 0), (5, 10)]);
+    exec(&mut cpu, ScrollUp(3));
+    assert_eq!(lit_pixels(&cpu), vec![(5, 7)]);
+}
+
+#[test]
+fn scroll_right_and_left() {
+    let mut cpu = on(Platform::SuperChip);
+    set_pixels(&mut cpu, &[(0, 1), (62, 1)]);
+    exec(&mut cpu, ScrollRight);
+    assert_eq!(lit_pixels(&cpu), vec![(4, 1)]);
+    exec(&mut cpu, ScrollLeft);
+    exec(&mut cpu, ScrollLeft);
+    assert!(lit_pixels(&cpu).is_empty());
+}
+
+#[test]
+fn exit_halts_on_the_instruction() {
+    let mut cpu = on(Platform::SuperChip);
+    assert_eq!(step_code(&mut cpu, &[0x00, 0xFD]), Ok(StepOutcome::Halted));
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.step(), Ok(StepOutcome::Halted));
+}
+
+#[test]
+fn resolution_switches_clear_the_screen() {
+    let mut cpu = on(Platform::SuperChip);
+    set_pixels(&mut cpu, &[(1, 1)]);
+    exec(&mut cpu, HighRes);
+    assert!(cpu.is_hires());
+    assert_eq!(cpu.display_width(), 128);
+    assert!(lit_pixels(&cpu).is_empty());
+    set_pixels(&mut cpu, &[(100, 60)]);
+    exec(&mut cpu, LowRes);
+    assert!(!cpu.is_hires());
+    assert!(cpu.display.iter().all(|p| *p == 0));
+}
+
+#[test]
+fn jump() {
+    let mut cpu = legacy();
+    exec(&mut cpu, Jump(0x345));
+    assert_eq!(cpu.pc(), 0x345);
+}
+
+#[test]
+fn set_register_immediate() {
+    let mut cpu = legacy();
+    exec(&mut cpu, SetRI(0x3, 0xAB));
+    assert_eq!(cpu.register(0x3), 0xAB);
+}
+
+#[test]
+fn add_immediate_wraps_without_touching_vf() {
+    let mut cpu = legacy();
+    cpu.set_register(0x2, 0xFF);
+    exec(&mut cpu, AddRI(0x2, 0x03));
+    assert_eq!(cpu.register(0x2), 0x02);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn set_index() {
+    let mut cpu = legacy();
+    exec(&mut cpu, SetX(0x123));
+    assert_eq!(cpu.index(), 0x123);
+}
+
+#[test]
+fn draw_sets_pixels_and_clears_vf() {
+    let mut cpu = legacy();
+    cpu.write_memory(0x300, &[0b1010_0000, 0b0100_0000]);
+    cpu.set_index(0x300);
+    cpu.set_register(0x0, 10);
+    cpu.set_register(0x1, 5);
+    cpu.set_register(0xF, 1);
+    exec(&mut cpu, Draw(0x0, 0x1, 2));
+    assert_eq!(lit_pixels(&cpu), vec![(10, 5), (12, 5), (11, 6)]);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn draw_collision_sets_vf_and_erases() {
+    let mut cpu = legacy();
+    cpu.write_memory(0x300, &[0x80]);
+    cpu.set_index(0x300);
+    exec(&mut cpu, Draw(0x0, 0x0, 1));
+    exec(&mut cpu, Draw(0x0, 0x0, 1));
+    assert_eq!(cpu.register(0xF), 1);
+    assert!(lit_pixels(&cpu).is_empty());
+}
+
+#[test]
+fn draw_start_position_wraps() {
+    let mut cpu = legacy();
+    cpu.write_memory(0x300, &[0x80]);
+    cpu.set_index(0x300);
+    cpu.set_register(0x0, 64 + 3);
+    cpu.set_register(0x1, 32 + 2);
+    exec(&mut cpu, Draw(0x0, 0x1, 1));
+    assert_eq!(lit_pixels(&cpu), vec![(3, 2)]);
+}
+
+#[test]
+fn draw_clips_or_wraps_at_the_edge() {
+    let sprite = [0xFF, 0xFF];
+    for (wrap, expected) in [(false, 4), (true, 16)] {
+        let mut cpu = machine(Quirks { wrap_sprites: wrap, ..Quirks::default() });
+        cpu.write_memory(0x300, &sprite);
+        cpu.set_index(0x300);
+        cpu.set_register(0x0, 60);
+        cpu.set_register(0x1, 31);
+        exec(&mut cpu, Draw(0x0, 0x1, 2));
+        assert_eq!(lit_pixels(&cpu).len(), expected, "wrap = {}", wrap);
+    }
+}
+
+#[test]
+fn draw_waits_for_vblank_with_display_wait() {
+    let mut cpu = machine(Quirks::vip());
+    assert_eq!(step_code(&mut cpu, &[0xD0, 0x01]), Ok(StepOutcome::Executed));
+    assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForVBlank));
+    cpu.tick_timers();
+    assert_ne!(cpu.step(), Ok(StepOutcome::WaitingForVBlank));
+}
+
+#[test]
+fn draw_large_sprite_on_superchip() {
+    let mut cpu = on(Platform::SuperChip);
+    let mut sprite = [0; 32];
+    sprite[0] = 0x80;
+    sprite[31] = 0x01;
+    cpu.write_memory(0x300, &sprite);
+    cpu.set_index(0x300);
+    exec(&mut cpu, HighRes);
+    exec(&mut cpu, Draw(0x0, 0x0, 0));
+    assert_eq!(lit_pixels(&cpu), vec![(0, 0), (15, 15)]);
+}
+
+#[test]
+fn draw_each_selected_plane_from_consecutive_data() {
+    let mut cpu = on(Platform::XoChip);
+    cpu.write_memory(0x300, &[0x80, 0x40]);
+    cpu.set_index(0x300);
+    exec(&mut cpu, SelectPlane(3));
+    exec(&mut cpu, Draw(0x0, 0x0, 1));
+    assert_eq!(cpu.pixel(0, 0), 1);
+    assert_eq!(cpu.pixel(1, 0), 2);
+}
+
+#[test]
+fn draw_out_of_memory_faults() {
+    let mut cpu = legacy();
+    cpu.set_index(0xFFE);
+    assert_eq!(step_code(&mut cpu, &[0xD0, 0x05]), Err(ExecError::MemoryOutOfBounds { pc: 0x200, address: 0x1000 }));
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn call_and_return() {
+    let mut cpu = legacy();
+    exec(&mut cpu, Call(0x400));
+    assert_eq!(cpu.pc(), 0x400);
+    assert_eq!(cpu.stack(), &[0x202]);
+    cpu.set_pc(0x402);
+    exec(&mut cpu, Return);
+    assert_eq!(cpu.pc(), 0x202);
+    assert!(cpu.stack().is_empty());
+}
+
+#[test]
+fn call_overflows_a_full_stack() {
+    let mut cpu = legacy();
+    for _ in 0..STACK_SIZE {
+        cpu.push_stack(0x200);
+    }
+    assert_eq!(step_code(&mut cpu, &[0x23, 0x00]), Err(ExecError::StackOverflow { pc: 0x200 }));
+    assert_eq!(cpu.stack().len(), STACK_SIZE);
+}
+
+#[test]
+fn return_underflows_an_empty_stack() {
+    let mut cpu = legacy();
+    assert_eq!(step_code(&mut cpu, &[0x00, 0xEE]), Err(ExecError::StackUnderflow { pc: 0x200 }));
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn skip_if_equal_immediate() {
+    let mut cpu = legacy();
+    cpu.set_register(0x4, 0x12);
+    exec(&mut cpu, SkipIEQ(0x4, 0x12));
+    assert_eq!(cpu.pc(), 0x204);
+    exec(&mut cpu, SkipIEQ(0x4, 0x13));
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn skip_if_not_equal_immediate() {
+    let mut cpu = legacy();
+    cpu.set_register(0x4, 0x12);
+    exec(&mut cpu, SkipINEQ(0x4, 0x12));
+    assert_eq!(cpu.pc(), 0x202);
+    exec(&mut cpu, SkipINEQ(0x4, 0x13));
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    let mut cpu = legacy();
+    cpu.set_register(0x1, 7);
+    cpu.set_register(0x2, 7);
+    exec(&mut cpu, SkipREQ(0x1, 0x2));
+    assert_eq!(cpu.pc(), 0x204);
+    cpu.set_register(0x2, 8);
+    exec(&mut cpu, SkipREQ(0x1, 0x2));
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn skip_if_registers_not_equal() {
+    let mut cpu = legacy();
+    cpu.set_register(0x1, 7);
+    cpu.set_register(0x2, 7);
+    exec(&mut cpu, SkipRNEQ(0x1, 0x2));
+    assert_eq!(cpu.pc(), 0x202);
+    cpu.set_register(0x2, 8);
+    exec(&mut cpu, SkipRNEQ(0x1, 0x2));
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn skip_steps_over_long_load_on_xochip() {
+    let mut cpu = on(Platform::XoChip);
+    cpu.write_memory(0x202, &[0xF0, 0x00, 0x12, 0x34]);
+    exec(&mut cpu, SkipIEQ(0x0, 0));
+    assert_eq!(cpu.pc(), 0x206);
+
+    let mut cpu = on(Platform::Chip8);
+    cpu.write_memory(0x202, &[0xF0, 0x00, 0x12, 0x34]);
+    exec(&mut cpu, SkipIEQ(0x0, 0));
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn set_register_from_register() {
+    let mut cpu = legacy();
+    cpu.set_register(0xB, 0x42);
+    exec(&mut cpu, SetRR(0xA, 0xB));
+    assert_eq!(cpu.register(0xA), 0x42);
+}
+
+#[test]
+fn logic_ops() {
+    for (instruction, expected) in [(OrRR(0x0, 0x1), 0b1110), (AndRR(0x0, 0x1), 0b1000), (XorRR(0x0, 0x1), 0b0110)] {
+        let mut cpu = legacy();
+        cpu.set_register(0x0, 0b1100);
+        cpu.set_register(0x1, 0b1010);
+        cpu.set_register(0xF, 1);
+        exec(&mut cpu, instruction);
+        assert_eq!(cpu.register(0x0), expected, "{:?}", instruction);
+        assert_eq!(cpu.register(0xF), 1, "{:?}", instruction);
+    }
+}
+
+#[test]
+fn logic_ops_reset_vf_with_quirk() {
+    for instruction in [OrRR(0x0, 0x1), AndRR(0x0, 0x1), XorRR(0x0, 0x1)] {
+        let mut cpu = machine(Quirks { logic_resets_vf: true, ..Quirks::default() });
+        cpu.set_register(0xF, 1);
+        exec(&mut cpu, instruction);
+        assert_eq!(cpu.register(0xF), 0, "{:?}", instruction);
+    }
+}
+
+#[test]
+fn add_registers_carry() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0xF0);
+    cpu.set_register(0x1, 0x20);
+    exec(&mut cpu, AddRR(0x0, 0x1));
+    assert_eq!(cpu.register(0x0), 0x10);
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn add_registers_without_carry_clears_vf() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0x10);
+    cpu.set_register(0x1, 0x20);
+    cpu.set_register(0xF, 1);
+    exec(&mut cpu, AddRR(0x0, 0x1));
+    assert_eq!(cpu.register(0x0), 0x30);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn add_registers_into_vf_keeps_the_flag() {
+    let mut cpu = legacy();
+    cpu.set_register(0xF, 0xFF);
+    cpu.set_register(0x1, 0x02);
+    exec(&mut cpu, AddRR(0xF, 0x1));
+    assert_eq!(cpu.register(0xF), 1);
+
+    cpu.set_register(0xF, 0x01);
+    exec(&mut cpu, AddRR(0xF, 0x1));
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn add_vf_to_itself() {
+    let mut cpu = legacy();
+    cpu.set_register(0xF, 0x90);
+    exec(&mut cpu, AddRR(0xF, 0xF));
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn subtract_registers() {
+    // (VX, VY, VX - VY, VF)
+    for (x, y, result, flag) in [(5, 3, 2, 1), (3, 5, 0xFE, 0), (5, 5, 0, 1), (0, 0xFF, 1, 0)] {
+        let mut cpu = legacy();
+        cpu.set_register(0x0, x);
+        cpu.set_register(0x1, y);
+        exec(&mut cpu, SubAB(0x0, 0x1));
+        assert_eq!((cpu.register(0x0), cpu.register(0xF)), (result, flag), "{} - {}", x, y);
+    }
+}
+
+#[test]
+fn subtract_registers_reversed() {
+    // (VX, VY, VY - VX, VF)
+    for (x, y, result, flag) in [(3, 5, 2, 1), (5, 3, 0xFE, 0), (5, 5, 0, 1), (0xFF, 0, 1, 0)] {
+        let mut cpu = legacy();
+        cpu.set_register(0x0, x);
+        cpu.set_register(0x1, y);
+        exec(&mut cpu, SubBA(0x0, 0x1));
+        assert_eq!((cpu.register(0x0), cpu.register(0xF)), (result, flag), "{} - {}", y, x);
+    }
+}
+
+#[test]
+fn subtract_into_vf_keeps_the_flag() {
+    let mut cpu = legacy();
+    cpu.set_register(0xF, 5);
+    cpu.set_register(0x1, 3);
+    exec(&mut cpu, SubAB(0xF, 0x1));
+    assert_eq!(cpu.register(0xF), 1);
+
+    cpu.set_register(0xF, 5);
+    exec(&mut cpu, SubBA(0xF, 0x1));
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn shift_right() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0b101);
+    cpu.set_register(0x1, 0b1000);
+    exec(&mut cpu, ShiftRightRR(0x0, 0x1));
+    assert_eq!(cpu.register(0x0), 0b10);
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn shift_left() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0b0100_0001);
+    cpu.set_register(0x1, 0b1000_0000);
+    exec(&mut cpu, ShiftLeftRR(0x0, 0x1));
+    assert_eq!(cpu.register(0x0), 0b1000_0010);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn shifts_read_vy_with_quirk() {
+    let mut cpu = machine(Quirks { shift_uses_vy: true, ..Quirks::default() });
+    cpu.set_register(0x0, 0);
+    cpu.set_register(0x1, 0b1000_0001);
+    exec(&mut cpu, ShiftRightRR(0x0, 0x1));
+    assert_eq!((cpu.register(0x0), cpu.register(0xF)), (0b0100_0000, 1));
+    exec(&mut cpu, ShiftLeftRR(0x0, 0x1));
+    assert_eq!((cpu.register(0x0), cpu.register(0xF)), (0b0000_0010, 1));
+    assert_eq!(cpu.register(0x1), 0b1000_0001);
+}
+
+#[test]
+fn shift_into_vf_keeps_the_flag() {
+    let mut cpu = legacy();
+    cpu.set_register(0xF, 0b10);
+    exec(&mut cpu, ShiftRightRR(0xF, 0xF));
+    assert_eq!(cpu.register(0xF), 0);
+    cpu.set_register(0xF, 0x80);
+    exec(&mut cpu, ShiftLeftRR(0xF, 0xF));
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn jump_with_offset() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0x10);
+    cpu.set_register(0x3, 0x20);
+    exec(&mut cpu, JumpOffset(0x345));
+    assert_eq!(cpu.pc(), 0x355);
+
+    let mut cpu = machine(Quirks { jump_uses_vx: true, ..Quirks::default() });
+    cpu.set_register(0x0, 0x10);
+    cpu.set_register(0x3, 0x20);
+    exec(&mut cpu, JumpOffset(0x345));
+    assert_eq!(cpu.pc(), 0x365);
+}
+
+#[test]
+fn random_is_masked_and_seeded() {
+    let mut a = legacy();
+    let mut b = legacy();
+    for _ in 0..32 {
+        exec(&mut a, Random(0x0, 0x0F));
+        exec(&mut b, Random(0x0, 0x0F));
+        assert!(a.register(0x0) <= 0x0F);
+        assert_eq!(a.register(0x0), b.register(0x0));
+    }
+    exec(&mut a, Random(0x0, 0));
+    assert_eq!(a.register(0x0), 0);
+}
+
+#[test]
+fn skip_on_key() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 0xA);
+    exec(&mut cpu, SkipKeyEQ(0x0));
+    assert_eq!(cpu.pc(), 0x202);
+    exec(&mut cpu, SkipKeyNEQ(0x0));
+    assert_eq!(cpu.pc(), 0x204);
+    cpu.set_key(0xA, true);
+    exec(&mut cpu, SkipKeyEQ(0x0));
+    assert_eq!(cpu.pc(), 0x206);
+    exec(&mut cpu, SkipKeyNEQ(0x0));
+    assert_eq!(cpu.pc(), 0x206);
+}
+
+#[test]
+fn timers() {
+    let mut cpu = legacy();
+    cpu.set_register(0x0, 30);
+    cpu.set_register(0x1, 5);
+    exec(&mut cpu, SetDelayR(0x0));
+    exec(&mut cpu, SetSoundR(0x1));
+    assert_eq!((cpu.delay_timer, cpu.sound_timer), (30, 5));
+    cpu.tick_timers();
+    exec(&mut cpu, SetRDelay(0x2));
+    assert_eq!(cpu.register(0x2), 29);
+    assert!(cpu.is_sound_active());
+}
+
+#[test]
+fn add_to_index() {
+    let mut cpu = machine(Quirks { index_overflow_sets_vf: false, ..Quirks::default() });
+    cpu.set_index(0xFFE);
+    cpu.set_register(0x0, 4);
+    exec(&mut cpu, AddXR(0x0));
+    assert_eq!(cpu.index(), 0x1002);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn add_to_index_sets_vf_on_overflow_with_quirk() {
+    let mut cpu = legacy();
+    cpu.set_index(0xFFE);
+    cpu.set_register(0x0, 4);
+    exec(&mut cpu, AddXR(0x0));
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn get_key_waits_for_a_key() {
+    let mut cpu = legacy();
+    assert_eq!(step_code(&mut cpu, &[0xF3, 0x0A]), Ok(StepOutcome::WaitingForKey));
+    assert_eq!(cpu.pc(), 0x200);
+    cpu.set_key(0x7, true);
+    assert_eq!(cpu.step(), Ok(StepOutcome::Executed));
+    assert_eq!(cpu.register(0x3), 0x7);
+    assert_eq!(cpu.pc(), 0x202);
+}
+
+#[test]
+fn font_addresses() {
+    let mut cpu = on(Platform::SuperChip);
+    cpu.set_register(0x0, 0x1A);
+    exec(&mut cpu, SetXFontR(0x0));
+    assert_eq!(cpu.index(), (FONT_ADDRESS + 0xA * 5) as u16);
+    let i = cpu.index() as usize;
+    assert_eq!(&cpu.memory()[i..i + 5], &[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    exec(&mut cpu, SetXBigFontR(0x0));
+    assert_eq!(cpu.index(), (BIG_FONT_ADDRESS + 0xA * 10) as u16);
+}
+
+#[test]
+fn store_decimal() {
+    let mut cpu = legacy();
+    cpu.set_register(0x5, 209);
+    cpu.set_index(0x300);
+    exec(&mut cpu, StoreDecimalR(0x5));
+    assert_eq!(&cpu.memory()[0x300..0x303], &[2, 0, 9]);
+    assert_eq!(cpu.index(), 0x300);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let mut cpu = legacy();
+    for r in 0..16 {
+        cpu.set_register(r, r as u8 + 1);
+    }
+    cpu.set_index(0x300);
+    exec(&mut cpu, Store(0x2));
+    assert_eq!(&cpu.memory()[0x300..0x304], &[1, 2, 3, 0]);
+    assert_eq!(cpu.index(), 0x300);
+
+    cpu.write_memory(0x300, &[9, 9, 9, 9]);
+    exec(&mut cpu, Load(0x2));
+    assert_eq!(cpu.register(0x2), 9);
+    assert_eq!(cpu.register(0x3), 4);
+    assert_eq!(cpu.index(), 0x300);
+}
+
+#[test]
+fn store_and_load_increment_index_with_quirk() {
+    let mut cpu = machine(Quirks { memory_increments_i: true, ..Quirks::default() });
+    cpu.set_index(0x300);
+    exec(&mut cpu, Store(0x3));
+    assert_eq!(cpu.index(), 0x304);
+    exec(&mut cpu, Load(0x0));
+    assert_eq!(cpu.index(), 0x305);
+}
+
+#[test]
+fn store_at_the_end_of_memory() {
+    let mut cpu = machine(Quirks::vip());
+    cpu.set_index(0xFFF);
+    cpu.set_register(0x0, 0xAB);
+    exec(&mut cpu, Store(0x0));
+    assert_eq!(&cpu.memory()[0xFFF..], &[0xAB]);
+    assert_eq!(step_code(&mut cpu, &[0xF1, 0x55]), Err(ExecError::MemoryOutOfBounds { pc: 0x200, address: 0x1000 }));
+}
+
+#[test]
+fn index_increment_wraps_at_the_end_of_xochip_memory() {
+    let mut cpu = CPU::with_platform(Platform::XoChip, Quirks::vip());
+    cpu.set_index(0xFFFF);
+    exec(&mut cpu, Load(0x0));
+    assert_eq!(cpu.index(), 0);
+}
+
+#[test]
+fn store_and_load_flags() {
+    let mut cpu = on(Platform::SuperChip);
+    cpu.set_register(0x0, 1);
+    cpu.set_register(0x1, 2);
+    cpu.set_register(0x2, 3);
+    exec(&mut cpu, StoreFlags(0x1));
+    for r in 0..3 {
+        cpu.set_register(r, 0);
+    }
+    exec(&mut cpu, LoadFlags(0x2));
+    assert_eq!([cpu.register(0x0), cpu.register(0x1), cpu.register(0x2)], [1, 2, 0]);
+}
+
+#[test]
+fn long_index_load() {
+    let mut cpu = on(Platform::XoChip);
+    assert_eq!(step_code(&mut cpu, &[0xF0, 0x00, 0xBE, 0xEF]), Ok(StepOutcome::Executed));
+    assert_eq!(cpu.index(), 0xBEEF);
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn store_and_load_register_ranges() {
+    let mut cpu = on(Platform::XoChip);
+    cpu.set_index(0x300);
+    for r in 0..16 {
+        cpu.set_register(r, r as u8);
+    }
+    exec(&mut cpu, StoreRange(0x2, 0x4));
+    assert_eq!(&cpu.memory()[0x300..0x303], &[2, 3, 4]);
+    exec(&mut cpu, StoreRange(0x4, 0x2));
+    assert_eq!(&cpu.memory()[0x300..0x303], &[4, 3, 2]);
+    assert_eq!(cpu.index(), 0x300);
+
+    exec(&mut cpu, LoadRange(0x8, 0x9));
+    assert_eq!([cpu.register(0x8), cpu.register(0x9)], [4, 3]);
+    exec(&mut cpu, LoadRange(0x9, 0x8));
+    assert_eq!([cpu.register(0x8), cpu.register(0x9)], [3, 4]);
+}
+
+#[test]
+fn pitch_and_audio_pattern() {
+    let mut cpu = on(Platform::XoChip);
+    cpu.set_register(0x0, 112);
+    exec(&mut cpu, SetPitchR(0x0));
+    assert_eq!(cpu.pitch(), 112);
+
+    let pattern: Vec<u8> = (0..16).collect();
+    cpu.write_memory(0x300, &pattern);
+    cpu.set_index(0x300);
+    exec(&mut cpu, LoadAudio);
+    assert_eq!(cpu.audio_pattern().map(|p| p.to_vec()), Some(pattern));
+}
+
+#[test]
+fn unknown_opcode_faults() {
+    let mut cpu = legacy();
+    assert_eq!(step_code(&mut cpu, &[0xF0, 0xFF]), Err(ExecError::UnknownOpcode { pc: 0x200, raw: 0xF0FF }));
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn decode_memory_instructions_use_x() {
+    let cpu = legacy();
+    assert_eq!(cpu.decode([0xF1, 0x55]), Store(0x1));
+    assert_eq!(cpu.decode([0xF1, 0x65]), Load(0x1));
+    assert_eq!(cpu.decode([0xF1, 0x33]), StoreDecimalR(0x1));
+}
+
+#[test]
+fn decode_is_platform_gated() {
+    assert_eq!(legacy().decode([0x00, 0xFF]), Data(0x00, 0xFF));
+    assert_eq!(on(Platform::SuperChip).decode([0x00, 0xFF]), HighRes);
+    assert_eq!(on(Platform::SuperChip).decode([0xF2, 0x01]), Data(0xF2, 0x01));
+    assert_eq!(on(Platform::XoChip).decode([0xF2, 0x01]), SelectPlane(2));
+}
+
+#[test]
+fn step_records_memory_accesses_when_tracing() {
+    let mut cpu = legacy();
+    cpu.set_index(0x300);
+    step_code(&mut cpu, &[0xF2, 0x55]).unwrap();
+    assert!(cpu.accesses().is_empty());
+
+    cpu.set_access_tracing(true);
+    step_code(&mut cpu, &[0xF2, 0x55]).unwrap();
+    assert_eq!(cpu.accesses(), &[
+        MemoryAccess { kind: AccessKind::Fetch, address: 0x200, len: 2 },
+        MemoryAccess { kind: AccessKind::Write, address: 0x300, len: 3 },
+    ]);
+    step_code(&mut cpu, &[0xD0, 0x04]).unwrap();
+    assert_eq!(cpu.accesses()[1], MemoryAccess { kind: AccessKind::Read, address: 0x300, len: 4 });
+}
+
+#[test]
+fn code_written_by_the_program_runs() {
+    let program = [
+        0x70, 0x01, // V0 += 1
+        0x60, 0x71, // V0 = 0x71
+        0x61, 0x05, // V1 = 5
+        0xA2, 0x00, // I = 0x200
+        0xF1, 0x55, // store V0-V1 over the first instruction: V1 += 5
+        0x12, 0x00, // jump 200
+    ];
+    for cache in [true, false] {
+        let mut cpu = legacy();
+        cpu.set_instruction_cache(cache);
+        cpu.load(program.to_vec()).unwrap();
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.register(0), cpu.register(1)), (0x71, 10), "cache {}", cache);
+    }
+}
+
+#[test]
+fn writing_a_long_address_is_seen() {
+    let mut cpu = on(Platform::XoChip);
+    step_code(&mut cpu, &[0xF0, 0x00, 0x03, 0x00]).unwrap();
+    assert_eq!(cpu.index(), 0x300);
+    step_code(&mut cpu, &[0xF0, 0x00, 0x04, 0x00]).unwrap();
+    assert_eq!(cpu.index(), 0x400);
+}
@@ -0,0 +1,776 @@
+//! A Debug Adapter Protocol server, so editors such as VS Code can debug a
+//! ROM while the emulator keeps running its window.
+//!
+//! Breakpoints can be set on addresses, or on assembler source lines through
+//! a [`SourceMap`] passed as `sourceMap` in the launch arguments. Registers,
+//! timers and the call stack are shown as variables, and memory can be read,
+//! written and disassembled.
+//!
+//! Like [`GdbStub`](crate::gdb::GdbStub), the server never blocks once
+//! connected: the frontend calls [`DapServer::poll`] regularly and runs frames
+//! through [`DapServer::run_frame`].
+
+use crate::core::CPU;
+use crate::debugger::{Debugger, Expr, Stop};
+use crate::error::{ExecError, StepOutcome};
+use crate::source_map::SourceMap;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+const THREAD_ID: u64 = 1;
+// far more than any request needs, even writing all of XO-CHIP memory
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+// variablesReference for each scope
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const STACK: u64 = 3;
+
+/// A breakpoint as the editor set it.
+#[derive(Debug, Clone)]
+struct Breakpoint {
+    address: usize,
+    condition: Option<String>,
+}
+
+/// A debug adapter for one machine, talking to one editor.
+pub struct DapServer {
+    requests: Receiver<io::Result<Value>>,
+    output: Box<dyn Write + Send>,
+    // messages waiting to be sent, without their sequence numbers
+    outbox: Vec<Value>,
+    seq: u64,
+    debugger: Debugger,
+    source_map: SourceMap,
+    source_breakpoints: BTreeMap<PathBuf, Vec<Breakpoint>>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    stop_on_entry: bool,
+    launched: Option<PathBuf>,
+    attached: bool,
+    quit: bool,
+}
+
+impl DapServer {
+    /// Speaks the protocol over `input` and `output`, such as stdin and
+    /// stdout. The machine stays stopped until the editor finishes
+    /// configuring breakpoints.
+    pub fn new<R: Read + Send + 'static, W: Write + Send + 'static>(input: R, output: W) -> Self {
+        let (sender, requests) = channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            loop {
+                let message = match read_message(&mut input) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => Err(io::Error::new(ErrorKind::UnexpectedEof, "editor disconnected")),
+                    Err(err) => Err(err),
+                };
+                let done = message.is_err();
+                if sender.send(message).is_err() || done {
+                    break;
+                }
+            }
+        });
+        DapServer {
+            requests,
+            output: Box::new(output),
+            outbox: Vec::new(),
+            seq: 1,
+            debugger: Debugger::new(),
+            source_map: SourceMap::new(),
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: Vec::new(),
+            stop_on_entry: false,
+            launched: None,
+            attached: true,
+            quit: false,
+        }
+    }
+
+    /// Waits for an editor to connect to `address`.
+    pub fn accept<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream.try_clone()?, stream))
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Whether the editor has the machine stopped, or has not finished
+    /// configuring it yet.
+    pub fn is_stopped(&self) -> bool {
+        self.debugger.is_paused()
+    }
+
+    /// The program the editor launched since this was last called, which
+    /// is already loaded into the machine with its settings kept. Frontends
+    /// that choose settings for each ROM can set the machine up again here.
+    pub fn take_launched(&mut self) -> Option<PathBuf> {
+        self.launched.take()
+    }
+
+    /// Whether the editor asked for the emulator to exit.
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Handles the requests that have arrived. Losing the editor detaches it
+    /// and lets the machine run.
+    pub fn poll(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        while self.attached {
+            match self.requests.try_recv() {
+                Ok(Ok(request)) => {
+                    let launched = self.launched.is_some();
+                    self.handle(cpu, &request);
+                    // let the frontend set up a launched program before anything else touches it
+                    if !launched && self.launched.is_some() {
+                        break;
+                    }
+                },
+                Ok(Err(err)) => {
+                    self.detach(cpu);
+                    return Err(err);
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.detach(cpu),
+            }
+        }
+        self.flush()
+    }
+
+    /// Runs one frame unless the editor has the machine stopped, reporting
+    /// breakpoints and faults to it. Faults are only returned once the editor
+    /// has gone.
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles: usize) -> Result<(), ExecError> {
+        if self.is_stopped() {
+            return Ok(());
+        }
+        match self.debugger.run_frame(cpu, cycles) {
+            Ok(Some(stop)) => self.stopped_at(&stop),
+            Ok(None) if cpu.is_halted() && self.attached => {
+                self.debugger.pause();
+                self.exited();
+            },
+            Ok(None) => {},
+            Err(err) if self.attached => self.fault(&err),
+            Err(err) => {
+                self.debugger.resume(cpu);
+                return Err(err);
+            },
+        }
+        // a failed write detaches the editor
+        let _ = self.flush();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for mut message in self.outbox.drain(..) {
+            message["seq"] = json!(self.seq);
+            self.seq += 1;
+            if !self.attached {
+                continue;
+            }
+            if let Err(err) = write_message(&mut self.output, &message) {
+                self.attached = false;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self, cpu: &mut CPU) {
+        self.attached = false;
+        self.debugger.clear(cpu);
+        self.debugger.resume(cpu);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.outbox.push(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+        self.event("stopped", body);
+    }
+
+    fn stopped_at(&mut self, stop: &Stop) {
+        let reason = match stop {
+            Stop::Breakpoint(_) => "breakpoint",
+            Stop::Finished => "step",
+            _ => "data breakpoint",
+        };
+        self.stopped(reason, Some(stop.to_string()));
+    }
+
+    fn fault(&mut self, err: &ExecError) {
+        self.event("output", json!({ "category": "stderr", "output": format!("CPU fault: {}\n", err) }));
+        self.stopped("exception", Some(err.to_string()));
+    }
+
+    fn exited(&mut self) {
+        self.event("exited", json!({ "exitCode": 0 }));
+        self.event("terminated", json!({}));
+    }
+
+    /// Handles one request, queueing its response and any events it causes.
+    fn handle(&mut self, cpu: &mut CPU, request: &Value) {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        // the response goes before any events the request causes
+        let position = self.outbox.len();
+        let result = match command {
+            "initialize" => {
+                self.event("initialized", json!({}));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsConditionalBreakpoints": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsTerminateRequest": true,
+                }))
+            },
+            "launch" | "attach" => self.launch(cpu, args).map(|_| Value::Null),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None);
+                } else {
+                    self.debugger.resume(cpu);
+                }
+                Ok(Value::Null)
+            },
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(cpu)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+            ] })),
+            "variables" => Ok(json!({ "variables": variables(cpu, args["variablesReference"].as_u64().unwrap_or(0)) })),
+            "evaluate" => args["expression"].as_str().unwrap_or_default().parse::<Expr>()
+                .map(|expr| {
+                    let value = expr.eval(cpu);
+                    let result = if value >= 0 {format!("{:#x} ({})", value, value)} else {value.to_string()};
+                    json!({ "result": result, "variablesReference": 0 })
+                })
+                .map_err(|err| err.to_string()),
+            "continue" => {
+                self.debugger.resume(cpu);
+                Ok(json!({ "allThreadsContinued": true }))
+            },
+            "next" => {
+                if !self.debugger.step_over(cpu) {
+                    self.step(cpu);
+                }
+                Ok(Value::Null)
+            },
+            "stepIn" => {
+                self.step(cpu);
+                Ok(Value::Null)
+            },
+            "stepOut" => match self.debugger.step_out(cpu) {
+                true => Ok(Value::Null),
+                false => Err("Not in a subroutine".to_string()),
+            },
+            "pause" => {
+                self.debugger.pause();
+                self.stopped("pause", None);
+                Ok(Value::Null)
+            },
+            "readMemory" => read_memory(cpu, args),
+            "writeMemory" => write_memory(cpu, args),
+            "disassemble" => self.disassemble(cpu, args),
+            "disconnect" => {
+                self.quit = args["terminateDebuggee"].as_bool().unwrap_or(true);
+                self.debugger.clear(cpu);
+                self.debugger.resume(cpu);
+                Ok(Value::Null)
+            },
+            "terminate" => {
+                self.quit = true;
+                self.event("terminated", json!({}));
+                Ok(Value::Null)
+            },
+            _ => Err(format!("Unsupported request {}", command)),
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {},
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.outbox.insert(position, response);
+        // send the response before letting go of the editor
+        if command == "disconnect" {
+            let _ = self.flush();
+            self.attached = false;
+        }
+    }
+
+    fn launch(&mut self, cpu: &mut CPU, args: &Value) -> Result<(), String> {
+        if let Some(program) = args["program"].as_str() {
+            let rom = fs::read(program).map_err(|err| format!("Could not read {}: {}", program, err))?;
+            cpu.reload(rom).map_err(|err| format!("Could not load {}: {}", program, err))?;
+            self.launched = Some(PathBuf::from(program));
+        }
+        if let Some(path) = args["sourceMap"].as_str() {
+            self.source_map = SourceMap::load(Path::new(path)).map_err(|err| format!("Could not load {}: {}", path, err))?;
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(())
+    }
+
+    // Replaces the breakpoints in one source file, placing each on the
+    // first line at or after it that has code.
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let path = PathBuf::from(args["source"]["path"].as_str().unwrap_or_default());
+        let mut set = Vec::new();
+        let mut replies = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let reply = match self.source_map.address_of(&path, line) {
+                Some(location) => {
+                    let breakpoint = Breakpoint { address: location.address, condition: condition(requested) };
+                    let mut reply = verify(&breakpoint);
+                    if reply["verified"] == json!(true) {
+                        set.push(breakpoint);
+                    }
+                    reply["line"] = json!(location.line);
+                    reply
+                },
+                None if self.source_map.lines().is_empty() => json!({ "verified": false, "message": "No source map; pass sourceMap when launching" }),
+                None => json!({ "verified": false, "message": "No code at or after this line" }),
+            };
+            replies.push(reply);
+        }
+        self.source_breakpoints.insert(path, set);
+        self.sync_breakpoints();
+        json!({ "breakpoints": replies })
+    }
+
+    // Replaces the breakpoints set on addresses, such as from the disassembly view.
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Value {
+        let mut set = Vec::new();
+        let mut replies = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let address = requested["instructionReference"].as_str().and_then(parse_address)
+                .and_then(|a| a.checked_add_signed(requested["offset"].as_i64().unwrap_or(0) as isize));
+            let reply = match address {
+                Some(address) => {
+                    let breakpoint = Breakpoint { address, condition: condition(requested) };
+                    let reply = verify(&breakpoint);
+                    if reply["verified"] == json!(true) {
+                        set.push(breakpoint);
+                    }
+                    reply
+                },
+                None => json!({ "verified": false, "message": "Not an address" }),
+            };
+            replies.push(reply);
+        }
+        self.instruction_breakpoints = set;
+        self.sync_breakpoints();
+        json!({ "breakpoints": replies })
+    }
+
+    // Hands the editor's breakpoints to the debugger, which keeps one per address.
+    fn sync_breakpoints(&mut self) {
+        let old: Vec<usize> = self.debugger.breakpoints().collect();
+        for address in old {
+            self.debugger.remove_breakpoint(address);
+        }
+        let all = self.source_breakpoints.values().flatten().chain(&self.instruction_breakpoints);
+        for breakpoint in all {
+            match &breakpoint.condition {
+                // conditions were checked when the breakpoint was set
+                Some(condition) => {
+                    let _ = self.debugger.add_conditional_breakpoint(breakpoint.address, condition);
+                },
+                None => self.debugger.add_breakpoint(breakpoint.address),
+            }
+        }
+    }
+
+    fn step(&mut self, cpu: &mut CPU) {
+        match self.debugger.step_instruction(cpu) {
+            Ok((_, Some(stop))) => self.stopped_at(&stop),
+            Ok((StepOutcome::Halted, None)) => self.exited(),
+            Ok(_) => self.stopped("step", None),
+            Err(err) => self.fault(&err),
+        }
+    }
+
+    // The current instruction, then each call site on the stack.
+    fn stack_trace(&self, cpu: &CPU) -> Value {
+        let mut addresses = vec![cpu.pc()];
+        addresses.extend(cpu.stack().iter().rev().map(|r| r.saturating_sub(2)));
+        let frames: Vec<Value> = addresses.iter().enumerate().map(|(id, address)| {
+            let mut frame = json!({
+                "id": id,
+                "name": format!("{:#06x}", address),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{:#06x}", address),
+            });
+            if let Some(location) = self.source_map.location(*address) {
+                frame["source"] = source(&location.path);
+                frame["line"] = json!(location.line);
+                frame["column"] = json!(1);
+            }
+            frame
+        }).collect();
+        json!({ "stackFrames": frames, "totalFrames": addresses.len() })
+    }
+
+    fn disassemble(&self, cpu: &CPU, args: &Value) -> Result<Value, String> {
+        let base = args["memoryReference"].as_str().and_then(parse_address).unwrap_or(0) as i64;
+        let start = args["instructionOffset"].as_i64().unwrap_or(0).checked_mul(2)
+            .and_then(|offset| offset.checked_add(args["offset"].as_i64().unwrap_or(0)))
+            .and_then(|offset| base.checked_add(offset));
+        let Some(mut address) = start else {
+            return Err("Offset is out of range".to_string());
+        };
+        // more than fills memory is an editor asking for everything
+        let count = args["instructionCount"].as_u64().unwrap_or(0).min(cpu.memory().len() as u64 / 2) as usize;
+        let mut instructions = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (text, bytes, len) = match usize::try_from(address).ok().filter(|a| *a < cpu.memory().len()) {
+                Some(a) => match cpu.decode_at(a) {
+                    Some((instruction, len)) => (instruction.to_string(), hex(&cpu.memory()[a..a + len]), len),
+                    None => ("??".to_string(), hex(&cpu.memory()[a..(a + 2).min(cpu.memory().len())]), 2),
+                },
+                None => ("??".to_string(), String::new(), 2),
+            };
+            let mut instruction = json!({
+                "address": format!("{:#06x}", address),
+                "instructionBytes": bytes,
+                "instruction": text,
+            });
+            if let Some(location) = usize::try_from(address).ok().and_then(|a| self.source_map.location(a)) {
+                instruction["location"] = source(&location.path);
+                instruction["line"] = json!(location.line);
+            }
+            instructions.push(instruction);
+            address = address.saturating_add(len as i64);
+        }
+        Ok(json!({ "instructions": instructions }))
+    }
+}
+
+fn condition(breakpoint: &Value) -> Option<String> {
+    breakpoint["condition"].as_str().map(str::trim).filter(|c| !c.is_empty()).map(String::from)
+}
+
+// The reply for a breakpoint, which is rejected if its condition does not parse.
+fn verify(breakpoint: &Breakpoint) -> Value {
+    let reference = format!("{:#06x}", breakpoint.address);
+    match breakpoint.condition.as_deref().map(str::parse::<Expr>) {
+        Some(Err(err)) => json!({ "verified": false, "message": err.to_string(), "instructionReference": reference }),
+        _ => json!({ "verified": true, "instructionReference": reference }),
+    }
+}
+
+fn source(path: &Path) -> Value {
+    json!({
+        "name": path.file_name().map(|n| n.to_string_lossy()),
+        "path": path.to_string_lossy(),
+    })
+}
+
+fn variables(cpu: &CPU, reference: u64) -> Vec<Value> {
+    let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+    match reference {
+        REGISTERS => {
+            let mut registers: Vec<Value> = (0..16)
+                .map(|r| variable(format!("V{:X}", r), format!("{:#04x}", cpu.register(r))))
+                .collect();
+            let mut index = variable("I".to_string(), format!("{:#06x}", cpu.index()));
+            index["memoryReference"] = json!(format!("{:#06x}", cpu.index()));
+            registers.push(index);
+            let mut pc = variable("PC".to_string(), format!("{:#06x}", cpu.pc()));
+            pc["memoryReference"] = json!(format!("{:#06x}", cpu.pc()));
+            registers.push(pc);
+            registers.push(variable("SP".to_string(), cpu.stack().len().to_string()));
+            registers
+        },
+        TIMERS => vec![
+            variable("DT".to_string(), cpu.delay_timer.to_string()),
+            variable("ST".to_string(), cpu.sound_timer.to_string()),
+        ],
+        STACK => cpu.stack().iter().enumerate().map(|(n, r)| variable(n.to_string(), format!("{:#06x}", r))).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_memory(cpu: &CPU, args: &Value) -> Result<Value, String> {
+    let start = memory_address(args)?;
+    let count = args["count"].as_u64().unwrap_or(0) as usize;
+    let memory = cpu.memory();
+    let bytes = memory.get(start.min(memory.len())..start.saturating_add(count).min(memory.len())).unwrap_or_default();
+    Ok(json!({
+        "address": format!("{:#06x}", start),
+        "data": BASE64.encode(bytes),
+        "unreadableBytes": count - bytes.len(),
+    }))
+}
+
+fn write_memory(cpu: &mut CPU, args: &Value) -> Result<Value, String> {
+    let start = memory_address(args)?;
+    let data = BASE64.decode(args["data"].as_str().unwrap_or_default()).map_err(|err| err.to_string())?;
+    match cpu.write_memory(start, &data) {
+        true => Ok(json!({ "bytesWritten": data.len() })),
+        false => Err(format!("{:#06x} is outside memory", start)),
+    }
+}
+
+fn memory_address(args: &Value) -> Result<usize, String> {
+    args["memoryReference"].as_str()
+        .and_then(parse_address)
+        .and_then(|a| a.checked_add_signed(args["offset"].as_i64().unwrap_or(0) as isize))
+        .ok_or_else(|| "Not an address".to_string())
+}
+
+fn parse_address(text: &str) -> Option<usize> {
+    let text = text.trim();
+    usize::from_str_radix(text.strip_prefix("0x").unwrap_or(text), 16).ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reads one message: headers, a blank line, then a JSON body of the
+/// `Content-Length` given. Returns `None` at the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("message of {} bytes is too long", length)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn write_message<W: Write + ?Sized>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    // 200: V0 = 5, 202: call 208, 204: V1 = 6, 206: jump 206, 208: V2 = 7, 20a: return
+    fn machine() -> CPU {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(vec![0x60, 0x05, 0x22, 0x08, 0x61, 0x06, 0x12, 0x06, 0x62, 0x07, 0x00, 0xEE]).unwrap();
+        cpu
+    }
+
+    fn server() -> DapServer {
+        DapServer::new(io::empty(), io::sink())
+    }
+
+    // Sends one request, returning its response and the events after it.
+    fn request(server: &mut DapServer, cpu: &mut CPU, command: &str, arguments: Value) -> (Value, Vec<Value>) {
+        server.handle(cpu, &json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments }));
+        let mut messages = std::mem::take(&mut server.outbox).into_iter();
+        let response = messages.next().unwrap();
+        assert_eq!(response["command"], json!(command));
+        (response, messages.collect())
+    }
+
+    // Collects what the server writes, for requests whose events are sent by run_frame.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn stop_reasons(&self) -> Vec<Value> {
+            let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+            let mut input = Cursor::new(bytes);
+            let mut reasons = Vec::new();
+            while let Some(message) = read_message(&mut input).unwrap() {
+                reasons.push(message["body"]["reason"].clone());
+            }
+            reasons
+        }
+    }
+
+    #[test]
+    fn frames_messages() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({ "seq": 1 })).unwrap();
+        write_message(&mut buffer, &json!({ "seq": 2 })).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 9\r\n\r\n{\"seq\":1}"));
+        let mut input = Cursor::new(buffer);
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 2 })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+
+        let mut huge = Cursor::new(format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX));
+        assert_eq!(read_message(&mut huge).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn launches_into_the_configured_machine() {
+        let mut cpu = machine();
+        cpu.set_engine(crate::Engine::Blocks);
+        let quirks = Quirks { jump_uses_vx: true, ..Quirks::vip() };
+        cpu.set_quirks(quirks);
+        let path = std::env::temp_dir().join(format!("rust_chip8-{}.ch8", std::process::id()));
+        fs::write(&path, [0x6A, 0x42, 0x12, 0x02]).unwrap();
+        let mut server = server();
+
+        let (response, _) = request(&mut server, &mut cpu, "launch", json!({ "program": "/nonexistent.ch8" }));
+        assert_eq!(response["success"], json!(false));
+        assert_eq!((server.take_launched(), cpu.memory()[0x200]), (None, 0x60));
+
+        let (response, _) = request(&mut server, &mut cpu, "launch", json!({ "program": path }));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(response["success"], json!(true));
+        assert_eq!(server.take_launched(), Some(path));
+        assert_eq!(server.take_launched(), None);
+        assert_eq!((cpu.engine(), cpu.quirks(), cpu.pc()), (crate::Engine::Blocks, quirks, 0x200));
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.register(0xA), 0x42);
+    }
+
+    #[test]
+    fn configures_then_runs_to_a_breakpoint() {
+        let mut cpu = machine();
+        let output = Output::default();
+        let mut server = DapServer::new(io::empty(), output.clone());
+        let (response, events) = request(&mut server, &mut cpu, "initialize", json!({}));
+        assert_eq!(response["body"]["supportsInstructionBreakpoints"], json!(true));
+        assert_eq!(events[0]["event"], json!("initialized"));
+
+        let (response, _) = request(&mut server, &mut cpu, "setInstructionBreakpoints", json!({
+            "breakpoints": [{ "instructionReference": "0x0208" }, { "instructionReference": "0x204", "condition": "V0 ==" }],
+        }));
+        let breakpoints = &response["body"]["breakpoints"];
+        assert_eq!((&breakpoints[0]["verified"], &breakpoints[1]["verified"]), (&json!(true), &json!(false)));
+        assert!(server.is_stopped());
+
+        request(&mut server, &mut cpu, "configurationDone", json!({}));
+        server.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.pc(), 0x208);
+        assert_eq!(output.stop_reasons(), [json!("breakpoint")]);
+
+        let (response, _) = request(&mut server, &mut cpu, "stackTrace", json!({ "threadId": 1 }));
+        let frames = &response["body"]["stackFrames"];
+        assert_eq!(frames[0]["instructionPointerReference"], json!("0x0208"));
+        assert_eq!(frames[1]["instructionPointerReference"], json!("0x0202"));
+
+        let (_, events) = request(&mut server, &mut cpu, "stepIn", json!({ "threadId": 1 }));
+        assert_eq!((cpu.register(2), events[0]["body"]["reason"].clone()), (7, json!("step")));
+        request(&mut server, &mut cpu, "stepOut", json!({ "threadId": 1 }));
+        server.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(output.stop_reasons(), [json!("step")]);
+    }
+
+    #[test]
+    fn source_breakpoints_use_the_source_map() {
+        let mut cpu = machine();
+        let mut server = server();
+        server.source_map = SourceMap::parse("200 game.8o:1\n202 game.8o:2\n204 game.8o:4\n", Path::new("/src")).unwrap();
+        let (response, _) = request(&mut server, &mut cpu, "setBreakpoints", json!({
+            "source": { "path": "/src/game.8o" },
+            "breakpoints": [{ "line": 3 }, { "line": 9 }],
+        }));
+        let breakpoints = &response["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["line"], json!(4));
+        assert_eq!(breakpoints[1]["verified"], json!(false));
+        assert_eq!(server.debugger.breakpoints().collect::<Vec<_>>(), [0x204]);
+
+        let (response, _) = request(&mut server, &mut cpu, "stackTrace", json!({}));
+        assert_eq!(response["body"]["stackFrames"][0]["line"], json!(1));
+        assert_eq!(response["body"]["stackFrames"][0]["source"]["name"], json!("game.8o"));
+    }
+
+    #[test]
+    fn variables_and_memory() {
+        let mut cpu = machine();
+        let mut server = server();
+        cpu.set_register(0xA, 0x42);
+        let (response, _) = request(&mut server, &mut cpu, "variables", json!({ "variablesReference": REGISTERS }));
+        assert_eq!(response["body"]["variables"][10]["value"], json!("0x42"));
+        assert_eq!(response["body"]["variables"][17]["name"], json!("PC"));
+
+        let (response, _) = request(&mut server, &mut cpu, "readMemory", json!({ "memoryReference": "0x200", "count": 2 }));
+        assert_eq!(response["body"]["data"], json!(BASE64.encode([0x60, 0x05])));
+        let (response, _) = request(&mut server, &mut cpu, "writeMemory", json!({ "memoryReference": "0x300", "data": BASE64.encode([1, 2]) }));
+        assert_eq!(response["body"]["bytesWritten"], json!(2));
+        assert_eq!(&cpu.memory()[0x300..0x302], &[1, 2]);
+
+        let (response, _) = request(&mut server, &mut cpu, "disassemble", json!({ "memoryReference": "0x200", "instructionCount": 2 }));
+        assert_eq!(response["body"]["instructions"][1]["instructionBytes"], json!("2208"));
+        let (response, _) = request(&mut server, &mut cpu, "disassemble", json!({ "memoryReference": "0x200", "instructionCount": u64::MAX }));
+        assert_eq!(response["body"]["instructions"].as_array().unwrap().len(), cpu.memory().len() / 2);
+        let (response, _) = request(&mut server, &mut cpu, "disassemble", json!({ "memoryReference": "0x200", "instructionOffset": i64::MAX, "instructionCount": 1 }));
+        assert_eq!(response["success"], json!(false));
+        let (response, _) = request(&mut server, &mut cpu, "disassemble", json!({ "memoryReference": "0x200", "offset": i64::MAX, "instructionCount": 2 }));
+        assert_eq!(response["success"], json!(false));
+        let (response, _) = request(&mut server, &mut cpu, "disassemble", json!({ "memoryReference": "0x0", "offset": i64::MAX - 1, "instructionCount": 2 }));
+        assert_eq!(response["body"]["instructions"][1]["instruction"], json!("??"));
+        let (response, _) = request(&mut server, &mut cpu, "evaluate", json!({ "expression": "VA + 1" }));
+        assert_eq!(response["body"]["result"], json!("0x43 (67)"));
+        let (response, _) = request(&mut server, &mut cpu, "bogus", json!({}));
+        assert_eq!(response["success"], json!(false));
+    }
+}
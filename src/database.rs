@@ -0,0 +1,251 @@
+//! Per-game settings looked up by the SHA-1 of the ROM, read from JSON in
+//! the format of the community CHIP-8 database's `programs.json`: a list of
+//! programs, each with its ROMs keyed by hash.
+//!
+//! ```text
+//! [{"title": "Pong", "roms": {"<sha1>": {
+//!     "platforms": ["originalChip8"], "tickrate": 15,
+//!     "quirkyPlatforms": {"originalChip8": {"vblank": false}},
+//!     "colors": {"pixels": ["#000000", "#ffffff"]}, "keys": {"up": 1, "down": 4}}}}]
+//! ```
+//!
+//! A small database is bundled with the crate. Settings from another file,
+//! such as a newer copy of the community one, are layered over it with
+//! [`RomDatabase::merge`].
+
+use crate::palette::{Color, Palette};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rom::RomHash;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BUNDLED: &str = include_str!("../data/programs.json");
+
+/// What the database knows about one ROM. Settings it does not give are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    /// The first platform the ROM runs on that this interpreter emulates.
+    pub platform: Option<Platform>,
+    /// The quirks of that platform, with any the ROM needs changed.
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<usize>,
+    pub palette: Option<Palette>,
+    /// CHIP-8 keys for the database's named buttons: `up`, `down`, `left`,
+    /// `right`, `a` and `b`.
+    pub keys: BTreeMap<String, u8>,
+}
+
+/// ROM settings by hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomDatabase {
+    roms: HashMap<RomHash, RomInfo>,
+}
+
+/// Why a database could not be loaded.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The file is not a list of programs with their ROMs.
+    Json(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Json(err) => write!(f, "not a ROM database: {}", err),
+            DatabaseError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<io::Error> for DatabaseError {
+    fn from(err: io::Error) -> Self {
+        DatabaseError::Io(err)
+    }
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The database bundled with the crate.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED).expect("the bundled ROM database is valid")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, DatabaseError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a `programs.json` list. ROMs whose hash is not valid are skipped.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Value = serde_json::from_str(json).map_err(|err| DatabaseError::Json(err.to_string()))?;
+        let programs = json.as_array().ok_or_else(|| DatabaseError::Json("expected a list of programs".to_string()))?;
+        let mut database = RomDatabase::new();
+        for program in programs {
+            let title = program["title"].as_str().unwrap_or("").to_string();
+            let Some(roms) = program["roms"].as_object() else {
+                continue;
+            };
+            for (hash, rom) in roms {
+                if let Some(hash) = RomHash::from_hex(hash) {
+                    database.roms.insert(hash, rom_info(title.clone(), rom));
+                }
+            }
+        }
+        Ok(database)
+    }
+
+    /// Adds the ROMs in `other`, replacing any already known.
+    pub fn merge(&mut self, other: RomDatabase) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn get(&self, hash: RomHash) -> Option<&RomInfo> {
+        self.roms.get(&hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+fn rom_info(title: String, rom: &Value) -> RomInfo {
+    let platforms = rom["platforms"].as_array().map_or(&[][..], Vec::as_slice);
+    let (id, platform, mut quirks) = match platforms.iter().filter_map(Value::as_str).find_map(|id| platform(id).map(|p| (id, p))) {
+        Some((id, (platform, quirks))) => (id, Some(platform), Some(quirks)),
+        None => ("", None, None),
+    };
+    if let Some(quirks) = quirks.as_mut() {
+        apply_quirks(quirks, &rom["quirkyPlatforms"][id]);
+    }
+
+    let colors: Vec<Color> = rom["colors"]["pixels"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .filter_map(|c| c.as_str()?.parse().ok())
+        .collect();
+    let palette = (!colors.is_empty()).then(|| {
+        let mut palette = Palette::default();
+        let slots = [&mut palette.background, &mut palette.foreground, &mut palette.plane2, &mut palette.blend];
+        for (slot, color) in slots.into_iter().zip(colors) {
+            *slot = color;
+        }
+        palette
+    });
+
+    let keys = rom["keys"]
+        .as_object()
+        .map(|keys| {
+            keys.iter()
+                .filter_map(|(name, key)| Some((name.clone(), key.as_u64().filter(|k| *k < 16)? as u8)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    RomInfo {
+        title,
+        platform,
+        quirks,
+        cycles_per_frame: rom["tickrate"].as_u64().map(|t| t as usize),
+        palette,
+        keys,
+    }
+}
+
+// The community database's platform ids, with the quirks each implies.
+fn platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" | "chip8x" => Some((Platform::Chip8, Quirks::vip())),
+        "modernChip8" => {
+            Some((Platform::Chip8, Quirks { logic_resets_vf: false, display_wait: false, ..Quirks::vip() }))
+        },
+        "chip48" | "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::schip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
+
+// Quirk names are the database's, which are set when a platform departs from the VIP.
+fn apply_quirks(quirks: &mut Quirks, overrides: &Value) {
+    let flag = |name: &str| overrides[name].as_bool();
+    if let Some(on) = flag("shift") {
+        quirks.shift_uses_vy = !on;
+    }
+    if let Some(on) = flag("memoryLeaveIUnchanged") {
+        quirks.memory_increments_i = !on;
+    }
+    if let Some(on) = flag("wrap") {
+        quirks.wrap_sprites = on;
+    }
+    if let Some(on) = flag("jump") {
+        quirks.jump_uses_vx = on;
+    }
+    if let Some(on) = flag("vblank") {
+        quirks.display_wait = on;
+    }
+    if let Some(on) = flag("logic") {
+        quirks.logic_resets_vf = on;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn reads_rom_settings() {
+        let json = format!(
+            r##"[{{"title": "Game", "roms": {{"{}": {{"platforms": ["megachip8", "superchip"], "tickrate": 30,
+                "quirkyPlatforms": {{"superchip": {{"vblank": true}}, "xochip": {{"wrap": true}}}},
+                "colors": {{"pixels": ["#112233", "#445566"]}}, "keys": {{"up": 5, "down": 8, "bad": 16}}}}}}}}]"##,
+            HASH
+        );
+        let database = RomDatabase::parse(&json).unwrap();
+        let info = database.get(RomHash::from_hex(HASH).unwrap()).unwrap();
+        assert_eq!(info.title, "Game");
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.quirks, Some(Quirks { display_wait: true, ..Quirks::schip() }));
+        assert_eq!(info.cycles_per_frame, Some(30));
+        let palette = info.palette.unwrap();
+        assert_eq!((palette.background, palette.foreground), (Color(0x11, 0x22, 0x33), Color(0x44, 0x55, 0x66)));
+        assert_eq!(palette.plane2, Palette::default().plane2);
+        assert_eq!(info.keys.iter().collect::<Vec<_>>(), [(&"down".to_string(), &8), (&"up".to_string(), &5)]);
+    }
+
+    #[test]
+    fn later_databases_take_precedence() {
+        let mut database = RomDatabase::bundled();
+        let hires = RomHash::of(include_bytes!("../tests/roms/hires.sc8"));
+        assert_eq!(database.get(hires).and_then(|info| info.platform), Some(Platform::SuperChip));
+
+        let json = format!(r#"[{{"title": "Mine", "roms": {{"{}": {{"platforms": ["xochip"]}}}}}}]"#, hires);
+        let known = database.len();
+        database.merge(RomDatabase::parse(&json).unwrap());
+        assert_eq!(database.len(), known);
+        assert_eq!(database.get(hires).map(|info| info.title.as_str()), Some("Mine"));
+        assert_eq!(database.get(hires).and_then(|info| info.platform), Some(Platform::XoChip));
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        assert!(matches!(RomDatabase::parse("{}"), Err(DatabaseError::Json(_))));
+        assert!(matches!(RomDatabase::parse("[{"), Err(DatabaseError::Json(_))));
+    }
+}
@@ -0,0 +1,827 @@
+//! A command-line debugger: breakpoints, stepping and state inspection driven
+//! by text commands, for use alongside any frontend.
+//!
+//! The frontend feeds command lines to [`Debugger::execute`] and, while the
+//! debugger is not paused, runs frames through [`Debugger::run_frame`] instead
+//! of [`CPU::run_frame`].
+
+mod expr;
+
+pub use self::expr::{Expr, Op, ParseError};
+
+use self::expr::parse_register;
+use crate::core::{AccessKind, MemoryAccess, CPU};
+use crate::error::{ExecError, StepOutcome};
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "(c8db) ";
+// instructions shown by `list`, and how many bytes before pc it starts
+const LIST_LENGTH: usize = 10;
+const LIST_BEFORE: usize = 8;
+const EXAMINE_LENGTH: usize = 64;
+
+const HELP: &str = "\
+break [ADDR]     set a breakpoint, or list them (alias b)
+break ADDR if EXPR
+                 stop at ADDR only when EXPR is true
+delete [ADDR]    remove a breakpoint, or all of them (alias d)
+watch ADDR [LEN] stop when memory is written; rwatch for reads, awatch for both
+watch VX         stop when a register changes
+when EXPR        stop when EXPR becomes true
+watch            list watchpoints
+unwatch [N]      remove watchpoint N, or all of them
+step [N]         execute N instructions (alias s)
+next             step, running over a subroutine call (alias n)
+finish           run until the current subroutine returns (alias out)
+continue         run until a breakpoint (alias c)
+pause            stop running
+regs             show registers, I, the stack and timers (alias r)
+x ADDR [LEN]     examine LEN bytes of memory
+list [ADDR]      disassemble around ADDR or pc (alias l)
+quit             exit (alias q)
+Addresses are hex. An empty line repeats the last command.
+Expressions use V0-VF, I, PC, SP, DT, ST, [ADDR] for a byte of memory,
+numbers (0x for hex), + - & ! == != < <= > >= && ||, for example
+V3 == 0x10 && I > 0x300";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Running,
+    // run until the stack is at most `depth` deep and, if given, pc is `address`
+    Until { address: Option<usize>, depth: usize },
+}
+
+/// Which memory accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Reads, including instruction fetches and sprite data.
+    Read,
+    Write,
+    /// Reads and writes.
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            WatchKind::Read => kind != AccessKind::Write,
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Watch {
+    Memory { address: usize, len: usize, kind: WatchKind },
+    Register(u8),
+    // `last` is the value after the previous instruction, so we stop on the rising edge
+    Condition { text: String, expr: Expr, last: bool },
+}
+
+#[derive(Debug, Clone)]
+struct Watchpoint {
+    id: usize,
+    watch: Watch,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.watch {
+            Watch::Memory { address, len, kind } => {
+                let kind = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Access => "access",
+                };
+                write!(f, "#{} {} {:#06x}..{:#06x}", self.id, kind, address, address.saturating_add(*len))
+            },
+            Watch::Register(r) => write!(f, "#{} V{:X}", self.id, r),
+            Watch::Condition { text, .. } => write!(f, "#{} when {}", self.id, text),
+        }
+    }
+}
+
+/// Why [`Debugger::run_frame`] paused the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// pc reached a breakpoint; the instruction there has not run yet.
+    Breakpoint(usize),
+    /// A `next` or `finish` command completed.
+    Finished,
+    /// The instruction at `pc` touched memory covered by watchpoint `id`.
+    Memory { id: usize, pc: usize, access: MemoryAccess },
+    /// The instruction at `pc` changed a watched register.
+    Register { id: usize, pc: usize, register: u8, old: u8, new: u8 },
+    /// A watched expression became true after the instruction at `pc`.
+    Condition { id: usize, pc: usize, text: String },
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(address) => write!(f, "Breakpoint at {:#06x}", address),
+            Stop::Finished => write!(f, "Stopped"),
+            Stop::Memory { id, pc, access } => {
+                let kind = match access.kind {
+                    AccessKind::Fetch => "fetch",
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                write!(f, "Watchpoint {}: {} of {} bytes at {:#06x} by {:#06x}", id, kind, access.len, access.address, pc)
+            },
+            Stop::Register { id, pc, register, old, new } => {
+                write!(f, "Watchpoint {}: V{:X} changed from {:#04x} to {:#04x} by {:#06x}", id, register, old, new, pc)
+            },
+            Stop::Condition { id, pc, text } => write!(f, "Watchpoint {}: {} after {:#06x}", id, text, pc),
+        }
+    }
+}
+
+/// A breakpoint's condition, kept with the text it was parsed from.
+#[derive(Debug, Clone)]
+struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+/// Breakpoints, watchpoints and run state for one machine.
+pub struct Debugger {
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    watches: Vec<Watchpoint>,
+    next_watch: usize,
+    mode: Mode,
+    // breakpoint we are resuming from, which must not stop us again
+    resume_from: Option<usize>,
+    last_command: String,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger with no breakpoints, paused.
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watches: Vec::new(),
+            next_watch: 1,
+            mode: Mode::Paused,
+            resume_from: None,
+            last_command: String::new(),
+        }
+    }
+
+    /// Whether the machine should be left alone until the next command.
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address, None);
+    }
+
+    /// Adds a breakpoint that only stops when `condition` is true.
+    pub fn add_conditional_breakpoint(&mut self, address: usize, condition: &str) -> Result<(), ParseError> {
+        let expr = condition.parse()?;
+        self.breakpoints.insert(address, Some(Condition { text: condition.to_string(), expr }));
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    /// Stops when an instruction touches any of the `len` bytes from
+    /// `address`. Returns the watchpoint's number.
+    pub fn watch_memory(&mut self, cpu: &mut CPU, address: usize, len: usize, kind: WatchKind) -> usize {
+        cpu.set_access_tracing(true);
+        self.add_watch(Watch::Memory { address, len: len.max(1), kind })
+    }
+
+    /// Stops when an instruction changes register `VX`.
+    pub fn watch_register(&mut self, x: u8) -> usize {
+        self.add_watch(Watch::Register(x & 0xF))
+    }
+
+    /// Stops when `condition` goes from false to true.
+    pub fn watch_condition(&mut self, cpu: &CPU, condition: &str) -> Result<usize, ParseError> {
+        let expr: Expr = condition.parse()?;
+        let last = expr.is_true(cpu);
+        Ok(self.add_watch(Watch::Condition { text: condition.to_string(), expr, last }))
+    }
+
+    /// Removes the memory watchpoint covering exactly this range and kind.
+    pub fn remove_memory_watch(&mut self, cpu: &mut CPU, address: usize, len: usize, kind: WatchKind) -> bool {
+        let id = self.watches.iter().find(|w| match w.watch {
+            Watch::Memory { address: a, len: l, kind: k } => (a, l, k) == (address, len.max(1), kind),
+            _ => false,
+        }).map(|w| w.id);
+        id.is_some_and(|id| self.remove_watch(cpu, id))
+    }
+
+    /// Removes watchpoint `id`, returning whether it existed.
+    pub fn remove_watch(&mut self, cpu: &mut CPU, id: usize) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        self.update_tracing(cpu);
+        self.watches.len() < before
+    }
+
+    fn add_watch(&mut self, watch: Watch) -> usize {
+        let id = self.next_watch;
+        self.next_watch += 1;
+        self.watches.push(Watchpoint { id, watch });
+        id
+    }
+
+    // Access tracing costs a little on every access, so only keep it on for memory watches.
+    fn update_tracing(&self, cpu: &mut CPU) {
+        cpu.set_access_tracing(self.watches.iter().any(|w| matches!(w.watch, Watch::Memory { .. })));
+    }
+
+    fn breakpoint_hit(&self, cpu: &CPU, pc: usize) -> bool {
+        match self.breakpoints.get(&pc) {
+            Some(Some(condition)) => condition.expr.is_true(cpu),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // Registers before an instruction, when a watchpoint needs them.
+    fn watch_state(&self, cpu: &CPU) -> Option<[u8; 16]> {
+        if self.watches.is_empty() {
+            return None;
+        }
+        let mut registers = [0; 16];
+        for (r, value) in registers.iter_mut().enumerate() {
+            *value = cpu.register(r);
+        }
+        Some(registers)
+    }
+
+    // Checks every watchpoint after the instruction at `pc` ran, returning the first that fired.
+    fn check_watches(&mut self, cpu: &CPU, pc: usize, before: &[u8; 16]) -> Option<Stop> {
+        let mut stop = None;
+        for watchpoint in self.watches.iter_mut() {
+            let id = watchpoint.id;
+            let fired = match &mut watchpoint.watch {
+                Watch::Memory { address, len, kind } => cpu.accesses()
+                    .iter()
+                    .find(|a| kind.matches(a.kind) && a.address < address.saturating_add(*len) && *address < a.address + a.len)
+                    .map(|access| Stop::Memory { id, pc, access: *access }),
+                Watch::Register(r) => {
+                    let (old, new) = (before[*r as usize], cpu.register(*r as usize));
+                    (old != new).then_some(Stop::Register { id, pc, register: *r, old, new })
+                },
+                Watch::Condition { text, expr, last } => {
+                    let now = expr.is_true(cpu);
+                    let rose = now && !*last;
+                    *last = now;
+                    rose.then(|| Stop::Condition { id, pc, text: text.clone() })
+                },
+            };
+            if stop.is_none() {
+                stop = fired;
+            }
+        }
+        stop
+    }
+
+    /// Lets [`Debugger::run_frame`] run the machine until something stops it.
+    pub fn resume(&mut self, cpu: &CPU) {
+        self.resume_until(cpu, Mode::Running);
+    }
+
+    /// If the next instruction is a subroutine call, resumes until it returns
+    /// and returns `true`. Otherwise does nothing, so the caller can step.
+    pub fn step_over(&mut self, cpu: &CPU) -> bool {
+        match cpu.decode_at(cpu.pc()) {
+            Some((Instruction::Call(_), len)) => {
+                self.resume_until(cpu, Mode::Until { address: Some(cpu.pc() + len), depth: cpu.stack().len() });
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Resumes until the current subroutine returns. Returns `false` outside
+    /// a subroutine.
+    pub fn step_out(&mut self, cpu: &CPU) -> bool {
+        match cpu.stack().len() {
+            0 => false,
+            depth => {
+                self.resume_until(cpu, Mode::Until { address: None, depth: depth - 1 });
+                true
+            },
+        }
+    }
+
+    /// Removes every breakpoint and watchpoint.
+    pub fn clear(&mut self, cpu: &mut CPU) {
+        self.breakpoints.clear();
+        self.watches.clear();
+        self.update_tracing(cpu);
+    }
+
+    /// Executes one instruction, waiting out a display wait, and checks the
+    /// watchpoints. Breakpoints are ignored and the debugger stays paused.
+    pub fn step_instruction(&mut self, cpu: &mut CPU) -> Result<(StepOutcome, Option<Stop>), ExecError> {
+        self.pause();
+        loop {
+            let pc = cpu.pc();
+            let before = self.watch_state(cpu);
+            match cpu.step()? {
+                StepOutcome::WaitingForVBlank => cpu.tick_timers(),
+                StepOutcome::Executed => {
+                    let stop = before.and_then(|before| self.check_watches(cpu, pc, &before));
+                    return Ok((StepOutcome::Executed, stop));
+                },
+                outcome => return Ok((outcome, None)),
+            }
+        }
+    }
+
+    /// Runs one frame, like [`CPU::run_frame`], unless the debugger is paused.
+    ///
+    /// Stops before an instruction at a breakpoint whose condition holds,
+    /// after an instruction that trips a watchpoint, or once a `next` or
+    /// `finish` completes; the timers are not ticked for a frame that stops
+    /// early. A fault pauses the debugger and is returned.
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles: usize) -> Result<Option<Stop>, ExecError> {
+        if self.is_paused() {
+            return Ok(None);
+        }
+        for _ in 0..cycles {
+            let pc = cpu.pc();
+            if self.resume_from != Some(pc) {
+                self.resume_from = None;
+                if self.breakpoint_hit(cpu, pc) {
+                    self.mode = Mode::Paused;
+                    return Ok(Some(Stop::Breakpoint(pc)));
+                }
+            }
+            let before = self.watch_state(cpu);
+            match cpu.step() {
+                Ok(StepOutcome::WaitingForVBlank) | Ok(StepOutcome::Halted) => break,
+                Ok(_) => {},
+                Err(err) => {
+                    self.mode = Mode::Paused;
+                    return Err(err);
+                },
+            }
+            if let Some(stop) = before.and_then(|before| self.check_watches(cpu, pc, &before)) {
+                self.mode = Mode::Paused;
+                return Ok(Some(stop));
+            }
+            if let Mode::Until { address, depth } = self.mode {
+                if cpu.stack().len() <= depth && address.is_none_or(|a| a == cpu.pc()) {
+                    self.mode = Mode::Paused;
+                    return Ok(Some(Stop::Finished));
+                }
+            }
+        }
+        cpu.tick_timers();
+        Ok(None)
+    }
+
+    /// Prints why the machine stopped and where it is.
+    pub fn report<W: Write>(&self, cpu: &CPU, stop: &Stop, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{}", stop)?;
+        self.list(cpu, cpu.pc(), 1, out)
+    }
+
+    /// Prints the prompt if the debugger is waiting for a command.
+    pub fn prompt<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.is_paused() {
+            write!(out, "{}", PROMPT)?;
+        }
+        out.flush()
+    }
+
+    /// Reads and executes commands from `input` until the machine is resumed.
+    /// Returns `false` when asked to quit or at the end of the input.
+    pub fn interact<R: BufRead, W: Write>(&mut self, cpu: &mut CPU, input: &mut R, out: &mut W) -> io::Result<bool> {
+        while self.is_paused() {
+            self.prompt(out)?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || !self.execute(cpu, &line, out)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Executes one command line. Returns `false` if it was `quit`.
+    pub fn execute<W: Write>(&mut self, cpu: &mut CPU, line: &str, out: &mut W) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = words.collect();
+        self.last_command = line.clone();
+
+        match command {
+            "help" | "h" | "?" => {
+                writeln!(out, "{}", HELP)?;
+            },
+            "break" | "b" => match args.first() {
+                None => {
+                    if self.breakpoints.is_empty() {
+                        writeln!(out, "No breakpoints")?;
+                    }
+                    for (address, condition) in &self.breakpoints {
+                        match condition {
+                            Some(condition) => writeln!(out, "{:#06x} if {}", address, condition.text)?,
+                            None => writeln!(out, "{:#06x}", address)?,
+                        }
+                    }
+                },
+                Some(arg) => match (parse_address(arg), args.get(1)) {
+                    (Some(address), None) => {
+                        self.add_breakpoint(address);
+                        writeln!(out, "Breakpoint set at {:#06x}", address)?;
+                    },
+                    (Some(address), Some(&"if")) if args.len() > 2 => {
+                        match self.add_conditional_breakpoint(address, &args[2..].join(" ")) {
+                            Ok(()) => writeln!(out, "Breakpoint set at {:#06x} if {}", address, args[2..].join(" "))?,
+                            Err(err) => writeln!(out, "Bad condition: {}", err)?,
+                        }
+                    },
+                    (Some(_), Some(_)) => writeln!(out, "Usage: break ADDR [if EXPR]")?,
+                    (None, _) => writeln!(out, "Bad address: {}", arg)?,
+                },
+            },
+            "watch" | "rwatch" | "awatch" => match (command, args.first()) {
+                ("watch", None) => {
+                    if self.watches.is_empty() {
+                        writeln!(out, "No watchpoints")?;
+                    }
+                    for watchpoint in &self.watches {
+                        writeln!(out, "{}", watchpoint)?;
+                    }
+                },
+                ("watch", Some(arg)) if parse_register(arg).is_some() => {
+                    self.watch_register(parse_register(arg).unwrap_or_default());
+                    self.print_last_watch(out)?;
+                },
+                (_, Some(arg)) => {
+                    let kind = match command {
+                        "rwatch" => WatchKind::Read,
+                        "awatch" => WatchKind::Access,
+                        _ => WatchKind::Write,
+                    };
+                    let len = args.get(1).map_or(Some(1), |len| len.parse().ok());
+                    match (parse_address(arg), len) {
+                        (Some(address), Some(len)) => {
+                            self.watch_memory(cpu, address, len, kind);
+                            self.print_last_watch(out)?;
+                        },
+                        _ => writeln!(out, "Usage: {} ADDR [LEN]", command)?,
+                    }
+                },
+                (_, None) => writeln!(out, "Usage: {} ADDR [LEN]", command)?,
+            },
+            "when" => match self.watch_condition(cpu, &args.join(" ")) {
+                Ok(_) => self.print_last_watch(out)?,
+                Err(err) => writeln!(out, "Bad condition: {}", err)?,
+            },
+            "unwatch" => match args.first() {
+                None => {
+                    self.watches.clear();
+                    self.update_tracing(cpu);
+                    writeln!(out, "Deleted all watchpoints")?;
+                },
+                Some(arg) => match arg.trim_start_matches('#').parse() {
+                    Ok(id) if self.remove_watch(cpu, id) => writeln!(out, "Deleted watchpoint {}", id)?,
+                    _ => writeln!(out, "No watchpoint {}", arg)?,
+                },
+            },
+            "delete" | "d" => match args.first() {
+                None => {
+                    self.breakpoints.clear();
+                    writeln!(out, "Deleted all breakpoints")?;
+                },
+                Some(arg) => match parse_address(arg) {
+                    Some(address) if self.remove_breakpoint(address) => writeln!(out, "Deleted breakpoint at {:#06x}", address)?,
+                    Some(address) => writeln!(out, "No breakpoint at {:#06x}", address)?,
+                    None => writeln!(out, "Bad address: {}", arg)?,
+                },
+            },
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(arg) => match arg.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            writeln!(out, "Bad count: {}", arg)?;
+                            return Ok(true);
+                        },
+                    },
+                    None => 1,
+                };
+                self.pause();
+                for _ in 0..count {
+                    if !self.step(cpu, out)? {
+                        break;
+                    }
+                }
+                self.list(cpu, cpu.pc(), 1, out)?;
+            },
+            "next" | "n" => {
+                if !self.step_over(cpu) {
+                    self.step(cpu, out)?;
+                    self.list(cpu, cpu.pc(), 1, out)?;
+                }
+            },
+            "finish" | "out" => {
+                if !self.step_out(cpu) {
+                    writeln!(out, "Not in a subroutine")?;
+                }
+            },
+            "continue" | "c" => {
+                self.resume(cpu);
+            },
+            "pause" => {
+                self.pause();
+                self.list(cpu, cpu.pc(), 1, out)?;
+            },
+            "regs" | "r" => {
+                self.registers(cpu, out)?;
+            },
+            "x" => {
+                let address = args.first().and_then(|arg| parse_address(arg));
+                let len = args.get(1).map_or(Some(EXAMINE_LENGTH), |arg| arg.parse().ok());
+                match (address, len) {
+                    (Some(address), Some(len)) => self.examine(cpu, address, len, out)?,
+                    _ => writeln!(out, "Usage: x ADDR [LEN]")?,
+                }
+            },
+            "list" | "l" => match args.first() {
+                Some(arg) => match parse_address(arg) {
+                    Some(address) => self.list(cpu, address, LIST_LENGTH, out)?,
+                    None => writeln!(out, "Bad address: {}", arg)?,
+                },
+                None => {
+                    let start = cpu.pc().saturating_sub(LIST_BEFORE);
+                    self.list(cpu, start, LIST_LENGTH, out)?;
+                },
+            },
+            "quit" | "q" => {
+                return Ok(false);
+            },
+            _ => {
+                writeln!(out, "Unknown command {}; type help for a list", command)?;
+            },
+        }
+        Ok(true)
+    }
+
+    fn print_last_watch<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self.watches.last() {
+            Some(watchpoint) => writeln!(out, "Watchpoint {}", watchpoint),
+            None => Ok(()),
+        }
+    }
+
+    fn resume_until(&mut self, cpu: &CPU, mode: Mode) {
+        self.mode = mode;
+        self.resume_from = Some(cpu.pc());
+    }
+
+    // Executes one instruction for the REPL. Returns whether the machine can keep stepping.
+    fn step<W: Write>(&mut self, cpu: &mut CPU, out: &mut W) -> io::Result<bool> {
+        match self.step_instruction(cpu) {
+            Ok((StepOutcome::Executed, None)) => Ok(true),
+            Ok((_, Some(stop))) => writeln!(out, "{}", stop).map(|_| false),
+            Ok((StepOutcome::WaitingForKey, None)) => writeln!(out, "Waiting for a key").map(|_| false),
+            Ok((StepOutcome::Halted, None)) => writeln!(out, "Program has exited").map(|_| false),
+            Ok((StepOutcome::WaitingForVBlank, None)) => Ok(true),
+            Err(err) => writeln!(out, "CPU fault: {}", err).map(|_| false),
+        }
+    }
+
+    fn registers<W: Write>(&self, cpu: &CPU, out: &mut W) -> io::Result<()> {
+        for row in 0..4 {
+            let line: Vec<String> = (0..4)
+                .map(|column| row * 4 + column)
+                .map(|r| format!("V{:X}={:02x}", r, cpu.register(r)))
+                .collect();
+            writeln!(out, "{}", line.join("  "))?;
+        }
+        writeln!(out, "I={:#06x}  PC={:#06x}  DT={}  ST={}", cpu.index(), cpu.pc(), cpu.delay_timer, cpu.sound_timer)?;
+        let stack: Vec<String> = cpu.stack().iter().map(|a| format!("{:#06x}", a)).collect();
+        writeln!(out, "stack: [{}]", stack.join(", "))
+    }
+
+    fn examine<W: Write>(&self, cpu: &CPU, address: usize, len: usize, out: &mut W) -> io::Result<()> {
+        let memory = cpu.memory();
+        let end = address.saturating_add(len).min(memory.len());
+        if address >= end {
+            return writeln!(out, "Address {:#06x} is outside memory", address);
+        }
+        for (line, bytes) in memory[address..end].chunks(16).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(out, "{:#06x}: {}", address + line * 16, hex.join(" "))?;
+        }
+        Ok(())
+    }
+
+    // Disassembles `count` instructions from `address`, marking pc and breakpoints.
+    fn list<W: Write>(&self, cpu: &CPU, address: usize, count: usize, out: &mut W) -> io::Result<()> {
+        let pc = cpu.pc();
+        let mut address = address;
+        for _ in 0..count {
+            // starting before pc can land in the middle of code; resync on pc
+            if address < pc && cpu.decode_at(address).is_some_and(|(_, len)| address + len > pc) {
+                address = pc;
+            }
+            let Some((instruction, len)) = cpu.decode_at(address) else {
+                break;
+            };
+            let marker = if address == pc {"=>"} else {"  "};
+            let breakpoint = if self.breakpoints.contains_key(&address) {"*"} else {" "};
+            let raw: String = cpu.memory()[address..address + len].iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(out, "{}{} {:#06x}  {:<8}  {}", marker, breakpoint, address, raw, instruction)?;
+            address += len;
+        }
+        Ok(())
+    }
+}
+
+fn parse_address(text: &str) -> Option<usize> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // 200: call 206, 202: jump 202, 206: V0 += 1, 208: V0 += 1, 20A: return
+    const PROGRAM: [u8; 12] = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x70, 0x01, 0x70, 0x01, 0x00, 0xEE];
+
+    fn machine() -> CPU {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(PROGRAM.to_vec()).unwrap();
+        cpu
+    }
+
+    fn run(debugger: &mut Debugger, cpu: &mut CPU, line: &str) -> String {
+        let mut out = Vec::new();
+        debugger.execute(cpu, line, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_resumes() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "b 208");
+        run(&mut debugger, &mut cpu, "c");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Breakpoint(0x208))));
+        assert!(debugger.is_paused());
+        assert_eq!(cpu.register(0), 1);
+
+        run(&mut debugger, &mut cpu, "c");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(None));
+        assert_eq!(cpu.register(0), 2);
+    }
+
+    #[test]
+    fn paused_frames_do_nothing() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(None));
+        assert_eq!((cpu.pc(), cpu.frame_count()), (0x200, 0));
+    }
+
+    #[test]
+    fn step_next_and_finish() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "s");
+        assert_eq!(cpu.pc(), 0x206);
+        run(&mut debugger, &mut cpu, "finish");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Finished)));
+        assert_eq!((cpu.pc(), cpu.register(0)), (0x202, 2));
+
+        let mut cpu = machine();
+        run(&mut debugger, &mut cpu, "n");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Finished)));
+        assert_eq!((cpu.pc(), cpu.register(0)), (0x202, 2));
+    }
+
+    #[test]
+    fn empty_line_repeats_the_last_command() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "s");
+        run(&mut debugger, &mut cpu, "");
+        assert_eq!(cpu.pc(), 0x208);
+    }
+
+    #[test]
+    fn inspection_commands() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "b 206");
+        let listing = run(&mut debugger, &mut cpu, "l");
+        assert!(listing.contains("\n=>  0x0200  2206      CALL 0x206\n"));
+        assert!(listing.contains("  * 0x0206  7001"));
+        assert!(run(&mut debugger, &mut cpu, "x 200 4").starts_with("0x0200: 22 06 12 02\n"));
+        assert!(run(&mut debugger, &mut cpu, &format!("x ffe {}", usize::MAX)).starts_with("0x0ffe: 00 00\n"));
+        assert!(run(&mut debugger, &mut cpu, "x 1000 2").starts_with("Address 0x1000 is outside memory"));
+        assert!(run(&mut debugger, &mut cpu, "r").contains("PC=0x0200"));
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "b 208 if V0 == 5");
+        run(&mut debugger, &mut cpu, "b 20a if V0 == 2 && SP == 1");
+        assert!(run(&mut debugger, &mut cpu, "b 206 if V0 ==").starts_with("Bad condition"));
+        run(&mut debugger, &mut cpu, "c");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Breakpoint(0x20A))));
+    }
+
+    #[test]
+    fn register_and_condition_watches() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "watch v0");
+        run(&mut debugger, &mut cpu, "c");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Register { id: 1, pc: 0x206, register: 0, old: 0, new: 1 })));
+
+        run(&mut debugger, &mut cpu, "unwatch 1");
+        run(&mut debugger, &mut cpu, "when V0 == 2");
+        run(&mut debugger, &mut cpu, "c");
+        let stop = debugger.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(stop, Some(Stop::Condition { id: 2, pc: 0x208, text: "V0 == 2".to_string() }));
+        // only the edge stops us
+        run(&mut debugger, &mut cpu, "c");
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(None));
+    }
+
+    #[test]
+    fn memory_watches() {
+        // 200: I = 300, 202: V0 = 5, 204: store V0, 206: jump 206
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(vec![0xA3, 0x00, 0x60, 0x05, 0xF0, 0x55, 0x12, 0x06]).unwrap();
+        let mut debugger = Debugger::new();
+        run(&mut debugger, &mut cpu, "watch 2ff 2");
+        run(&mut debugger, &mut cpu, "c");
+        let access = MemoryAccess { kind: AccessKind::Write, address: 0x300, len: 1 };
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Memory { id: 1, pc: 0x204, access })));
+
+        run(&mut debugger, &mut cpu, "unwatch");
+        run(&mut debugger, &mut cpu, "rwatch 206");
+        run(&mut debugger, &mut cpu, "s");
+        assert_eq!(cpu.pc(), 0x206);
+        let out = run(&mut debugger, &mut cpu, "s");
+        assert!(out.starts_with("Watchpoint 2: fetch of 2 bytes at 0x0206 by 0x0206"), "{}", out);
+    }
+
+    #[test]
+    fn memory_watches_reach_the_end_of_memory() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        let out = run(&mut debugger, &mut cpu, &format!("awatch 202 {}", usize::MAX));
+        assert_eq!(out, format!("Watchpoint #1 access 0x0202..{:#06x}\n", usize::MAX));
+        run(&mut debugger, &mut cpu, "c");
+        let access = MemoryAccess { kind: AccessKind::Fetch, address: 0x206, len: 2 };
+        assert_eq!(debugger.run_frame(&mut cpu, 10), Ok(Some(Stop::Memory { id: 1, pc: 0x206, access })));
+    }
+
+    #[test]
+    fn interact_reads_until_resumed() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new();
+        let mut out = Vec::new();
+        let mut input = io::Cursor::new("s\nc\nq\n");
+        assert!(debugger.interact(&mut cpu, &mut input, &mut out).unwrap());
+        assert!(!debugger.is_paused());
+        debugger.pause();
+        assert!(!debugger.interact(&mut cpu, &mut input, &mut out).unwrap());
+    }
+}
@@ -0,0 +1,320 @@
+//! Expressions over machine state for conditional breakpoints, such as
+//! `V3 == 0x10 && I > 0x300`.
+//!
+//! Operands are numbers (decimal, or hex with `0x`), the registers `V0`-`VF`,
+//! `I`, `PC`, `SP` (stack depth), `DT`, `ST`, and `[EXPR]` for the byte of
+//! memory at an address. Operators, loosest first: `||`, `&&`, the
+//! comparisons, then `+`, `-` and `&`, and unary `!`. Anything non-zero is
+//! true.
+
+use crate::core::CPU;
+use std::fmt;
+use std::str::FromStr;
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(u8),
+    Index,
+    Pc,
+    StackDepth,
+    DelayTimer,
+    SoundTimer,
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+/// Why an expression could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    /// Evaluates the expression against the machine. Memory outside the
+    /// address space reads as zero.
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => cpu.register(*r as usize) as i64,
+            Expr::Index => cpu.index() as i64,
+            Expr::Pc => cpu.pc() as i64,
+            Expr::StackDepth => cpu.stack().len() as i64,
+            Expr::DelayTimer => cpu.delay_timer as i64,
+            Expr::SoundTimer => cpu.sound_timer as i64,
+            Expr::Memory(address) => {
+                let address = address.eval(cpu);
+                usize::try_from(address).ok().and_then(|a| cpu.memory().get(a)).map_or(0, |b| *b as i64)
+            },
+            Expr::Not(e) => (e.eval(cpu) == 0) as i64,
+            Expr::Binary(op, a, b) => {
+                let a = a.eval(cpu);
+                // || and && short-circuit
+                match op {
+                    Op::Or if a != 0 => return 1,
+                    Op::And if a == 0 => return 0,
+                    _ => {},
+                }
+                let b = b.eval(cpu);
+                match op {
+                    Op::Or | Op::And => (b != 0) as i64,
+                    Op::Eq => (a == b) as i64,
+                    Op::Ne => (a != b) as i64,
+                    Op::Lt => (a < b) as i64,
+                    Op::Le => (a <= b) as i64,
+                    Op::Gt => (a > b) as i64,
+                    Op::Ge => (a >= b) as i64,
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    Op::BitAnd => a & b,
+                }
+            },
+        }
+    }
+
+    /// Whether the expression is currently non-zero.
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(ParseError(format!("Unexpected {}", token))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+// longest first, so `<=` is not read as `<`
+const SYMBOLS: [&str; 16] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "!", "(", ")", "[", "]"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(ParseError(format!("Unexpected character {:?}", rest.chars().next().unwrap_or(' '))));
+            }
+            let word = &rest[..end];
+            tokens.push(match parse_number(word) {
+                Some(n) => Token::Number(n),
+                None if word.starts_with(|c: char| c.is_ascii_digit()) => return Err(ParseError(format!("Bad number {}", word))),
+                None => Token::Name(word.to_ascii_uppercase()),
+            });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // Consumes the next token if it is one of `symbols`.
+    fn symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Symbol(s)) if symbols.contains(s) => {
+                let s = *s;
+                self.position += 1;
+                Some(s)
+            },
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        match self.symbol(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(ParseError(format!("Expected {}", symbol))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.symbol(&["||"]).is_some() {
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.comparison()?;
+        while self.symbol(&["&&"]).is_some() {
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.sum()?;
+        let op = match self.symbol(&["==", "!=", "<=", ">=", "<", ">"]) {
+            Some("==") => Op::Eq,
+            Some("!=") => Op::Ne,
+            Some("<=") => Op::Le,
+            Some(">=") => Op::Ge,
+            Some("<") => Op::Lt,
+            Some(">") => Op::Gt,
+            _ => return Ok(expr),
+        };
+        Ok(Expr::Binary(op, Box::new(expr), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while let Some(symbol) = self.symbol(&["+", "-", "&"]) {
+            let op = match symbol {
+                "+" => Op::Add,
+                "-" => Op::Sub,
+                _ => Op::BitAnd,
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.symbol(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.symbol(&["-"]).is_some() {
+            return Ok(Expr::Binary(Op::Sub, Box::new(Expr::Number(0)), Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Symbol("(")) => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            Some(Token::Symbol("[")) => {
+                let expr = self.or()?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            },
+            Some(Token::Name(name)) => match name.as_str() {
+                "I" => Ok(Expr::Index),
+                "PC" => Ok(Expr::Pc),
+                "SP" => Ok(Expr::StackDepth),
+                "DT" => Ok(Expr::DelayTimer),
+                "ST" => Ok(Expr::SoundTimer),
+                _ => parse_register(&name).map(Expr::Register).ok_or_else(|| ParseError(format!("Unknown name {}", name))),
+            },
+            Some(token) => Err(ParseError(format!("Unexpected {}", token))),
+            None => Err(ParseError("Unexpected end of expression".to_string())),
+        }
+    }
+}
+
+/// Parses a register name such as `V3` or `vf`.
+pub(crate) fn parse_register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix('V').or_else(|| name.strip_prefix('v'))?;
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn eval(text: &str, cpu: &CPU) -> i64 {
+        text.parse::<Expr>().unwrap_or_else(|err| panic!("{}: {}", text, err)).eval(cpu)
+    }
+
+    #[test]
+    fn evaluates_against_the_machine() {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(vec![0x63, 0x10, 0xA3, 0x20]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(eval("V3 == 0x10 && I > 0x300", &cpu), 1);
+        assert_eq!(eval("v3 == 16 && i > 0x320", &cpu), 0);
+        assert_eq!(eval("[PC - 4] == 0x63", &cpu), 1);
+        assert_eq!(eval("!(V3 & 0x0F) || 0", &cpu), 1);
+        assert_eq!(eval("SP + DT + ST", &cpu), 0);
+        assert_eq!(eval("[0x10000]", &cpu), 0);
+        assert_eq!(eval("1 + 2 - 4", &cpu), -1);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for text in ["", "V3 ==", "VG", "(1", "[1", "1 2", "0xZZ", "V3 = 1", "@"] {
+            assert!(text.parse::<Expr>().is_err(), "{:?} parsed", text);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! A disassembler that follows control flow from 0x200 to tell code from
+//! data, and writes Octo source that reassembles to the same ROM.
+//!
+//! Code is found by following jumps, calls, both sides of skips and the
+//! entries of `jump0` tables. Everything else is data. Jump and call targets
+//! get labels, as do the addresses `i` is pointed at; data that is drawn with
+//! `sprite` is written as binary rows so it can be read as a bitmap.
+
+use crate::core::CPU;
+use crate::error::LoadError;
+use crate::instruction::Instruction::{self, *};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const START: usize = 0x200;
+// A jump0 table has at most 256 bytes of entries, as V0 is one byte.
+const JUMP_TABLE_LEN: usize = 0x100;
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+    Main,
+    Subroutine,
+    Code,
+    Data,
+}
+
+/// What following the control flow found.
+#[derive(Default)]
+struct Analysis {
+    // instructions reached, by address
+    code: BTreeMap<usize, (Instruction, usize)>,
+    labels: BTreeMap<usize, Label>,
+    // sprite data drawn from an address: its size, and bytes per row
+    sprites: BTreeMap<usize, (usize, usize)>,
+}
+
+/// Disassembles a ROM for `platform` into Octo source.
+pub fn disassemble(rom: &[u8], platform: Platform) -> Result<String, LoadError> {
+    // decode with the interpreter's own decoder so the two cannot disagree
+    let mut cpu = CPU::with_platform(platform, Quirks::default());
+    cpu.load(rom.to_vec())?;
+    let end = START + rom.len();
+    let analysis = analyze(&cpu, end);
+    Ok(write_source(&cpu.memory()[..end], &analysis))
+}
+
+fn analyze(cpu: &CPU, end: usize) -> Analysis {
+    let mut analysis = Analysis::default();
+    analysis.labels.insert(START, Label::Main);
+    // addresses to follow, with where `i` points if known and the planes selected
+    let mut pending = vec![(START, None, 1u8)];
+    // paths already followed; one is followed again if the state differs, to find more sprites
+    let mut seen = BTreeSet::new();
+    while let Some((mut address, mut index, mut planes)) = pending.pop() {
+        while (START..end).contains(&address) && seen.insert((address, index, planes)) {
+            let Some((instruction, len)) = cpu.decode_at(address).filter(|(_, len)| address + len <= end) else {
+                break;
+            };
+            if matches!(instruction, Data(..)) {
+                break;
+            }
+            analysis.code.insert(address, (instruction, len));
+            let next = address + len;
+            match instruction {
+                Jump(target) => {
+                    analysis.label(target as usize, Label::Code, end);
+                    pending.push((target as usize, index, planes));
+                    break;
+                },
+                Call(target) => {
+                    analysis.label(target as usize, Label::Subroutine, end);
+                    pending.push((target as usize, index, planes));
+                    // the subroutine may move `i`
+                    index = None;
+                },
+                JumpOffset(base) => {
+                    let base = base as usize;
+                    analysis.label(base, Label::Code, end);
+                    // follow the table's entries for as long as they are jumps
+                    for entry in (base..(base + JUMP_TABLE_LEN).min(end)).step_by(2) {
+                        match cpu.decode_at(entry) {
+                            Some((Jump(_), _)) => pending.push((entry, index, planes)),
+                            _ => break,
+                        }
+                    }
+                    break;
+                },
+                Return | Exit => break,
+                SkipIEQ(..) | SkipINEQ(..) | SkipREQ(..) | SkipRNEQ(..) | SkipKeyEQ(_) | SkipKeyNEQ(_) => {
+                    let skipped = cpu.decode_at(next).map_or(2, |(_, len)| len);
+                    pending.push((next + skipped, index, planes));
+                },
+                SetX(target) | SetXLong(target) => {
+                    analysis.label(target as usize, Label::Data, end);
+                    index = Some(target as usize);
+                },
+                Draw(_, _, rows) => {
+                    if let Some(i) = index.filter(|i| (START..end).contains(i)) {
+                        // 0 rows is a 16x16 sprite, and each selected plane has its own copy
+                        let (rows, width) = if rows == 0 {(16, 2)} else {(rows as usize, 1)};
+                        let size = rows * width * planes.count_ones().max(1) as usize;
+                        let known = analysis.sprites.entry(i).or_insert((size, width));
+                        *known = (known.0.max(size), known.1.max(width));
+                    }
+                },
+                SelectPlane(n) => planes = n,
+                // these move `i`, or might depending on the quirks
+                AddXR(_) | SetXFontR(_) | SetXBigFontR(_) | Store(_) | Load(_) => index = None,
+                _ => {},
+            }
+            address = next;
+        }
+    }
+    analysis
+}
+
+impl Analysis {
+    fn label(&mut self, address: usize, label: Label, end: usize) {
+        if (START..end).contains(&address) {
+            // code labels win over data labels
+            let existing = self.labels.entry(address).or_insert(label);
+            if *existing == Label::Data {
+                *existing = label;
+            }
+        }
+    }
+
+    fn name(&self, address: usize) -> Option<String> {
+        self.labels.get(&address).map(|label| match label {
+            Label::Main => "main".to_string(),
+            Label::Subroutine => format!("sub_{:03x}", address),
+            Label::Code => format!("label_{:03x}", address),
+            Label::Data if self.sprites.contains_key(&address) => format!("sprite_{:03x}", address),
+            Label::Data => format!("data_{:03x}", address),
+        })
+    }
+
+    // The instruction at `address` if it can be written as one: it must
+    // reassemble to the same bytes, with no label inside it.
+    fn instruction(&self, memory: &[u8], address: usize) -> Option<(Instruction, usize)> {
+        let (instruction, len) = *self.code.get(&address)?;
+        let same = memory.get(address..address + len) == Some(&instruction.encode()[..]);
+        let labelled = self.labels.range(address + 1..address + len).next().is_some();
+        (same && !labelled && instruction != NOP).then_some((instruction, len))
+    }
+}
+
+fn write_source(memory: &[u8], analysis: &Analysis) -> String {
+    let end = memory.len();
+    let mut out = String::new();
+    let mut address = START;
+    while address < end {
+        if let Some(name) = analysis.name(address) {
+            if address != START {
+                out.push('\n');
+            }
+            let _ = writeln!(out, ": {}", name);
+        }
+        if let Some((instruction, len)) = analysis.instruction(memory, address) {
+            let _ = writeln!(out, "\t{}", octo(instruction, analysis));
+            address += len;
+            continue;
+        }
+        // data runs until the next label or instruction
+        let run_end = (address + 1..end)
+            .find(|a| analysis.labels.contains_key(a) || analysis.instruction(memory, *a).is_some())
+            .unwrap_or(end);
+        let mut bytes = &memory[address..run_end];
+        if let Some(&(size, width)) = analysis.sprites.get(&address) {
+            let (sprite, rest) = bytes.split_at(size.min(bytes.len()));
+            for row in sprite.chunks(width) {
+                let row: Vec<String> = row.iter().map(|b| format!("0b{:08b}", b)).collect();
+                let _ = writeln!(out, "\t{}", row.join(" "));
+            }
+            bytes = rest;
+        }
+        for line in bytes.chunks(BYTES_PER_LINE) {
+            let line: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+            let _ = writeln!(out, "\t{}", line.join(" "));
+        }
+        address = run_end;
+    }
+    out
+}
+
+// Writes an instruction in Octo's syntax, with labels for the addresses that have them.
+fn octo(instruction: Instruction, analysis: &Analysis) -> String {
+    let target = |address: u16| analysis.name(address as usize);
+    let named = match instruction {
+        Jump(a) => target(a).map(|name| format!("jump {}", name)),
+        Call(a) => target(a),
+        JumpOffset(a) => target(a).map(|name| format!("jump0 {}", name)),
+        SetX(a) => target(a).map(|name| format!("i := {}", name)),
+        SetXLong(a) => target(a).map(|name| format!("i := long {}", name)),
+        _ => None,
+    };
+    named.unwrap_or_else(|| instruction.octo().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(rom: &[u8], platform: Platform) -> Vec<String> {
+        disassemble(rom, platform).unwrap().lines().map(|l| l.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn separates_code_from_data() {
+        let rom = [
+            0xA2, 0x0C, // 200: i := sprite_20c
+            0x22, 0x08, // 202: call sub_208
+            0x32, 0x01, // 204: if v2 != 0x01 then
+            0x12, 0x04, // 206: jump 204
+            0xD0, 0x13, // 208: sprite v0 v1 3
+            0x00, 0xEE, // 20a: return
+            0xF0, 0x90, 0xF0, // 20c: sprite
+            0x12, 0x34, // 20f: never reached
+        ];
+        assert_eq!(lines(&rom, Platform::Chip8), [
+            ": main", "i := sprite_20c", "sub_208",
+            "", ": label_204", "if v2 != 0x01 then", "jump label_204",
+            "", ": sub_208", "sprite v0 v1 3", "return",
+            "", ": sprite_20c", "0b11110000", "0b10010000", "0b11110000", "0x12 0x34",
+        ]);
+    }
+
+    #[test]
+    fn follows_jump_tables_and_skips_over_long_instructions() {
+        let rom = [
+            0xB2, 0x04, // 200: jump0 label_204
+            0x00, 0x00, // 202: data
+            0x12, 0x08, // 204: jump label_208
+            0x12, 0x0A, // 206: jump label_20a
+            0x4A, 0x00, // 208: if va == 0x00 then
+            0xF0, 0x00, 0x02, 0x00, // 20a: i := long main
+            0x00, 0xFD, // 20e: exit
+        ];
+        assert_eq!(lines(&rom, Platform::XoChip), [
+            ": main", "jump0 label_204", "0x00 0x00",
+            "", ": label_204", "jump label_208", "jump label_20a",
+            "", ": label_208", "if va == 0x00 then",
+            "", ": label_20a", "i := long main", "exit",
+        ]);
+    }
+
+    #[test]
+    fn writes_words_that_would_not_reassemble_as_bytes() {
+        // 5121 decodes as 5120, and 0x0100 is a machine code call
+        let rom = [0x51, 0x21, 0x01, 0xE0, 0x12, 0x00];
+        assert_eq!(lines(&rom, Platform::Chip8), [": main", "0x51 0x21 0x01 0xe0", "jump main"]);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::cartridge::CartridgeError;
+use std::fmt;
+
+/// What happened when the CPU executed a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction ran and the program counter moved on.
+    Executed,
+    /// `GetKey` is blocking until a key is pressed; the same instruction will run again.
+    WaitingForKey,
+    /// A sprite was drawn and, with the display wait quirk, nothing more runs until the next frame.
+    WaitingForVBlank,
+    /// The program executed `exit` (SUPER-CHIP `00FD`) and the machine has stopped.
+    Halted,
+}
+
+/// A fault raised while executing an instruction.
+///
+/// When `step` returns an error the program counter is left pointing at the
+/// faulting instruction, so stepping again reproduces the same error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// The word at `pc` does not decode to any instruction.
+    UnknownOpcode { pc: usize, raw: u16 },
+    /// `Call` at `pc` with every stack slot already in use.
+    StackOverflow { pc: usize },
+    /// `Return` at `pc` with an empty stack.
+    StackUnderflow { pc: usize },
+    /// The instruction at `pc` tried to touch `address`, which is outside memory.
+    MemoryOutOfBounds { pc: usize, address: usize },
+}
+
+impl ExecError {
+    /// Address of the instruction that faulted.
+    pub fn pc(&self) -> usize {
+        match *self {
+            ExecError::UnknownOpcode { pc, .. } => pc,
+            ExecError::StackOverflow { pc } => pc,
+            ExecError::StackUnderflow { pc } => pc,
+            ExecError::MemoryOutOfBounds { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExecError::UnknownOpcode { pc, raw } => {
+                write!(f, "unknown opcode {:#06x} at {:#05x}", raw, pc)
+            },
+            ExecError::StackOverflow { pc } => {
+                write!(f, "stack overflow at {:#05x}", pc)
+            },
+            ExecError::StackUnderflow { pc } => {
+                write!(f, "return with empty stack at {:#05x}", pc)
+            },
+            ExecError::MemoryOutOfBounds { pc, address } => {
+                write!(f, "memory access at {:#06x} out of bounds at {:#05x}", address, pc)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// A program that could not be loaded into memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The program is `size` bytes but only `max` fit between 0x200 and the end of memory.
+    TooLarge { size: usize, max: usize },
+    /// The file is an Octo cartridge that could not be read.
+    Cartridge(CartridgeError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::TooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but at most {} bytes fit in memory", size, max)
+            },
+            LoadError::Cartridge(err) => {
+                write!(f, "{}", err)
+            },
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
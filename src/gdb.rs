@@ -0,0 +1,565 @@
+//! A GDB remote serial protocol stub, so `gdb` (or anything else that speaks
+//! the protocol) can attach to a running ROM over TCP.
+//!
+//! The target has registers `v0`-`vf`, `i`, `pc`, `sp` (the stack depth), `dt`
+//! and `st`, described to GDB with a target description, and the machine's
+//! memory as its address space. Breakpoints, watchpoints, single-step and
+//! continue go through a [`Debugger`].
+//!
+//! The stub never blocks: the frontend calls [`GdbStub::poll`] regularly to
+//! handle packets and runs frames through [`GdbStub::run_frame`].
+
+use crate::core::{AccessKind, CPU};
+use crate::debugger::{Debugger, Stop, WatchKind};
+use crate::error::{ExecError, StepOutcome};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+const PACKET_SIZE: usize = 0x1000;
+const REGISTER_COUNT: usize = 21;
+const INDEX: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DELAY_TIMER: usize = 19;
+const SOUND_TIMER: usize = 20;
+
+// stop signals
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// A GDB server on a TCP socket, controlling one machine.
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>,
+    debugger: Debugger,
+}
+
+impl GdbStub {
+    /// Listens on `address`. The machine stays stopped until GDB attaches and
+    /// continues it, and runs freely once GDB detaches.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub { listener, client: None, input: Vec::new(), debugger: Debugger::new() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Whether GDB has the machine stopped, or has not attached yet.
+    pub fn is_stopped(&self) -> bool {
+        self.debugger.is_paused()
+    }
+
+    /// Accepts a connection and handles any packets that have arrived.
+    pub fn poll(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nodelay(true)?;
+                    self.client = Some(stream);
+                    self.input.clear();
+                    self.debugger.pause();
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        let result = self.receive().and_then(|_| self.answer(cpu));
+        if result.is_err() {
+            self.detach(cpu);
+        }
+        result
+    }
+
+    // Handles the complete packets in the input.
+    fn answer(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        while let Some(packet) = self.next_packet()? {
+            if let Some(reply) = self.handle(cpu, &packet) {
+                self.send(&reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs one frame unless GDB has the machine stopped, reporting
+    /// breakpoints, watchpoints and faults to GDB. Faults are only returned
+    /// when GDB is not attached.
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles: usize) -> Result<(), ExecError> {
+        if self.is_stopped() {
+            return Ok(());
+        }
+        let reply = match self.debugger.run_frame(cpu, cycles) {
+            Ok(Some(stop)) => stop_reply(&stop),
+            Ok(None) if cpu.is_halted() && self.is_attached() => {
+                self.debugger.pause();
+                "W00".to_string()
+            },
+            Ok(None) => return Ok(()),
+            Err(err) if self.is_attached() => fault_reply(&err),
+            Err(err) => {
+                self.debugger.resume(cpu);
+                return Err(err);
+            },
+        };
+        // a failed send means GDB is gone, so let the machine run on
+        if self.send(&reply).is_err() {
+            self.detach(cpu);
+        }
+        Ok(())
+    }
+
+    // Reads whatever the client has sent without blocking.
+    fn receive(&mut self) -> io::Result<()> {
+        let Some(client) = self.client.as_mut() else {
+            return Ok(());
+        };
+        client.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        let result = loop {
+            match client.read(&mut buffer) {
+                Ok(0) => break Err(io::Error::new(ErrorKind::ConnectionAborted, "GDB disconnected")),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {},
+                Err(err) => break Err(err),
+            }
+        };
+        client.set_nonblocking(false)?;
+        result
+    }
+
+    // Takes the next complete packet from the input, acknowledging it.
+    // An interrupt (Ctrl-C) is returned as the packet "\x03".
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let Some(&first) = self.input.first() else {
+                return Ok(None);
+            };
+            match first {
+                b'$' => {},
+                0x03 => {
+                    self.input.remove(0);
+                    return Ok(Some("\x03".to_string()));
+                },
+                // acks, and noise between packets
+                _ => {
+                    self.input.remove(0);
+                    continue;
+                },
+            }
+            let Some(end) = self.input.iter().position(|b| *b == b'#') else {
+                return Ok(None);
+            };
+            if self.input.len() < end + 3 {
+                return Ok(None);
+            }
+            let body = self.input[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.input[end + 1..end + 3]).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+            self.input.drain(..end + 3);
+            if checksum != Some(body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))) {
+                self.write_raw(b"-")?;
+                continue;
+            }
+            self.write_raw(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+        }
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let checksum = body.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write_raw(format!("${}#{:02x}", body, checksum).as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let Some(client) = self.client.as_mut() else {
+            return Ok(());
+        };
+        let result = client.write_all(bytes).and_then(|_| client.flush());
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+
+    fn detach(&mut self, cpu: &mut CPU) {
+        self.client = None;
+        self.input.clear();
+        self.debugger.clear(cpu);
+        self.debugger.resume(cpu);
+    }
+
+    /// Handles one packet, returning the reply to send, if any.
+    fn handle(&mut self, cpu: &mut CPU, packet: &str) -> Option<String> {
+        if packet == "\x03" {
+            self.debugger.pause();
+            return Some(format!("S{:02x}", SIGINT));
+        }
+        let mut chars = packet.chars();
+        let Some(command) = chars.next() else {
+            return Some(String::new());
+        };
+        let args = chars.as_str();
+        // GDB waits for a reply to every packet, so malformed ones get an error
+        let malformed = || Some("E01".to_string());
+        let reply = match command {
+            '?' => format!("S{:02x}", SIGTRAP),
+            'g' => (0..REGISTER_COUNT).filter_map(|n| read_register(cpu, n)).map(|bytes| hex(&bytes)).collect(),
+            'G' => {
+                let Some(bytes) = unhex(args) else {
+                    return malformed();
+                };
+                let mut offset = 0;
+                for n in 0..REGISTER_COUNT {
+                    let size = register_size(n);
+                    if let Some(value) = bytes.get(offset..offset + size) {
+                        write_register(cpu, n, value);
+                    }
+                    offset += size;
+                }
+                "OK".to_string()
+            },
+            'p' => match usize::from_str_radix(args, 16).ok().and_then(|n| read_register(cpu, n)) {
+                Some(bytes) => hex(&bytes),
+                None => "E01".to_string(),
+            },
+            'P' => {
+                let Some((n, value)) = args.split_once('=') else {
+                    return malformed();
+                };
+                let Ok(n) = usize::from_str_radix(n, 16) else {
+                    return malformed();
+                };
+                match unhex(value) {
+                    Some(value) if write_register(cpu, n, &value) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            },
+            'm' => {
+                let Some((address, len)) = parse_range(args) else {
+                    return malformed();
+                };
+                match cpu.memory().get(address..) {
+                    Some(rest) if !rest.is_empty() => hex(&rest[..len.min(rest.len())]),
+                    _ => "E01".to_string(),
+                }
+            },
+            'M' => {
+                let Some((range, data)) = args.split_once(':') else {
+                    return malformed();
+                };
+                let Some((address, _)) = parse_range(range) else {
+                    return malformed();
+                };
+                match unhex(data) {
+                    Some(bytes) if cpu.write_memory(address, &bytes) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            },
+            'Z' | 'z' => {
+                let Some((kind, range)) = args.split_once(',') else {
+                    return malformed();
+                };
+                let Some((address, len)) = parse_range(range) else {
+                    return malformed();
+                };
+                let insert = command == 'Z';
+                match (kind, insert) {
+                    ("0" | "1", true) => self.debugger.add_breakpoint(address),
+                    ("0" | "1", false) => {
+                        self.debugger.remove_breakpoint(address);
+                    },
+                    ("2" | "3" | "4", _) => {
+                        let kind = match kind {
+                            "2" => WatchKind::Write,
+                            "3" => WatchKind::Read,
+                            _ => WatchKind::Access,
+                        };
+                        if insert {
+                            self.debugger.watch_memory(cpu, address, len, kind);
+                        } else {
+                            self.debugger.remove_memory_watch(cpu, address, len, kind);
+                        }
+                    },
+                    _ => return Some(String::new()),
+                }
+                "OK".to_string()
+            },
+            'c' => {
+                if let Ok(address) = usize::from_str_radix(args, 16) {
+                    cpu.set_pc(address);
+                }
+                self.debugger.resume(cpu);
+                return None;
+            },
+            's' => {
+                if let Ok(address) = usize::from_str_radix(args, 16) {
+                    cpu.set_pc(address);
+                }
+                match self.debugger.step_instruction(cpu) {
+                    Ok((_, Some(stop))) => stop_reply(&stop),
+                    Ok((StepOutcome::Halted, None)) => "W00".to_string(),
+                    Ok(_) => format!("S{:02x}", SIGTRAP),
+                    Err(err) => fault_reply(&err),
+                }
+            },
+            'D' => {
+                let _ = self.send("OK");
+                self.detach(cpu);
+                return None;
+            },
+            'k' => {
+                self.detach(cpu);
+                return None;
+            },
+            'H' | 'T' => "OK".to_string(),
+            'q' => self.query(args),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+", PACKET_SIZE);
+        }
+        if let Some(request) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let xml = target_xml();
+            let Some((offset, len)) = parse_range(request) else {
+                return "E01".to_string();
+            };
+            let end = offset.saturating_add(len);
+            let chunk = xml.get(offset.min(xml.len())..end.min(xml.len())).unwrap_or("");
+            let more = end < xml.len();
+            return format!("{}{}", if more {"m"} else {"l"}, chunk);
+        }
+        match query {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Memory { access, .. } => {
+            let kind = if access.kind == AccessKind::Write {"watch"} else {"rwatch"};
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, access.address)
+        },
+        Stop::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn fault_reply(err: &ExecError) -> String {
+    let signal = match err {
+        ExecError::UnknownOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    };
+    format!("S{:02x}", signal)
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        INDEX | PC => 2,
+        _ => 1,
+    }
+}
+
+// Registers go over the wire in little-endian order.
+fn read_register(cpu: &CPU, n: usize) -> Option<Vec<u8>> {
+    let value = match n {
+        0..=15 => cpu.register(n) as u16,
+        INDEX => cpu.index(),
+        PC => cpu.pc() as u16,
+        SP => cpu.stack().len() as u16,
+        DELAY_TIMER => cpu.delay_timer as u16,
+        SOUND_TIMER => cpu.sound_timer as u16,
+        _ => return None,
+    };
+    Some(value.to_le_bytes()[..register_size(n)].to_vec())
+}
+
+// The stack depth is read-only.
+fn write_register(cpu: &mut CPU, n: usize, bytes: &[u8]) -> bool {
+    if bytes.len() != register_size(n) {
+        return false;
+    }
+    let value = if bytes.len() == 2 {u16::from_le_bytes([bytes[0], bytes[1]])} else {bytes[0] as u16};
+    match n {
+        0..=15 => cpu.set_register(n, value as u8),
+        INDEX => cpu.set_index(value),
+        PC => cpu.set_pc(value as usize),
+        DELAY_TIMER => cpu.delay_timer = value as u8,
+        SOUND_TIMER => cpu.sound_timer = value as u8,
+        _ => return false,
+    }
+    true
+}
+
+fn target_xml() -> String {
+    let mut registers: Vec<String> = (0..16)
+        .map(|r| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", r))
+        .collect();
+    registers.push("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>".to_string());
+    registers.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".to_string());
+    registers.push("<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>".to_string());
+    registers.push("<reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>".to_string());
+    registers.push("<reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>".to_string());
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers.concat()
+    )
+}
+
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, len) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::io::BufReader;
+    use std::time::Duration;
+
+    // 200: V0 = 5, 202: V1 = 6, 204: jump 204
+    fn machine() -> CPU {
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load(vec![0x60, 0x05, 0x61, 0x06, 0x12, 0x04]).unwrap();
+        cpu
+    }
+
+    fn stub() -> GdbStub {
+        GdbStub::bind("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        cpu.set_index(0x1234);
+        let registers = gdb.handle(&mut cpu, "g").unwrap();
+        assert_eq!(registers.len(), (16 + 2 + 2 + 3) * 2);
+        assert_eq!(&registers[32..40], "34120002");
+
+        assert_eq!(gdb.handle(&mut cpu, "P3=7f").as_deref(), Some("OK"));
+        assert_eq!(cpu.register(3), 0x7F);
+        assert_eq!(gdb.handle(&mut cpu, "p11").as_deref(), Some("0002"));
+        assert_eq!(gdb.handle(&mut cpu, "P12=05").as_deref(), Some("E01"));
+
+        assert_eq!(gdb.handle(&mut cpu, "m200,4").as_deref(), Some("60056106"));
+        assert_eq!(gdb.handle(&mut cpu, "M300,2:abcd").as_deref(), Some("OK"));
+        assert_eq!(&cpu.memory()[0x300..0x302], &[0xAB, 0xCD]);
+        assert_eq!(gdb.handle(&mut cpu, "m1000,2").as_deref(), Some("E01"));
+        assert_eq!(gdb.handle(&mut cpu, "MFFF,2:0000").as_deref(), Some("E01"));
+    }
+
+    #[test]
+    fn malformed_packets_get_an_error() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        for packet in ["P3", "Px=05", "G0", "m200", "mxyz,2", "M200,1", "Mq,1:00", "Z0", "Z0,202", "z2,x,1"] {
+            assert_eq!(gdb.handle(&mut cpu, packet).as_deref(), Some("E01"), "{}", packet);
+        }
+    }
+
+    #[test]
+    fn target_description_is_chunked() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        let first = gdb.handle(&mut cpu, "qXfer:features:read:target.xml:0,a").unwrap();
+        assert_eq!(first, "m<?xml vers");
+        let all = gdb.handle(&mut cpu, "qXfer:features:read:target.xml:0,1000").unwrap();
+        assert!(all.starts_with('l') && all.contains("name=\"pc\""));
+    }
+
+    #[test]
+    fn breakpoints_step_and_continue() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        assert_eq!(gdb.handle(&mut cpu, "Z0,202,2").as_deref(), Some("OK"));
+        assert_eq!(gdb.handle(&mut cpu, "c"), None);
+        assert!(!gdb.is_stopped());
+        gdb.run_frame(&mut cpu, 10).unwrap();
+        assert!(gdb.is_stopped());
+        assert_eq!(cpu.pc(), 0x202);
+
+        assert_eq!(gdb.handle(&mut cpu, "s").as_deref(), Some("S05"));
+        assert_eq!((cpu.pc(), cpu.register(1)), (0x204, 6));
+        assert_eq!(gdb.handle(&mut cpu, "z0,202,2").as_deref(), Some("OK"));
+        assert_eq!(gdb.handle(&mut cpu, "\x03").as_deref(), Some("S02"));
+    }
+
+    // Sends `bytes` from a new client and returns the first `len` bytes of the replies.
+    fn exchange(cpu: &mut CPU, gdb: &mut GdbStub, bytes: &[u8], len: usize) -> Vec<u8> {
+        let mut client = TcpStream::connect(gdb.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(bytes).unwrap();
+        for _ in 0..100 {
+            gdb.poll(cpu).unwrap();
+            if gdb.is_attached() && gdb.input.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut reply = vec![0; len];
+        BufReader::new(&client).read_exact(&mut reply).unwrap();
+        reply
+    }
+
+    #[test]
+    fn talks_over_tcp() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        assert_eq!(exchange(&mut cpu, &mut gdb, b"$?#3f", 8), b"+$S05#b8");
+        assert!(gdb.is_stopped());
+    }
+
+    #[test]
+    fn answers_empty_and_binary_packets() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        assert_eq!(exchange(&mut cpu, &mut gdb, b"$#00$\xff#ff", 10), b"+$#00+$#00");
+        assert!(gdb.is_attached());
+    }
+
+    #[test]
+    fn failed_sends_detach() {
+        let mut cpu = machine();
+        let mut gdb = stub();
+        exchange(&mut cpu, &mut gdb, b"$?#3f", 8);
+        assert_eq!(gdb.handle(&mut cpu, "Z0,202,2").as_deref(), Some("OK"));
+        assert_eq!(gdb.handle(&mut cpu, "c"), None);
+        gdb.client.as_ref().unwrap().shutdown(std::net::Shutdown::Write).unwrap();
+        gdb.run_frame(&mut cpu, 10).unwrap();
+        assert!(!gdb.is_attached() && !gdb.is_stopped());
+        // the breakpoint went with the client
+        cpu.set_pc(0x200);
+        gdb.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.pc(), 0x204);
+    }
+}
@@ -0,0 +1,260 @@
+use graphics::{Graphics, Context};
+use opengl_graphics::{OpenGL, GlGraphics};
+use piston::{EventSettings, Events, WindowSettings, RenderEvent, UpdateEvent, Button, PressEvent, Key, ReleaseEvent};
+use piston_window::PistonWindow;
+
+use rust_chip8::{CPU, FrameClock, Renderer, RewindBuffer};
+use std::{collections::BTreeMap, fs::{read, write}, io::{stdin, stdout}, sync::mpsc::{channel, Receiver}, thread, time::Duration};
+
+use crate::cli::Args;
+use crate::Session;
+
+pub fn run(args: &Args, mut session: Session) {
+    let mut renderer = Renderer::new(session.palette, args.scale as usize);
+    let rom_path = args.rom.as_path();
+    // Movies replay from power-on, so rewinding and loading states are off while one is active
+    let movie_active = session.movie_active();
+
+    #[cfg(feature = "beeper")]
+    match crate::beeper::Beeper::open(args.tone()) {
+        Ok(beeper) => {
+            session.cpu.set_audio_sink(Box::new(beeper));
+        },
+        Err(err) => println!("Sound disabled: {}", err),
+    }
+
+    // Setup graphics
+    let mut event_settings = EventSettings::new();
+    event_settings.max_fps = 60;
+    event_settings.ups = 120;
+    let mut events = Events::new(event_settings);
+    
+    let opengl = OpenGL::V3_2;
+    let mut window: PistonWindow = WindowSettings::new(
+        "CHIP-8",
+        [renderer.output_size().0 as u32, renderer.output_size().1 as u32]
+    ).exit_on_esc(true)
+    .graphics_api(opengl)
+    .build()
+    .unwrap();
+    let mut gl = GlGraphics::new(opengl);
+    let mut running = !args.paused; 
+    let mut clock = FrameClock::new();
+    let mut shift_held = false;
+    let mut rewinding = false;
+    let mut rewind = RewindBuffer::new(args.rewind_seconds, args.rewind_budget.saturating_mul(1024 * 1024));
+    let commands = session.debugger.as_ref().map(|debugger| {
+        let _ = debugger.prompt(&mut stdout());
+        read_commands()
+    });
+    
+    // Event loop
+    while let Some(e) = events.next(&mut window) {
+        session.poll_remote(args);
+        // an editor may have launched another program with its own colors
+        renderer.palette = session.palette;
+        if session.quit_requested() {
+            break;
+        }
+        if let Some(commands) = commands.as_ref() {
+            if !run_commands(&mut session, commands) {
+                break;
+            }
+        }
+
+        if let Some(args) = e.update_args() {
+            let frames = clock.advance(Duration::from_secs_f64(args.dt));
+            for _ in 0..frames {
+                if rewinding && !movie_active {
+                    rewind.rewind(&mut session.cpu);
+                    continue;
+                }
+                if !running || session.is_stopped() {
+                    break;
+                }
+                if let Err(err) = session.run_frame() {
+                    println!("CPU fault: {}", err);
+                    session.cpu.dump_registers();
+                    running = false;
+                }
+                if let Some(debugger) = session.debugger.as_ref().filter(|d| d.is_paused()) {
+                    let _ = debugger.prompt(&mut stdout());
+                }
+                rewind.push(&session.cpu);
+            }
+        }
+        
+        if let Some(args) = e.render_args() {
+            gl.draw(args.viewport(), |c, g| {
+                draw_screen(c, g, &session.cpu, &renderer);
+            });
+        }
+
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            let key_hex = key_for_button(key, &session.keys);
+            if key_hex >= 0 && session.player.is_none() {
+                session.cpu.set_key(key_hex as u8, true);
+            }
+
+            // Debug keys
+            if key == Key::Space {
+                running = !running;
+            }
+
+            if key == Key::P {
+                session.cpu.dump_registers();
+            }
+
+            // Save states: F1-F8 save to a slot, Shift+F1-F8 load it
+            if key == Key::LShift || key == Key::RShift {
+                shift_held = true;
+            }
+            if key == Key::Backspace {
+                rewinding = true;
+            }
+            if let Some(slot) = slot_for_key(key) {
+                let path = format!("{}.state{}", rom_path.display(), slot);
+                if shift_held && movie_active {
+                    println!("Loading states is disabled while a movie is recording or playing");
+                } else if shift_held {
+                    load_slot(&mut session.cpu, &path, slot);
+                    rewind.clear();
+                } else {
+                    save_slot(&session.cpu, &path, slot);
+                }
+            }
+        }
+
+        if let Some(Button::Keyboard(key)) = e.release_args() {
+            if key == Key::LShift || key == Key::RShift {
+                shift_held = false;
+            }
+            if key == Key::Backspace {
+                rewinding = false;
+            }
+
+            let key_hex = key_for_button(key, &session.keys);
+            if key_hex >= 0 && session.player.is_none() {
+                session.cpu.set_key(key_hex as u8, false);
+            }
+        }
+    }
+
+    session.finish(args);
+}
+
+// Reads debugger commands from stdin on another thread so the window keeps running.
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line in stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Executes any commands typed since the last event. Returns false to quit.
+fn run_commands(session: &mut Session, commands: &Receiver<String>) -> bool {
+    let Some(debugger) = session.debugger.as_mut() else {
+        return true;
+    };
+    let mut out = stdout();
+    while let Ok(line) = commands.try_recv() {
+        match debugger.execute(&mut session.cpu, &line, &mut out) {
+            Ok(true) => {
+                let _ = debugger.prompt(&mut out);
+            },
+            Ok(false) => return false,
+            Err(err) => {
+                println!("Debugger: {}", err);
+                return false;
+            },
+        }
+    }
+    true
+}
+
+fn slot_for_key(key: Key) -> Option<u8> {
+    match key {
+        Key::F1 => Some(1),
+        Key::F2 => Some(2),
+        Key::F3 => Some(3),
+        Key::F4 => Some(4),
+        Key::F5 => Some(5),
+        Key::F6 => Some(6),
+        Key::F7 => Some(7),
+        Key::F8 => Some(8),
+        _ => None
+    }
+}
+
+fn save_slot(cpu: &CPU, path: &str, slot: u8) {
+    match write(path, cpu.save_state()) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(err) => println!("Could not save slot {}: {}", slot, err),
+    }
+}
+
+fn load_slot(cpu: &mut CPU, path: &str, slot: u8) {
+    let result = read(path)
+        .map_err(rust_chip8::StateError::from)
+        .and_then(|bytes| cpu.load_state(&bytes));
+    match result {
+        Ok(()) => println!("Loaded state from slot {}", slot),
+        Err(err) => println!("Could not load slot {}: {}", slot, err),
+    }
+}
+
+// The arrow keys, Enter and Tab press the keys the ROM database gives for
+// its up, down, left, right, a and b buttons.
+fn key_for_button(key: Key, keys: &BTreeMap<String, u8>) -> i32 {
+    let button = match key {
+        Key::Up => "up",
+        Key::Down => "down",
+        Key::Left => "left",
+        Key::Right => "right",
+        Key::Return => "a",
+        Key::Tab => "b",
+        _ => return hex_for_key(key),
+    };
+    keys.get(button).map_or(-1, |k| *k as i32)
+}
+
+fn hex_for_key(key: Key) -> i32 {
+    match key {
+        Key::D1 => {0x1},
+        Key::D2 => {0x2},
+        Key::D3 => {0x3},
+        Key::D4 => {0xC},
+        Key::Q => {0x4},
+        Key::W => {0x5},
+        Key::E => {0x6},
+        Key::R => {0xD},
+        Key::A => {0x7},
+        Key::S => {0x8},
+        Key::D => {0x9},
+        Key::F => {0xE},
+        Key::Z => {0xA},
+        Key::X => {0x0},
+        Key::C => {0xB},
+        Key::V => {0xF}
+        _ => {-1}
+    }
+}
+
+
+fn draw_screen<G: Graphics>(c: Context, g: &mut G, cpu: &CPU, renderer: &Renderer) {
+    use graphics::{clear, rectangle};
+    clear(renderer.palette.background.to_rgba_f32(), g);
+    for pixel in renderer.lit_pixels(cpu) {
+        rectangle(
+            pixel.color.to_rgba_f32(), 
+            rectangle::square(pixel.x as f64, pixel.y as f64, pixel.size as f64),
+            c.transform, 
+            g
+        )
+    }
+}
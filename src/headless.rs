@@ -0,0 +1,93 @@
+use std::io::{stdin, stdout};
+use std::path::Path;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+use rust_chip8::{Renderer, WavSink, CPU};
+
+use crate::cli::Args;
+use crate::Session;
+
+/// Runs the ROM for `--frames` frames with no window, feeding scripted keys
+/// and writing screenshots along the way and on exit. Under the debugger,
+/// commands are read from stdin whenever it stops; under GDB or an editor,
+/// the run waits while they have the machine stopped.
+pub fn run(args: &Args, mut session: Session) {
+    let mut renderer = Renderer::new(session.palette, args.scale as usize);
+    let stem = args.rom.file_stem().map_or("screen".into(), |s| s.to_string_lossy().into_owned());
+    let script = args.keys.as_ref().map_or(&[][..], |k| k.0.as_slice());
+    let mut next_key = 0;
+    let mut fault = None;
+    let wav = args.wav.as_ref().filter(|path| match WavSink::create(path, args.tone()) {
+        Ok(sink) => {
+            session.cpu.set_audio_sink(Box::new(sink));
+            true
+        },
+        Err(err) => {
+            eprintln!("Could not write {}: {}", path.display(), err);
+            false
+        },
+    });
+
+    while session.cpu.frame_count() < args.frames {
+        if let Some(debugger) = session.debugger.as_mut() {
+            match debugger.interact(&mut session.cpu, &mut stdin().lock(), &mut stdout()) {
+                Ok(true) => {},
+                Ok(false) => break,
+                Err(err) => {
+                    eprintln!("Debugger: {}", err);
+                    break;
+                },
+            }
+        }
+        session.poll_remote(args);
+        // an editor may have launched another program with its own colors
+        renderer.palette = session.palette;
+        if session.quit_requested() {
+            break;
+        }
+        if session.is_stopped() {
+            sleep(Duration::from_millis(5));
+            continue;
+        }
+        let frame = session.cpu.frame_count();
+        while let Some(event) = script.get(next_key).filter(|e| e.frame <= frame) {
+            for key in 0..16 {
+                session.cpu.set_key(key, event.keys & (1 << key) > 0);
+            }
+            next_key += 1;
+        }
+        if let Err(err) = session.run_frame() {
+            fault = Some(err);
+            break;
+        }
+        let frame = session.cpu.frame_count();
+        if args.screenshot_at.contains(&frame) {
+            screenshot(&renderer, &session.cpu, &args.output_dir.join(format!("{}-{}.png", stem, frame)));
+        }
+    }
+
+    let last = args.screenshot.clone().unwrap_or_else(|| args.output_dir.join(format!("{}.png", stem)));
+    screenshot(&renderer, &session.cpu, &last);
+    if let Some(path) = wav {
+        match session.cpu.audio_sink_mut().finish() {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(err) => eprintln!("Could not write {}: {}", path.display(), err),
+        }
+    }
+    let frames = session.cpu.frame_count();
+    session.finish(args);
+    if let Some(err) = fault {
+        eprintln!("CPU fault after {} frames: {}", frames, err);
+        exit(2);
+    }
+    println!("Ran {} frames", frames);
+}
+
+fn screenshot(renderer: &Renderer, cpu: &CPU, path: &Path) {
+    match renderer.save_png(cpu, path) {
+        Ok(()) => println!("Wrote {}", path.display()),
+        Err(err) => eprintln!("Could not write {}: {}", path.display(), err),
+    }
+}
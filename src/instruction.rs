@@ -1,4 +1,10 @@
-#[derive(Debug)]
+use crate::platform::Platform;
+use self::Instruction::*;
+use std::fmt;
+
+/// A decoded CHIP-8 instruction. Register operands are register numbers (0x0-0xF).
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     NOP,
     ClearScreen,
@@ -35,5 +41,605 @@ pub enum Instruction {
     StoreDecimalR(u8),
     Store(u8),
     Load(u8),
+    // SUPER-CHIP
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    SetXBigFontR(u8),
+    StoreFlags(u8),
+    LoadFlags(u8),
+    // XO-CHIP
+    ScrollUp(u8),
+    SetXLong(u16), // F000 NNNN, the only four byte instruction
+    SelectPlane(u8),
+    StoreRange(u8, u8),
+    LoadRange(u8, u8),
+    SetPitchR(u8),
+    LoadAudio,
     Data(u8, u8) // default if no other opcode matched
-}
\ No newline at end of file
+}
+
+const HIGH_MASK: u8 = 0xF0;
+const LOW_MASK: u8 = 0x0F;
+
+impl Instruction {
+    /// Decodes the instruction at the start of `bytes` for `platform`,
+    /// returning it with its length in bytes. Bytes that are not an
+    /// instruction decode as `Data`. Returns `None` if `bytes` ends first.
+    pub fn decode(bytes: &[u8], platform: Platform) -> Option<(Instruction, usize)> {
+        match Self::decode_word([*bytes.first()?, *bytes.get(1)?], platform) {
+            SetXLong(_) => {
+                let address = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+                Some((SetXLong(address), 4))
+            },
+            instruction => Some((instruction, 2)),
+        }
+    }
+
+    /// Decodes one instruction word for `platform`. `SetXLong` is decoded
+    /// with a zero address, as its address is in the next word.
+    pub(crate) fn decode_word(raw: [u8; 2], platform: Platform) -> Instruction {
+        let opcode = (raw[0] & HIGH_MASK) >> 4;
+        let register_a = raw[0] & LOW_MASK;
+        let register_b = (raw[1] & HIGH_MASK) >> 4;
+        let n = raw[1] & LOW_MASK;
+        let nn = raw[1];
+        let nnn = (((raw[0] & LOW_MASK) as u16) << 8) | raw[1] as u16;
+
+        match opcode {
+            0x00 => {
+                match raw[1] {
+                    0xE0 => {
+                        ClearScreen
+                    },
+                    0xEE => {
+                        Return
+                    },
+                    0x00 => {
+                        NOP
+                    },
+                    0xFB if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        ScrollRight
+                    },
+                    0xFC if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        ScrollLeft
+                    },
+                    0xFD if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        Exit
+                    },
+                    0xFE if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        LowRes
+                    },
+                    0xFF if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        HighRes
+                    },
+                    0xC0..=0xCF if raw[0] == 0x00 && platform.has_schip_instructions() => {
+                        ScrollDown(n)
+                    },
+                    0xD0..=0xDF if raw[0] == 0x00 && platform.has_xo_instructions() => {
+                        ScrollUp(n)
+                    },
+                    _ => {
+                        Data(raw[0],raw[1])
+                    }
+                }
+            },
+            0x01 => {
+                Jump(nnn)
+            },
+            0x02 => {
+                Call(nnn)
+            },
+            // Skips
+            0x03 => {
+                SkipIEQ(register_a, nn)
+            },
+            0x04 => {
+                SkipINEQ(register_a, nn)
+            },
+            0x05 => {
+                match n {
+                    0x02 if platform.has_xo_instructions() => {
+                        StoreRange(register_a, register_b)
+                    },
+                    0x03 if platform.has_xo_instructions() => {
+                        LoadRange(register_a, register_b)
+                    },
+                    _ => {
+                        SkipREQ(register_a, register_b)
+                    }
+                }
+            },
+            0x09 => {
+                SkipRNEQ(register_a, register_b)
+            },
+            0x06 => {
+                SetRI(register_a, nn)
+            },
+            0x07 => {
+                AddRI(register_a, nn)
+            },
+            0x08 => {
+                match n {
+                    0x00 => {
+                        SetRR(register_a, register_b)
+                    },
+                    0x01 => {
+                        OrRR(register_a, register_b)
+                    },
+                    0x02 => {
+                        AndRR(register_a, register_b)
+                    },
+                    0x03 => {
+                        XorRR(register_a, register_b)
+                    },
+                    0x04 => {
+                        AddRR(register_a, register_b)
+                    },
+                    0x05 => {
+                        SubAB(register_a, register_b)
+                    },
+                    0x06 => {
+                        ShiftRightRR(register_a, register_b)
+                    },
+                    0x07 => {
+                        SubBA(register_a, register_b)
+                    },
+                    0x0E => {
+                        ShiftLeftRR(register_a, register_b)
+                    },
+                    _ => {
+                        Data(raw[0], raw[1])
+                    }
+                }
+
+            }
+            0x0A => {
+                SetX(nnn)
+            },
+            0x0B => {
+                JumpOffset(nnn)
+            }
+            0x0C => {
+                Random(register_a, nn)
+            },
+            0x0D => {
+                Draw(register_a, register_b, n)
+            },
+            0x0E => {
+                match nn {
+                    0x9E => {
+                        SkipKeyEQ(register_a)
+                    },
+                    0xA1 => {
+                        SkipKeyNEQ(register_a)
+                    },
+                    _ => {
+                        Data(raw[0], raw[1])
+                    }
+                }
+            },
+            0x0F => {
+                match nn {
+                    0x00 if raw[0] == 0xF0 && platform.has_xo_instructions() => {
+                        SetXLong(0)
+                    },
+                    0x01 if platform.has_xo_instructions() => {
+                        SelectPlane(register_a)
+                    },
+                    0x02 if raw[0] == 0xF0 && platform.has_xo_instructions() => {
+                        LoadAudio
+                    },
+                    0x3A if platform.has_xo_instructions() => {
+                        SetPitchR(register_a)
+                    },
+                    0x07 => {
+                        SetRDelay(register_a)
+                    },
+                    0x15 => {
+                        SetDelayR(register_a)
+                    },
+                    0x18 => {
+                        SetSoundR(register_a)
+                    },
+                    0x1E => {
+                        AddXR(register_a)
+                    },
+                    0x0A => {
+                        GetKey(register_a)
+                    },
+                    0x29 => {
+                        SetXFontR(register_a)
+                    },
+                    0x30 if platform.has_schip_instructions() => {
+                        SetXBigFontR(register_a)
+                    },
+                    0x75 if platform.has_schip_instructions() => {
+                        StoreFlags(register_a)
+                    },
+                    0x85 if platform.has_schip_instructions() => {
+                        LoadFlags(register_a)
+                    },
+                    0x33 => {
+                        StoreDecimalR(register_a)
+                    },
+                    0x55 => {
+                        Store(register_a)
+                    },
+                    0x65 => {
+                        Load(register_a)
+                    },
+                    _ => {
+                        Data(raw[0], raw[1])
+                    }
+                }
+            }
+            _ => {
+                Data(raw[0], raw[1])
+            }
+        }
+    }
+
+    /// The bytes this instruction is stored as. Every instruction is two
+    /// bytes except `SetXLong`, which is four.
+    pub fn encode(&self) -> Vec<u8> {
+        let xnn = |op: u16, x: u8, nn: u8| op << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+        let xyn = |op: u16, x: u8, y: u8, n: u8| xnn(op, x, (y & 0xF) << 4 | n & 0xF);
+        let nnn = |op: u16, nnn: u16| op << 12 | nnn & 0xFFF;
+        let word = match *self {
+            NOP => 0x0000,
+            ClearScreen => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(a) => nnn(0x1, a),
+            Call(a) => nnn(0x2, a),
+            SkipIEQ(x, nn) => xnn(0x3, x, nn),
+            SkipINEQ(x, nn) => xnn(0x4, x, nn),
+            SkipREQ(x, y) => xyn(0x5, x, y, 0x0),
+            StoreRange(x, y) => xyn(0x5, x, y, 0x2),
+            LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+            SetRI(x, nn) => xnn(0x6, x, nn),
+            AddRI(x, nn) => xnn(0x7, x, nn),
+            SetRR(x, y) => xyn(0x8, x, y, 0x0),
+            OrRR(x, y) => xyn(0x8, x, y, 0x1),
+            AndRR(x, y) => xyn(0x8, x, y, 0x2),
+            XorRR(x, y) => xyn(0x8, x, y, 0x3),
+            AddRR(x, y) => xyn(0x8, x, y, 0x4),
+            SubAB(x, y) => xyn(0x8, x, y, 0x5),
+            ShiftRightRR(x, y) => xyn(0x8, x, y, 0x6),
+            SubBA(x, y) => xyn(0x8, x, y, 0x7),
+            ShiftLeftRR(x, y) => xyn(0x8, x, y, 0xE),
+            SkipRNEQ(x, y) => xyn(0x9, x, y, 0x0),
+            SetX(a) => nnn(0xA, a),
+            JumpOffset(a) => nnn(0xB, a),
+            Random(x, nn) => xnn(0xC, x, nn),
+            Draw(x, y, n) => xyn(0xD, x, y, n),
+            SkipKeyEQ(x) => xnn(0xE, x, 0x9E),
+            SkipKeyNEQ(x) => xnn(0xE, x, 0xA1),
+            SetXLong(a) => return vec![0xF0, 0x00, (a >> 8) as u8, a as u8],
+            SelectPlane(n) => xnn(0xF, n, 0x01),
+            LoadAudio => 0xF002,
+            SetRDelay(x) => xnn(0xF, x, 0x07),
+            GetKey(x) => xnn(0xF, x, 0x0A),
+            SetDelayR(x) => xnn(0xF, x, 0x15),
+            SetSoundR(x) => xnn(0xF, x, 0x18),
+            AddXR(x) => xnn(0xF, x, 0x1E),
+            SetXFontR(x) => xnn(0xF, x, 0x29),
+            SetXBigFontR(x) => xnn(0xF, x, 0x30),
+            StoreDecimalR(x) => xnn(0xF, x, 0x33),
+            SetPitchR(x) => xnn(0xF, x, 0x3A),
+            Store(x) => xnn(0xF, x, 0x55),
+            Load(x) => xnn(0xF, x, 0x65),
+            StoreFlags(x) => xnn(0xF, x, 0x75),
+            LoadFlags(x) => xnn(0xF, x, 0x85),
+            Data(a, b) => return vec![a, b],
+        };
+        word.to_be_bytes().to_vec()
+    }
+
+    /// This instruction in Octo's syntax, for `format!("{}", i.octo())`.
+    pub fn octo(self) -> Octo {
+        Octo(self)
+    }
+
+    /// The registers this instruction may read, as a mask with bit N set
+    /// for VN. Quirky instructions report every register they could read.
+    pub fn registers_read(&self) -> u16 {
+        match *self {
+            AddRI(x, _) | SkipIEQ(x, _) | SkipINEQ(x, _) | SkipKeyEQ(x) | SkipKeyNEQ(x)
+            | SetDelayR(x) | SetSoundR(x) | AddXR(x) | SetXFontR(x) | SetXBigFontR(x)
+            | StoreDecimalR(x) | SetPitchR(x) => register(x),
+            SetRR(_, y) => register(y),
+            SkipREQ(x, y) | SkipRNEQ(x, y) | OrRR(x, y) | AndRR(x, y) | XorRR(x, y) | AddRR(x, y)
+            | SubAB(x, y) | SubBA(x, y) | ShiftRightRR(x, y) | ShiftLeftRR(x, y) | Draw(x, y, _) => {
+                register(x) | register(y)
+            },
+            // V0, or VX with the jump quirk
+            JumpOffset(a) => register(0) | register((a >> 8) as u8),
+            Store(x) | StoreFlags(x) => range(0, x),
+            StoreRange(x, y) => range(x, y),
+            _ => 0,
+        }
+    }
+
+    /// The registers this instruction may write, as a mask with bit N set
+    /// for VN. Quirky instructions report every register they could write.
+    pub fn registers_written(&self) -> u16 {
+        match *self {
+            SetRI(x, _) | AddRI(x, _) | SetRR(x, _) | Random(x, _) | SetRDelay(x) | GetKey(x) => register(x),
+            OrRR(x, _) | AndRR(x, _) | XorRR(x, _) | AddRR(x, _) | SubAB(x, _) | SubBA(x, _)
+            | ShiftRightRR(x, _) | ShiftLeftRR(x, _) => register(x) | register(0xF),
+            Draw(..) | AddXR(_) => register(0xF),
+            Load(x) | LoadFlags(x) => range(0, x),
+            LoadRange(x, y) => range(x, y),
+            _ => 0,
+        }
+    }
+
+    /// Whether this instruction may change VF as a flag. Logic ops only
+    /// do with the `logic_resets_vf` quirk, `i += vX` only with
+    /// `index_overflow_sets_vf`.
+    pub fn affects_vf(&self) -> bool {
+        matches!(
+            self,
+            OrRR(..) | AndRR(..) | XorRR(..) | AddRR(..) | SubAB(..) | SubBA(..) | ShiftRightRR(..)
+                | ShiftLeftRR(..) | Draw(..) | AddXR(_)
+        )
+    }
+
+    /// Whether this instruction may continue anywhere but the next
+    /// instruction: jumps, calls, returns, skips and exit.
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self,
+            Jump(_) | JumpOffset(_) | Call(_) | Return | Exit | SkipIEQ(..) | SkipINEQ(..) | SkipREQ(..)
+                | SkipRNEQ(..) | SkipKeyEQ(_) | SkipKeyNEQ(_)
+        )
+    }
+
+    /// Where a jump or call goes, if it is known without running it.
+    pub fn branch_target(&self) -> Option<u16> {
+        match *self {
+            Jump(a) | Call(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+fn register(x: u8) -> u16 {
+    1 << (x & 0xF)
+}
+
+// VX through VY inclusive, in either order.
+fn range(x: u8, y: u8) -> u16 {
+    let (low, high) = (x.min(y) & 0xF, x.max(y) & 0xF);
+    (low..=high).fold(0, |mask, r| mask | register(r))
+}
+
+/// Canonical mnemonics, in the style of Cowgod's reference: `LD V3, 0x10`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            NOP => write!(f, "NOP"),
+            ClearScreen => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            Jump(a) => write!(f, "JP {:#05x}", a),
+            Call(a) => write!(f, "CALL {:#05x}", a),
+            SkipIEQ(x, nn) => write!(f, "SE V{:X}, {:#04x}", x, nn),
+            SkipINEQ(x, nn) => write!(f, "SNE V{:X}, {:#04x}", x, nn),
+            SkipREQ(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SkipRNEQ(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            SetRI(x, nn) => write!(f, "LD V{:X}, {:#04x}", x, nn),
+            AddRI(x, nn) => write!(f, "ADD V{:X}, {:#04x}", x, nn),
+            SetRR(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            OrRR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            AndRR(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            XorRR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddRR(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            SubAB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRightRR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubBA(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeftRR(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SetX(a) => write!(f, "LD I, {:#05x}", a),
+            JumpOffset(a) => write!(f, "JP V0, {:#05x}", a),
+            Random(x, nn) => write!(f, "RND V{:X}, {:#04x}", x, nn),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKeyEQ(x) => write!(f, "SKP V{:X}", x),
+            SkipKeyNEQ(x) => write!(f, "SKNP V{:X}", x),
+            SetRDelay(x) => write!(f, "LD V{:X}, DT", x),
+            GetKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelayR(x) => write!(f, "LD DT, V{:X}", x),
+            SetSoundR(x) => write!(f, "LD ST, V{:X}", x),
+            AddXR(x) => write!(f, "ADD I, V{:X}", x),
+            SetXFontR(x) => write!(f, "LD F, V{:X}", x),
+            StoreDecimalR(x) => write!(f, "LD B, V{:X}", x),
+            Store(x) => write!(f, "LD [I], V{:X}", x),
+            Load(x) => write!(f, "LD V{:X}, [I]", x),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            SetXBigFontR(x) => write!(f, "LD HF, V{:X}", x),
+            StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            SetXLong(a) => write!(f, "LD I, {:#06x}", a),
+            SelectPlane(n) => write!(f, "PLANE {}", n),
+            StoreRange(x, y) => write!(f, "SAVE V{:X}-V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}-V{:X}", x, y),
+            SetPitchR(x) => write!(f, "PITCH V{:X}", x),
+            LoadAudio => write!(f, "AUDIO"),
+            Data(a, b) => write!(f, "DW {:#06x}", u16::from_be_bytes([a, b])),
+        }
+    }
+}
+
+/// An instruction displayed in Octo's syntax, as the assembler reads it:
+/// `v3 := 0x10`. Instructions Octo has no words for are written as bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Octo(pub Instruction);
+
+impl fmt::Display for Octo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ClearScreen => write!(f, "clear"),
+            Return => write!(f, "return"),
+            Jump(a) => write!(f, "jump 0x{:03x}", a),
+            Call(a) => write!(f, ":call 0x{:03x}", a),
+            JumpOffset(a) => write!(f, "jump0 0x{:03x}", a),
+            SetX(a) => write!(f, "i := 0x{:03x}", a),
+            SetXLong(a) => write!(f, "i := long 0x{:04x}", a),
+            // a skip runs the next instruction only if its condition is false
+            SkipIEQ(x, nn) => write!(f, "if v{:x} != 0x{:02x} then", x, nn),
+            SkipINEQ(x, nn) => write!(f, "if v{:x} == 0x{:02x} then", x, nn),
+            SkipREQ(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+            SkipRNEQ(x, y) => write!(f, "if v{:x} == v{:x} then", x, y),
+            SkipKeyEQ(x) => write!(f, "if v{:x} -key then", x),
+            SkipKeyNEQ(x) => write!(f, "if v{:x} key then", x),
+            SetRI(x, nn) => write!(f, "v{:x} := 0x{:02x}", x, nn),
+            AddRI(x, nn) => write!(f, "v{:x} += 0x{:02x}", x, nn),
+            SetRR(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+            OrRR(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+            AndRR(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+            XorRR(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+            AddRR(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+            SubAB(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+            SubBA(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+            ShiftRightRR(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+            ShiftLeftRR(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+            Random(x, nn) => write!(f, "v{:x} := random 0x{:02x}", x, nn),
+            Draw(x, y, n) => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+            SetRDelay(x) => write!(f, "v{:x} := delay", x),
+            SetDelayR(x) => write!(f, "delay := v{:x}", x),
+            SetSoundR(x) => write!(f, "buzzer := v{:x}", x),
+            AddXR(x) => write!(f, "i += v{:x}", x),
+            GetKey(x) => write!(f, "v{:x} := key", x),
+            SetXFontR(x) => write!(f, "i := hex v{:x}", x),
+            SetXBigFontR(x) => write!(f, "i := bighex v{:x}", x),
+            StoreDecimalR(x) => write!(f, "bcd v{:x}", x),
+            Store(x) => write!(f, "save v{:x}", x),
+            Load(x) => write!(f, "load v{:x}", x),
+            StoreFlags(x) => write!(f, "saveflags v{:x}", x),
+            LoadFlags(x) => write!(f, "loadflags v{:x}", x),
+            ScrollDown(n) => write!(f, "scroll-down {}", n),
+            ScrollUp(n) => write!(f, "scroll-up {}", n),
+            ScrollRight => write!(f, "scroll-right"),
+            ScrollLeft => write!(f, "scroll-left"),
+            Exit => write!(f, "exit"),
+            LowRes => write!(f, "lores"),
+            HighRes => write!(f, "hires"),
+            SelectPlane(n) => write!(f, "plane {}", n),
+            StoreRange(x, y) => write!(f, "save v{:x} - v{:x}", x, y),
+            LoadRange(x, y) => write!(f, "load v{:x} - v{:x}", x, y),
+            SetPitchR(x) => write!(f, "pitch := v{:x}", x),
+            LoadAudio => write!(f, "audio"),
+            NOP | Data(..) => {
+                let bytes: Vec<String> = self.0.encode().iter().map(|b| format!("0x{:02x}", b)).collect();
+                write!(f, "{}", bytes.join(" "))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Every variant, with operands in the range its encoding can hold.
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        let r = || 0u8..16;
+        let nnn = || 0u16..0x1000;
+        prop_oneof![
+            Just(NOP), Just(ClearScreen), Just(Return), Just(ScrollRight), Just(ScrollLeft),
+            Just(Exit), Just(LowRes), Just(HighRes), Just(LoadAudio),
+            nnn().prop_map(Jump), nnn().prop_map(Call), nnn().prop_map(SetX), nnn().prop_map(JumpOffset),
+            any::<u16>().prop_map(SetXLong),
+            (r(), any::<u8>()).prop_map(|(x, nn)| SetRI(x, nn)),
+            (r(), any::<u8>()).prop_map(|(x, nn)| AddRI(x, nn)),
+            (r(), any::<u8>()).prop_map(|(x, nn)| SkipIEQ(x, nn)),
+            (r(), any::<u8>()).prop_map(|(x, nn)| SkipINEQ(x, nn)),
+            (r(), any::<u8>()).prop_map(|(x, nn)| Random(x, nn)),
+            (r(), r()).prop_map(|(x, y)| SkipREQ(x, y)),
+            (r(), r()).prop_map(|(x, y)| SkipRNEQ(x, y)),
+            (r(), r()).prop_map(|(x, y)| SetRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| OrRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| AndRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| XorRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| AddRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| SubAB(x, y)),
+            (r(), r()).prop_map(|(x, y)| SubBA(x, y)),
+            (r(), r()).prop_map(|(x, y)| ShiftRightRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| ShiftLeftRR(x, y)),
+            (r(), r()).prop_map(|(x, y)| StoreRange(x, y)),
+            (r(), r()).prop_map(|(x, y)| LoadRange(x, y)),
+            (r(), r(), r()).prop_map(|(x, y, n)| Draw(x, y, n)),
+            r().prop_map(SkipKeyEQ), r().prop_map(SkipKeyNEQ), r().prop_map(SetRDelay),
+            r().prop_map(SetDelayR), r().prop_map(SetSoundR), r().prop_map(AddXR), r().prop_map(GetKey),
+            r().prop_map(SetXFontR), r().prop_map(SetXBigFontR), r().prop_map(StoreDecimalR),
+            r().prop_map(Store), r().prop_map(Load), r().prop_map(StoreFlags), r().prop_map(LoadFlags),
+            r().prop_map(ScrollDown), r().prop_map(ScrollUp), r().prop_map(SelectPlane), r().prop_map(SetPitchR),
+            // only the words that are no instruction are data
+            any::<[u8; 2]>()
+                .prop_filter("an instruction", |raw| matches!(Instruction::decode_word(*raw, Platform::XoChip), Data(..)))
+                .prop_map(|[a, b]| Data(a, b)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn decode_inverts_encode(instruction in instruction()) {
+            let bytes = instruction.encode();
+            prop_assert_eq!(Instruction::decode(&bytes, Platform::XoChip), Some((instruction, bytes.len())));
+        }
+
+        // The decoder ignores the low nibble of 5XY0 and 9XY0, so not every
+        // word re-encodes the same, but it always decodes the same again.
+        #[test]
+        fn decoded_words_reencode(raw in any::<[u8; 4]>()) {
+            for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+                let (instruction, len) = Instruction::decode(&raw, platform).unwrap();
+                prop_assert_eq!(Instruction::decode(&instruction.encode(), platform), Some((instruction, len)));
+            }
+        }
+    }
+
+    #[test]
+    fn writes_mnemonics() {
+        assert_eq!(SetRI(3, 0x10).to_string(), "LD V3, 0x10");
+        assert_eq!(Draw(0xA, 0xB, 5).to_string(), "DRW VA, VB, 5");
+        assert_eq!(SetX(0x2A4).to_string(), "LD I, 0x2a4");
+        assert_eq!(SetXLong(0x1234).to_string(), "LD I, 0x1234");
+        assert_eq!(Data(0x81, 0x2F).to_string(), "DW 0x812f");
+        assert_eq!(SetRI(3, 0x10).octo().to_string(), "v3 := 0x10");
+        assert_eq!(SkipKeyEQ(4).octo().to_string(), "if v4 -key then");
+        assert_eq!(Data(0x81, 0x2F).octo().to_string(), "0x81 0x2f");
+    }
+
+    #[test]
+    fn reports_operands() {
+        assert_eq!(SubBA(1, 2).registers_read(), 0b110);
+        assert_eq!(SubBA(1, 2).registers_written(), 0x8002);
+        assert!(SubBA(1, 2).affects_vf());
+        assert_eq!(Store(2).registers_read(), 0b111);
+        assert_eq!(LoadRange(5, 3).registers_written(), 0b111000);
+        assert_eq!(JumpOffset(0x3A0).registers_read(), 0b1001);
+        assert!(!SetRI(0xF, 1).affects_vf());
+        assert_eq!(SetRI(0xF, 1).registers_written(), 0x8000);
+        assert!(SkipREQ(1, 2).is_branch() && Return.is_branch());
+        assert!(!Draw(0, 0, 1).is_branch());
+        assert_eq!(Call(0x300).branch_target(), Some(0x300));
+        assert_eq!(Return.branch_target(), None);
+    }
+}
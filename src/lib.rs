@@ -0,0 +1,66 @@
+//! A CHIP-8 interpreter.
+//!
+//! The library is headless: it has no windowing or audio dependencies and can
+//! be driven from tests, tools or any frontend. The piston frontend in
+//! `main.rs` is built with the default `gui` feature.
+//!
+//! ```no_run
+//! use rust_chip8::{Chip8, Quirks};
+//!
+//! let mut chip8 = Chip8::new(Quirks::vip());
+//! chip8.load(std::fs::read("game.ch8").unwrap()).expect("ROM too large");
+//! loop {
+//!     chip8.set_key(0x5, true);
+//!     chip8.run_frame(11).expect("ROM crashed");
+//!     let _pixels = chip8.framebuffer();
+//! #   break;
+//! }
+//! ```
+
+pub mod assembler;
+pub mod audio;
+pub mod cartridge;
+mod bytes;
+pub mod core;
+pub mod dap;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod gdb;
+pub mod instruction;
+pub mod movie;
+pub mod palette;
+pub mod platform;
+pub mod quirks;
+pub mod render;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+pub mod source_map;
+pub mod timing;
+
+pub use crate::assembler::{assemble, AssembleError, Assembly};
+pub use crate::audio::{AudioFrame, AudioSink, NullSink, ToneConfig, ToneGenerator, WavSink, Waveform};
+pub use crate::cartridge::{Cartridge, CartridgeError};
+pub use crate::core::{AccessKind, Engine, MemoryAccess, CPU, DISPLAY_BUFFER, DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+pub use crate::dap::DapServer;
+pub use crate::database::{DatabaseError, RomDatabase, RomInfo};
+pub use crate::debugger::Debugger;
+pub use crate::disasm::disassemble;
+pub use crate::error::{ExecError, LoadError, StepOutcome};
+pub use crate::gdb::GdbStub;
+pub use crate::instruction::{Instruction, Octo};
+pub use crate::movie::{InputEvent, Movie, MovieError, MoviePlayer, MovieRecorder};
+pub use crate::palette::Palette;
+pub use crate::platform::Platform;
+pub use crate::quirks::Quirks;
+pub use crate::render::Renderer;
+pub use crate::rewind::RewindBuffer;
+pub use crate::rom::RomHash;
+pub use crate::savestate::{Snapshot, StateError};
+pub use crate::source_map::{SourceLine, SourceMap, SourceMapError};
+pub use crate::timing::{FrameClock, FRAME_RATE};
+
+/// The CHIP-8 machine. An alias for [`CPU`], which holds the whole machine state.
+pub type Chip8 = CPU;
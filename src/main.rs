@@ -1,128 +1,307 @@
+#[cfg(feature = "gui")]
 extern crate piston_window;
-mod core;
-mod instruction;
 
-use graphics::{Graphics, Context};
-use opengl_graphics::{OpenGL, GlGraphics};
-use piston::{EventSettings, Events, WindowSettings, RenderEvent, RenderArgs, Button, PressEvent, Key, AdvancedWindow, ReleaseEvent};
-use piston_window::PistonWindow;
+#[cfg(feature = "beeper")]
+mod beeper;
+mod cli;
+#[cfg(feature = "gui")]
+mod gui;
+mod headless;
 
-use crate::core::{DISPLAY_HEIGHT, DISPLAY_WIDTH, CPU};
-use std::{fs::read, time::{SystemTime, Duration}};
+use clap::Parser;
+use rust_chip8::{Cartridge, CPU, DapServer, Debugger, ExecError, GdbStub, Movie, MoviePlayer, MovieRecorder, NullSink, Palette, RomDatabase, RomHash};
+use std::{collections::BTreeMap, env::var_os, fmt::Display, fs::{read, read_to_string, write}, io::{stdout, Write}, path::{absolute, Path, PathBuf}, process::exit};
 
-const IPS: u64 = 700;
+use crate::cli::Args;
 
-fn main() {
-    let mut cpu = CPU::new();
-
-    // Get rom
-    let rom = read("/Users/bweeks/code/rust_chip8/roms/tetris.ch8").unwrap(); 
-    cpu.load(rom);
-
-    // Setup graphics
-    let mut event_settings = EventSettings::new();
-    event_settings.max_fps = 60;
-    event_settings.ups = IPS;
-    let mut events = Events::new(event_settings);
-    
-    let opengl = OpenGL::V3_2;
-    let mut window: PistonWindow = WindowSettings::new(
-        "CHIP-8",
-        [640 , 320]
-    ).exit_on_esc(true)
-    .graphics_api(opengl)
-    .build()
-    .unwrap();
-    let mut gl = GlGraphics::new(opengl);
-    let mut running = true; 
-    let mut last_tick = SystemTime::now();
-    let delay_duration = Duration::new(0, 160000000);
-    
-    // Event loop
-    while let Some(e) = events.next(&mut window) {
-        if running {
-            cpu.step();
-        }
+// Instructions per frame when neither the command line nor the ROM says.
+const DEFAULT_CYCLES_PER_FRAME: usize = 11;
+
+/// The machine being run, with the movie being played back or recorded and
+/// the debugger, GDB stub or debug adapter, if there is one.
+pub struct Session {
+    pub cpu: CPU,
+    pub player: Option<MoviePlayer>,
+    pub recorder: Option<MovieRecorder>,
+    pub debugger: Option<Debugger>,
+    pub gdb: Option<GdbStub>,
+    pub dap: Option<DapServer>,
+    pub cycles_per_frame: usize,
+    /// Colors from the command line, the cartridge or the ROM database.
+    pub palette: Palette,
+    /// CHIP-8 keys for the arrow keys and buttons, by the ROM database's
+    /// names for them.
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl Session {
+    /// Builds the machine described by the command line and loads the ROM,
+    /// exiting with a message if anything is missing or invalid.
+    fn start(args: &Args) -> Self {
+        let movie = args.play.as_ref().map(|path| {
+            Movie::load(path).unwrap_or_else(|err| fail(format!("Could not load movie {}: {}", path.display(), err)))
+        });
+
+        let Machine { cpu, cycles_per_frame, palette, keys } = Machine::load(args, &args.rom, movie.as_ref()).unwrap_or_else(|err| fail(err));
+        let player = movie.map(|movie| {
+            MoviePlayer::new(movie, &cpu).unwrap_or_else(|err| fail(format!("Could not play movie: {}", err)))
+        });
+        let recorder = args.record.as_ref().map(|_| MovieRecorder::new(&cpu, cycles_per_frame as u32));
+        let debugger = args.debug.then(|| {
+            println!("Debugger stopped at {:#06x}; type help for commands", cpu.pc());
+            Debugger::new()
+        });
+        let gdb = args.gdb.map(|port| {
+            let gdb = GdbStub::bind(("127.0.0.1", port)).unwrap_or_else(|err| fail(format!("Could not listen for GDB on port {}: {}", port, err)));
+            println!("Waiting for GDB on {}", gdb.local_addr().map_or_else(|_| port.to_string(), |a| a.to_string()));
+            gdb
+        });
+        let dap = args.dap.map(|port| {
+            println!("Waiting for an editor on 127.0.0.1:{}", port);
+            DapServer::accept(("127.0.0.1", port)).unwrap_or_else(|err| fail(format!("Could not accept an editor on port {}: {}", port, err)))
+        });
+        Session { cpu, player, recorder, debugger, gdb, dap, cycles_per_frame, palette, keys }
+    }
+
+    pub fn movie_active(&self) -> bool {
+        self.player.is_some() || self.recorder.is_some()
+    }
+
+    /// Whether the debugger, GDB or the editor is holding the machine still.
+    pub fn is_stopped(&self) -> bool {
+        self.debugger.as_ref().is_some_and(Debugger::is_paused)
+            || self.gdb.as_ref().is_some_and(GdbStub::is_stopped)
+            || self.dap.as_ref().is_some_and(DapServer::is_stopped)
+    }
+
+    /// Whether the editor has asked the emulator to exit.
+    pub fn quit_requested(&self) -> bool {
+        self.dap.as_ref().is_some_and(DapServer::quit_requested)
+    }
 
-        // Delay
-        let now = SystemTime::now();
-        if now.duration_since(last_tick).ok().unwrap() > delay_duration {
-            last_tick = now;
-            if cpu.delay_timer > 0 {
-                cpu.delay_timer -= 1;
+    /// Handles anything GDB or the editor has sent. A lost connection is
+    /// reported and the machine carries on running. A program the editor
+    /// launches is set up as one given on the command line would be.
+    pub fn poll_remote(&mut self, args: &Args) {
+        if let Some(gdb) = self.gdb.as_mut() {
+            if let Err(err) = gdb.poll(&mut self.cpu) {
+                println!("GDB: {}", err);
             }
         }
-        
-        if let Some(args) = e.render_args() {
-            gl.draw(args.viewport(), |c, g| {
-                draw_screen(args, c, g, &cpu.display);
-            });
+        if let Some(dap) = self.dap.as_mut() {
+            if let Err(err) = dap.poll(&mut self.cpu) {
+                println!("Editor: {}", err);
+            }
+        }
+        if let Some(path) = self.dap.as_mut().and_then(DapServer::take_launched) {
+            self.relaunch(args, &path);
         }
+    }
 
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            let key_hex = hex_for_key(key);
-            if key_hex >= 0 {
-                let key_index = key_hex as usize;
-                cpu.keys[key_index] = 1;
-            }
+    // Sets the machine up again for a program the editor launched. Any movie
+    // stops playing, and recording starts over from the new power-on.
+    fn relaunch(&mut self, args: &Args, path: &Path) {
+        let Machine { mut cpu, cycles_per_frame, palette, keys } = match Machine::load(args, path, None) {
+            Ok(machine) => machine,
+            Err(err) => {
+                println!("Editor: {}", err);
+                return;
+            },
+        };
+        cpu.set_audio_sink(self.cpu.set_audio_sink(Box::new(NullSink)));
+        self.cpu = cpu;
+        self.cycles_per_frame = cycles_per_frame;
+        self.palette = palette;
+        self.keys = keys;
+        self.player = None;
+        if self.recorder.is_some() {
+            self.recorder = Some(MovieRecorder::new(&self.cpu, cycles_per_frame as u32));
+        }
+    }
 
-            // Debug keys
-            if key == Key::Space {
-                running = !running;
-            }
+    /// Feeds movie input and runs one frame.
+    ///
+    /// Under the debugger nothing runs while it is paused, and breakpoints and
+    /// faults stop the machine at the prompt instead of returning an error.
+    /// Under GDB or an editor they are reported there.
+    pub fn run_frame(&mut self) -> Result<(), ExecError> {
+        if self.is_stopped() {
+            return Ok(());
+        }
+        if let Some(player) = self.player.as_mut() {
+            player.apply(&mut self.cpu);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&self.cpu);
+        }
+        if let Some(gdb) = self.gdb.as_mut() {
+            return gdb.run_frame(&mut self.cpu, self.cycles_per_frame);
+        }
+        if let Some(dap) = self.dap.as_mut() {
+            return dap.run_frame(&mut self.cpu, self.cycles_per_frame);
+        }
+        let Some(debugger) = self.debugger.as_mut() else {
+            return self.cpu.run_frame(self.cycles_per_frame);
+        };
+        let mut out = stdout();
+        let result = match debugger.run_frame(&mut self.cpu, self.cycles_per_frame) {
+            Ok(Some(stop)) => debugger.report(&self.cpu, &stop, &mut out),
+            Ok(None) => Ok(()),
+            Err(err) => writeln!(out, "CPU fault: {}", err),
+        };
+        if let Err(err) = result {
+            fail(format!("Could not write to the terminal: {}", err));
+        }
+        Ok(())
+    }
 
-            if key == Key::P {
-                cpu.dump_registers();
+    /// Saves the recording, if there is one.
+    pub fn finish(self, args: &Args) {
+        if let (Some(recorder), Some(path)) = (self.recorder, args.record.as_ref()) {
+            match recorder.finish().save(path) {
+                Ok(()) => println!("Saved movie to {}", path.display()),
+                Err(err) => println!("Could not save movie: {}", err),
             }
         }
+    }
+}
 
-        if let Some(Button::Keyboard(key)) = e.release_args() {
-            let key_hex = hex_for_key(key);
-            if key_hex >= 0 {
-                let key_index = key_hex as usize;
-                cpu.keys[key_index] = 0;
-            }
+/// A machine with a program loaded, and the settings it runs with.
+struct Machine {
+    cpu: CPU,
+    cycles_per_frame: usize,
+    palette: Palette,
+    keys: BTreeMap<String, u8>,
+}
+
+impl Machine {
+    /// Builds the machine for the ROM at `rom_path` and loads it. A movie
+    /// being played gives its own settings.
+    fn load(args: &Args, rom_path: &Path, movie: Option<&Movie>) -> Result<Self, String> {
+        let rom = read(rom_path).map_err(|err| format!("Could not read {}: {}", rom_path.display(), err))?;
+        // A broken cartridge is reported by load. A cartridge is known by
+        // its program, as save states and movies know it.
+        let cartridge = Cartridge::parse(&rom).ok();
+        let hash = RomHash::of(cartridge.as_ref().map_or(&rom, |c| &c.rom));
+        let known = rom_database(args).get(hash).cloned();
+        if let Some(known) = &known {
+            println!("Recognized {}", known.title);
         }
+
+        // The command line wins, then a cartridge's own settings, then the
+        // database's.
+        let platform = args.platform()
+            .or(cartridge.as_ref().map(|c| c.platform))
+            .or(known.as_ref().and_then(|k| k.platform))
+            .unwrap_or_default();
+        // the database's quirks are for the platform it gives
+        let quirks = args.quirks()
+            .or(known.as_ref().filter(|k| k.platform == Some(platform)).and_then(|k| k.quirks))
+            .unwrap_or_else(|| platform.default_quirks());
+        let mut cpu = match movie {
+            Some(movie) => movie.header.new_machine(),
+            None => CPU::with_platform(platform, quirks),
+        };
+        let rom_len = rom.len();
+        cpu.load(rom).map_err(|err| format!("Could not load {}: {}", rom_path.display(), err))?;
+        // loading a cartridge applies its quirks, which a movie or the command line overrides
+        match (movie, args.quirks()) {
+            (Some(movie), _) => cpu.set_quirks(movie.header.quirks),
+            (None, Some(quirks)) => cpu.set_quirks(quirks),
+            (None, None) => {},
+        }
+        cpu.set_engine(args.engine());
+
+        let cycles_per_frame = match movie {
+            Some(movie) => movie.header.cycles_per_frame as usize,
+            None => args.cycles_per_frame
+                .or(cartridge.as_ref().map(|c| c.cycles_per_frame))
+                .or(known.as_ref().and_then(|k| k.cycles_per_frame))
+                .unwrap_or(DEFAULT_CYCLES_PER_FRAME),
+        };
+        let palette = cartridge.as_ref().map(|c| c.palette)
+            .or(known.as_ref().and_then(|k| k.palette))
+            .unwrap_or_default();
+        let palette = args.palette(palette);
+        let keys = known.map(|k| k.keys).unwrap_or_default();
+        println!("Loaded {} bytes into memory", cartridge.map_or(rom_len, |c| c.rom.len()));
+        Ok(Machine { cpu, cycles_per_frame, palette, keys })
     }
 }
 
-fn hex_for_key(key: Key) -> i32 {
-    match key {
-        Key::D1 => {0x1},
-        Key::D2 => {0x2},
-        Key::D3 => {0x3},
-        Key::D4 => {0xC},
-        Key::Q => {0x4},
-        Key::W => {0x5},
-        Key::E => {0x6},
-        Key::R => {0xD},
-        Key::A => {0x7},
-        Key::S => {0x8},
-        Key::D => {0x9},
-        Key::F => {0xE},
-        Key::Z => {0xA},
-        Key::X => {0x0},
-        Key::C => {0xB},
-        Key::V => {0xF}
-        _ => {-1}
+fn main() {
+    let args = Args::parse();
+    if args.disassemble {
+        disassemble(&args);
+        return;
+    }
+    if let Some(output) = args.assemble.as_ref() {
+        assemble(&args.rom, output);
+        return;
+    }
+    let session = Session::start(&args);
+    if args.headless {
+        headless::run(&args, session);
+    } else {
+        run_window(&args, session);
     }
 }
 
+fn disassemble(args: &Args) {
+    let rom = read(&args.rom).unwrap_or_else(|err| fail(format!("Could not read {}: {}", args.rom.display(), err)));
+    let known = rom_database(args).get(RomHash::of(&rom)).and_then(|k| k.platform);
+    match rust_chip8::disassemble(&rom, args.platform().or(known).unwrap_or_default()) {
+        Ok(source) => print!("{}", source),
+        Err(err) => fail(format!("Could not disassemble {}: {}", args.rom.display(), err)),
+    }
+}
 
-fn draw_screen<G: Graphics>(args: RenderArgs, c: Context, g: &mut G, d: &[u8]) {
-    use graphics::{clear, rectangle};
-    clear([0.0, 0.0, 0.0, 1.0], g);
-    for y in 0..DISPLAY_HEIGHT {
-        for x in 0..DISPLAY_WIDTH {
-            let pix = d[y*DISPLAY_WIDTH + x];
-            if pix > 0 {
-                rectangle(
-                    [1.0, 1.0, 1.0, 1.0], 
-                    rectangle::square((x * 10) as f64, (y * 10) as f64, 9.0),
-                    c.transform, 
-                    g
-                )
-            }
+fn assemble(source_path: &Path, output: &Path) {
+    let source = read_to_string(source_path).unwrap_or_else(|err| fail(format!("Could not read {}: {}", source_path.display(), err)));
+    let assembly = rust_chip8::assemble(&source).unwrap_or_else(|err| fail(format!("{}: {}", source_path.display(), err)));
+    // the source map points editors at the source wherever they run from
+    let source_path = absolute(source_path).unwrap_or_else(|_| source_path.to_path_buf());
+    let files = [
+        (output.to_path_buf(), assembly.rom.clone()),
+        (output.with_extension("sym"), assembly.symbols().into_bytes()),
+        (output.with_extension("map"), assembly.source_map(&source_path).to_string().into_bytes()),
+    ];
+    for (path, contents) in files {
+        if let Err(err) = write(&path, contents) {
+            fail(format!("Could not write {}: {}", path.display(), err));
+        }
+    }
+    println!("Assembled {} bytes into {}", assembly.rom.len(), output.display());
+}
+
+/// The bundled ROM database, with the one from `--database` or the user's
+/// config directory layered over it.
+fn rom_database(args: &Args) -> RomDatabase {
+    let mut database = RomDatabase::bundled();
+    let user = args.database.clone().or_else(|| {
+        let config = var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config.join("rust_chip8").join("programs.json")).filter(|path| path.exists())
+    });
+    if let Some(path) = user {
+        match RomDatabase::load(&path) {
+            Ok(user) => database.merge(user),
+            Err(err) => fail(format!("Could not load ROM database {}: {}", path.display(), err)),
         }
     }
+    database
+}
+
+#[cfg(feature = "gui")]
+fn run_window(args: &Args, session: Session) {
+    gui::run(args, session);
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_window(_args: &Args, _session: Session) {
+    fail("Built without the gui feature; run with --headless");
+}
+
+fn fail<T: Display>(message: T) -> ! {
+    eprintln!("{}", message);
+    exit(1);
 }
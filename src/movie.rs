@@ -0,0 +1,292 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::bytes::{ByteReader, ByteWriter};
+use crate::core::CPU;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rom::RomHash;
+use crate::savestate::{platform_from_id, platform_id};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+/// Version written by [`Movie::to_bytes`].
+pub const MOVIE_VERSION: u16 = 1;
+
+/// Everything needed to rebuild the machine a movie was recorded on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieHeader {
+    pub rom_hash: RomHash,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub rng_seed: u64,
+    pub cycles_per_frame: u32,
+}
+
+impl MovieHeader {
+    /// A machine configured exactly as the recording one was at power-on,
+    /// ready for the ROM to be loaded.
+    pub fn new_machine(&self) -> CPU {
+        let mut cpu = CPU::with_platform(self.platform, self.quirks);
+        cpu.seed_rng(self.rng_seed);
+        cpu
+    }
+}
+
+/// A change of keypad state at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Frames since power-on.
+    pub frame: u64,
+    /// One bit per key, bit 0 for key 0x0.
+    pub keys: u16,
+}
+
+/// A recorded run: the machine configuration and every keypad change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub events: Vec<InputEvent>,
+}
+
+/// Why a movie could not be loaded or played.
+#[derive(Debug)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The movie was recorded with a different ROM.
+    RomMismatch { expected: RomHash, found: RomHash },
+    Corrupt,
+    Io(io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "movie version {} is not supported (expected {})", v, MOVIE_VERSION)
+            },
+            MovieError::RomMismatch { expected, found } => {
+                write!(f, "movie was recorded with ROM {} but {} is loaded", found, expected)
+            },
+            MovieError::Corrupt => write!(f, "movie is truncated or corrupt"),
+            MovieError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = ByteWriter::new();
+        w.slice(MAGIC);
+        w.u16(MOVIE_VERSION);
+        w.slice(&self.header.rom_hash.0);
+        w.u8(platform_id(self.header.platform));
+        w.u8(self.header.quirks.to_bits());
+        w.u64(self.header.rng_seed);
+        w.u32(self.header.cycles_per_frame);
+        w.u32(self.events.len() as u32);
+        for event in &self.events {
+            w.u64(event.frame);
+            w.u16(event.keys);
+        }
+        w.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut r = ByteReader::new(bytes);
+        if r.slice(MAGIC.len()) != Some(MAGIC.as_slice()) {
+            return Err(MovieError::BadMagic);
+        }
+        let version = r.u16().ok_or(MovieError::Corrupt)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        Self::read_body(&mut r).ok_or(MovieError::Corrupt)
+    }
+
+    fn read_body(r: &mut ByteReader) -> Option<Self> {
+        let header = MovieHeader {
+            rom_hash: RomHash(r.array()?),
+            platform: platform_from_id(r.u8()?)?,
+            quirks: Quirks::from_bits(r.u8()?),
+            rng_seed: r.u64()?,
+            cycles_per_frame: r.u32()?,
+        };
+        let count = r.u32()?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(InputEvent { frame: r.u64()?, keys: r.u16()? });
+        }
+        if !r.is_empty() {
+            return None;
+        }
+        Some(Movie { header, events })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
+
+fn key_bits(cpu: &CPU) -> u16 {
+    (0..16).filter(|k| cpu.is_key_pressed(*k)).fold(0, |bits, k| bits | 1 << k)
+}
+
+/// Records keypad changes while a game runs.
+///
+/// Start it on a freshly loaded machine and call [`MovieRecorder::record`]
+/// before every frame.
+pub struct MovieRecorder {
+    movie: Movie,
+    last_keys: Option<u16>,
+}
+
+impl MovieRecorder {
+    pub fn new(cpu: &CPU, cycles_per_frame: u32) -> Self {
+        let header = MovieHeader {
+            rom_hash: cpu.rom_hash(),
+            platform: cpu.platform(),
+            quirks: cpu.quirks(),
+            rng_seed: cpu.rng_seed(),
+            cycles_per_frame,
+        };
+        MovieRecorder { movie: Movie { header, events: Vec::new() }, last_keys: None }
+    }
+
+    /// Notes the keypad state for the frame about to run, if it changed.
+    pub fn record(&mut self, cpu: &CPU) {
+        let keys = key_bits(cpu);
+        if self.last_keys != Some(keys) {
+            self.movie.events.push(InputEvent { frame: cpu.frame_count(), keys });
+            self.last_keys = Some(keys);
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds a recorded movie's keypad changes back into a machine.
+pub struct MoviePlayer {
+    movie: Movie,
+    next_event: usize,
+}
+
+impl MoviePlayer {
+    /// Checks that `cpu` has the movie's ROM loaded.
+    pub fn new(movie: Movie, cpu: &CPU) -> Result<Self, MovieError> {
+        if movie.header.rom_hash != cpu.rom_hash() {
+            return Err(MovieError::RomMismatch { expected: cpu.rom_hash(), found: movie.header.rom_hash });
+        }
+        Ok(MoviePlayer { movie, next_event: 0 })
+    }
+
+    pub fn header(&self) -> &MovieHeader {
+        &self.movie.header
+    }
+
+    /// Sets the keypad for the frame about to run.
+    pub fn apply(&mut self, cpu: &mut CPU) {
+        while let Some(event) = self.movie.events.get(self.next_event) {
+            if event.frame > cpu.frame_count() {
+                break;
+            }
+            for key in 0..16 {
+                cpu.set_key(key, event.keys & (1 << key) > 0);
+            }
+            self.next_event += 1;
+        }
+    }
+
+    /// Whether every recorded input has been played back.
+    pub fn is_finished(&self) -> bool {
+        self.next_event >= self.movie.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200: V0 = random, V1 = 5; while key V1 is held, V2 += V0
+    const ROM: [u8; 12] = [0xC0, 0xFF, 0x61, 0x05, 0xE1, 0x9E, 0x12, 0x00, 0x82, 0x04, 0x12, 0x00];
+    const CYCLES: u32 = 9;
+
+    fn machine(rom: &[u8]) -> CPU {
+        let mut cpu = CPU::with_platform(Platform::Chip8, Quirks::vip());
+        cpu.seed_rng(0x5EED);
+        cpu.load(rom.to_vec()).unwrap();
+        cpu
+    }
+
+    fn record(cpu: &mut CPU, frames: u64) -> Movie {
+        let mut recorder = MovieRecorder::new(cpu, CYCLES);
+        for frame in 0..frames {
+            cpu.set_key(5, frame % 7 < 3);
+            cpu.set_key(0xA, frame % 11 == 0);
+            recorder.record(cpu);
+            cpu.run_frame(CYCLES as usize).unwrap();
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn replays_to_the_same_state() {
+        let mut recording = machine(&ROM);
+        let movie = record(&mut recording, 120);
+        assert_ne!(recording.register(2), 0);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let mut replay = movie.header.new_machine();
+        replay.load(ROM.to_vec()).unwrap();
+        let mut player = MoviePlayer::new(movie, &replay).unwrap();
+        for _ in 0..120 {
+            player.apply(&mut replay);
+            replay.run_frame(player.header().cycles_per_frame as usize).unwrap();
+        }
+        assert!(player.is_finished());
+        assert_eq!(replay.snapshot(), recording.snapshot());
+    }
+
+    #[test]
+    fn rejects_other_roms_and_files() {
+        let movie = record(&mut machine(&ROM), 10);
+        let other = machine(&[0x12, 0x00]);
+        assert!(matches!(
+            MoviePlayer::new(movie.clone(), &other),
+            Err(MovieError::RomMismatch { expected, found }) if expected == other.rom_hash() && found == movie.header.rom_hash
+        ));
+
+        let bytes = movie.to_bytes();
+        assert!(matches!(Movie::from_bytes(b"C8ST\x01\x00"), Err(MovieError::BadMagic)));
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(MOVIE_VERSION + 1).to_le_bytes());
+        assert!(matches!(Movie::from_bytes(&newer), Err(MovieError::UnsupportedVersion(v)) if v == MOVIE_VERSION + 1));
+        for len in 4..bytes.len() {
+            assert!(matches!(Movie::from_bytes(&bytes[..len]), Err(MovieError::Corrupt)), "{} bytes", len);
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(Movie::from_bytes(&trailing), Err(MovieError::Corrupt)));
+    }
+}
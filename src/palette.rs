@@ -0,0 +1,69 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An RGB color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    /// Channels scaled to 0.0-1.0 with full alpha, as graphics APIs expect.
+    pub fn to_rgba_f32(self) -> [f32; 4] {
+        [self.0 as f32 / 255.0, self.1 as f32 / 255.0, self.2 as f32 / 255.0, 1.0]
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parses `rrggbb` or `#rrggbb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' is not a color, expected rrggbb", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Color(channel(0), channel(2), channel(4)))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// The colors a framebuffer pixel value (0-3) is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Unlit pixels.
+    pub background: Color,
+    /// Pixels lit in the first bitplane, the only one outside XO-CHIP.
+    pub foreground: Color,
+    /// XO-CHIP pixels lit only in the second bitplane.
+    pub plane2: Color,
+    /// XO-CHIP pixels lit in both bitplanes.
+    pub blend: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: Color(0x00, 0x00, 0x00),
+            foreground: Color(0xFF, 0xFF, 0xFF),
+            plane2: Color(0xFF, 0x66, 0x00),
+            blend: Color(0x66, 0x22, 0x00),
+        }
+    }
+}
+
+impl Palette {
+    /// The color for a framebuffer pixel value.
+    pub fn color(&self, pixel: u8) -> Color {
+        match pixel & 0x3 {
+            0 => self.background,
+            1 => self.foreground,
+            2 => self.plane2,
+            _ => self.blend,
+        }
+    }
+}
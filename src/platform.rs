@@ -0,0 +1,45 @@
+use crate::quirks::Quirks;
+
+/// The CHIP-8 dialect the machine emulates. Instructions that do not exist on
+/// the selected platform decode as data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    /// The original 64x32 CHIP-8.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: 128x64 high resolution mode, scrolling, 16x16 sprites,
+    /// the large font and RPL user flags.
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes, long index
+    /// loads, register range save/load and the audio pattern buffer.
+    XoChip,
+}
+
+impl Platform {
+    /// The quirks profile ROMs for this platform usually expect.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::vip(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    /// Bytes of addressable memory.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    /// Whether the SUPER-CHIP instructions are available.
+    pub fn has_schip_instructions(self) -> bool {
+        self != Platform::Chip8
+    }
+
+    /// Whether the XO-CHIP instructions are available.
+    pub fn has_xo_instructions(self) -> bool {
+        self == Platform::XoChip
+    }
+}
@@ -0,0 +1,103 @@
+/// Behaviour toggles for instructions that differ between CHIP-8 interpreters.
+///
+/// ROMs written for the COSMAC VIP and for SUPER-CHIP disagree on these, so
+/// pick the profile the ROM was written for. `Quirks::default()` keeps the
+/// behaviour this interpreter has always had.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY and store the result in VX, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// `FX55`/`FX65` leave I pointing just past the last register saved or loaded.
+    pub memory_increments_i: bool,
+    /// `BXNN` jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites that cross the edge of the screen wrap around instead of being clipped.
+    pub wrap_sprites: bool,
+    /// `DXYN` waits for the next 60 Hz frame before the program continues.
+    pub display_wait: bool,
+    /// `FX1E` sets VF when I goes past 0xFFF.
+    pub index_overflow_sets_vf: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+            index_overflow_sets_vf: true,
+        }
+    }
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            display_wait: true,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP48.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    // One bit per quirk, in field order, for the binary file formats.
+    pub(crate) fn to_bits(self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.memory_increments_i,
+            self.jump_uses_vx,
+            self.logic_resets_vf,
+            self.wrap_sprites,
+            self.display_wait,
+            self.index_overflow_sets_vf,
+        ].iter().enumerate().fold(0, |bits, (i, on)| bits | (*on as u8) << i)
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        let bit = |i: u8| bits & (1 << i) > 0;
+        Quirks {
+            shift_uses_vy: bit(0),
+            memory_increments_i: bit(1),
+            jump_uses_vx: bit(2),
+            logic_resets_vf: bit(3),
+            wrap_sprites: bit(4),
+            display_wait: bit(5),
+            index_overflow_sets_vf: bit(6),
+        }
+    }
+}
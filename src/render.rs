@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::core::{CPU, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::palette::{Color, Palette};
+
+/// A lit CHIP-8 pixel as a filled square in window coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: usize,
+    pub y: usize,
+    pub size: usize,
+    pub color: Color,
+}
+
+/// Lays out the framebuffer at a given scale and palette.
+///
+/// The window frontend and the PNG writer both draw from
+/// [`Renderer::lit_pixels`], so screenshots look exactly like the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renderer {
+    pub palette: Palette,
+    /// Window pixels per CHIP-8 pixel in low resolution.
+    pub scale: usize,
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, scale: usize) -> Self {
+        Renderer { palette, scale }
+    }
+
+    /// Size of the window in window pixels. It doesn't change with the
+    /// resolution: high resolution pixels are drawn half the size.
+    pub fn output_size(&self) -> (usize, usize) {
+        (DISPLAY_WIDTH * self.scale, DISPLAY_HEIGHT * self.scale)
+    }
+
+    /// Size of the display as it is drawn now, in window pixels. At an odd
+    /// scale, high resolution pixels are rounded down and cover a little
+    /// less than the window.
+    pub fn image_size(&self, cpu: &CPU) -> (usize, usize) {
+        let cell = self.cell(cpu.display_width());
+        (cpu.display_width() * cell, cpu.display_height() * cell)
+    }
+
+    // Window pixels per CHIP-8 pixel at a display width.
+    fn cell(&self, width: usize) -> usize {
+        (self.scale * DISPLAY_WIDTH / width).max(1)
+    }
+
+    /// The squares to draw over the background. Each is one window pixel
+    /// smaller than its cell, leaving a faint grid between CHIP-8 pixels.
+    pub fn lit_pixels(&self, cpu: &CPU) -> Vec<PixelRect> {
+        let width = cpu.display_width();
+        let cell = self.cell(width);
+        let mut rects = Vec::new();
+        for (i, pixel) in cpu.framebuffer().iter().enumerate() {
+            if pixel & 0x3 > 0 {
+                rects.push(PixelRect {
+                    x: (i % width) * cell,
+                    y: (i / width) * cell,
+                    size: cell.saturating_sub(1).max(1),
+                    color: self.palette.color(*pixel),
+                });
+            }
+        }
+        rects
+    }
+
+    /// Renders the display to tightly packed 8-bit RGB, [`Renderer::image_size`] pixels.
+    pub fn render_rgb(&self, cpu: &CPU) -> Vec<u8> {
+        let (width, height) = self.image_size(cpu);
+        let background = self.palette.background;
+        let mut rgb: Vec<u8> = (0..width * height).flat_map(|_| [background.0, background.1, background.2]).collect();
+        for rect in self.lit_pixels(cpu) {
+            for y in rect.y..(rect.y + rect.size).min(height) {
+                for x in rect.x..(rect.x + rect.size).min(width) {
+                    let i = (y * width + x) * 3;
+                    rgb[i..i + 3].copy_from_slice(&[rect.color.0, rect.color.1, rect.color.2]);
+                }
+            }
+        }
+        rgb
+    }
+
+    /// Writes the display to a PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, cpu: &CPU, path: P) -> io::Result<()> {
+        let (width, height) = self.image_size(cpu);
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&self.render_rgb(cpu)).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use std::io::BufReader;
+
+    // Draws the font's 0 at the top left, after switching to high resolution if `hires`.
+    fn machine(hires: bool) -> CPU {
+        let mut cpu = CPU::with_platform(Platform::SuperChip, Quirks::schip());
+        let mode = if hires {0xFF} else {0xFE};
+        cpu.load(vec![0x00, mode, 0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x08]).unwrap();
+        cpu.run_frame(10).unwrap();
+        cpu
+    }
+
+    // Writes a screenshot and reads it back as its size and RGB pixels.
+    fn screenshot(renderer: &Renderer, cpu: &CPU, name: &str) -> (usize, usize, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!("rust_chip8-{}-{}.png", std::process::id(), name));
+        renderer.save_png(cpu, &path).unwrap();
+        let mut reader = png::Decoder::new(BufReader::new(File::open(&path).unwrap())).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgb).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (info.width as usize, info.height as usize, rgb)
+    }
+
+    #[test]
+    fn screenshots_match_the_window() {
+        let palette = Palette::default();
+        let lit = [palette.foreground.0, palette.foreground.1, palette.foreground.2];
+        for (hires, scale, cell) in [(false, 3, 3), (true, 3, 1), (true, 4, 2), (true, 2, 1)] {
+            let renderer = Renderer::new(palette, scale);
+            let cpu = machine(hires);
+            let (width, height, rgb) = screenshot(&renderer, &cpu, &format!("{}-{}", hires, scale));
+            assert_eq!((width, height), renderer.image_size(&cpu));
+            assert_eq!((width, height), (cpu.display_width() * cell, cpu.display_height() * cell));
+            assert!(width <= renderer.output_size().0 && height <= renderer.output_size().1);
+
+            // every lit rect is drawn where the window draws it, and nothing else is
+            let rects = renderer.lit_pixels(&cpu);
+            assert_eq!(rects.len(), 14);
+            let drawn = (0..width * height).filter(|i| rgb[i * 3..i * 3 + 3] == lit).count();
+            assert_eq!(drawn, rects.iter().map(|r| r.size * r.size).sum::<usize>(), "hires {} scale {}", hires, scale);
+            for rect in rects {
+                assert_eq!(rgb[(rect.y * width + rect.x) * 3..][..3], lit);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use crate::core::CPU;
+use crate::savestate::Snapshot;
+use crate::timing::FRAME_RATE;
+
+/// A ring buffer of recent machine states for playing a game backwards.
+///
+/// Only the newest state is kept whole. Each older frame is stored as the
+/// difference from the frame after it, which is small because most of memory
+/// and the display don't change from one frame to the next.
+pub struct RewindBuffer {
+    // serialized newest state
+    current: Option<Vec<u8>>,
+    // deltas.back() turns `current` into the frame before it, and so on
+    deltas: VecDeque<Vec<u8>>,
+    max_frames: usize,
+    memory_budget: usize,
+    used: usize,
+}
+
+impl RewindBuffer {
+    /// Keeps up to `seconds` of frames, using at most `memory_budget` bytes for the deltas.
+    pub fn new(seconds: u32, memory_budget: usize) -> Self {
+        RewindBuffer {
+            current: None,
+            deltas: VecDeque::new(),
+            max_frames: (seconds as usize).saturating_mul(FRAME_RATE as usize),
+            memory_budget,
+            used: 0,
+        }
+    }
+
+    /// Number of frames that can currently be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Bytes held by the buffer, including the newest full state.
+    pub fn memory_used(&self) -> usize {
+        self.used + self.current.as_ref().map_or(0, |c| c.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    /// Records the machine's state. Call once per frame, after running it.
+    pub fn push(&mut self, cpu: &CPU) {
+        let state = cpu.snapshot().to_bytes();
+        if let Some(previous) = self.current.take() {
+            if previous.len() == state.len() {
+                let delta = encode_delta(&state, &previous);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                // a different machine was loaded; the old history is useless
+                self.deltas.clear();
+                self.used = 0;
+            }
+        }
+        self.current = Some(state);
+        while self.deltas.len() > self.max_frames || (self.used > self.memory_budget && !self.deltas.is_empty()) {
+            if let Some(oldest) = self.deltas.pop_front() {
+                self.used -= oldest.len();
+            }
+        }
+    }
+
+    /// Moves the machine back one frame. Returns false when there is nothing
+    /// left to rewind or the recorded state doesn't fit the machine.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> bool {
+        let (Some(current), Some(delta)) = (self.current.as_ref(), self.deltas.back()) else {
+            return false;
+        };
+        let previous = apply_delta(current, delta);
+        let restored = Snapshot::from_bytes(&previous)
+            .ok()
+            .is_some_and(|snapshot| cpu.restore(&snapshot).is_ok());
+        if !restored {
+            self.clear();
+            return false;
+        }
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used -= delta.len();
+        }
+        self.current = Some(previous);
+        true
+    }
+}
+
+// The delta is the XOR of the two states, run-length encoded as pairs of
+// (run of unchanged bytes, literal changed bytes), each length a LEB128 varint.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < from.len() {
+        let start = i;
+        while i < from.len() && from[i] == to[i] {
+            i += 1;
+        }
+        let same = i - start;
+        let literal_start = i;
+        while i < from.len() && from[i] != to[i] {
+            i += 1;
+        }
+        write_varint(&mut out, same);
+        write_varint(&mut out, i - literal_start);
+        out.extend(from[literal_start..i].iter().zip(&to[literal_start..i]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut out = base.to_vec();
+    let mut position = 0;
+    let mut d = 0;
+    while d < delta.len() {
+        position += read_varint(delta, &mut d);
+        let literal = read_varint(delta, &mut d);
+        for byte in &delta[d..d + literal] {
+            out[position] ^= byte;
+            position += 1;
+        }
+        d += literal;
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = bytes.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use proptest::prelude::*;
+
+    // 200: V0 = random, I = font for V0, draw it, jump back
+    const ROM: [u8; 8] = [0xC0, 0x0F, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+
+    fn machine() -> CPU {
+        let mut cpu = CPU::with_platform(Platform::Chip8, Platform::Chip8.default_quirks());
+        cpu.seed_rng(3);
+        cpu.load(ROM.to_vec()).unwrap();
+        cpu
+    }
+
+    proptest! {
+        #[test]
+        fn deltas_restore_the_older_state(
+            to in prop::collection::vec(any::<u8>(), 0..600),
+            changes in prop::collection::vec((any::<usize>(), any::<u8>()), 0..40),
+        ) {
+            let mut from = to.clone();
+            for (i, byte) in changes {
+                if !from.is_empty() {
+                    let len = from.len();
+                    from[i % len] = byte;
+                }
+            }
+            prop_assert_eq!(apply_delta(&from, &encode_delta(&from, &to)), to);
+        }
+    }
+
+    #[test]
+    fn varints_round_trip_at_their_edges() {
+        for (value, len) in [(0, 1), (0x7F, 1), (0x80, 2), (0x3FFF, 2), (0x4000, 3), (usize::MAX, 10)] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(bytes.len(), len, "{:#x}", value);
+            let mut position = 0;
+            assert_eq!(read_varint(&bytes, &mut position), value);
+            assert_eq!(position, len);
+        }
+    }
+
+    #[test]
+    fn rewinds_to_earlier_frames_exactly() {
+        let mut cpu = machine();
+        let mut rewind = RewindBuffer::new(10, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..30 {
+            cpu.run_frame(7).unwrap();
+            rewind.push(&cpu);
+            states.push(cpu.snapshot());
+        }
+        assert_eq!(rewind.len(), 29);
+        for k in 1..=5 {
+            assert!(rewind.rewind(&mut cpu));
+            assert_eq!(cpu.snapshot(), states[29 - k], "{} frames back", k);
+        }
+        // running on from there records over the frames rewound past
+        cpu.run_frame(7).unwrap();
+        rewind.push(&cpu);
+        assert_eq!(rewind.len(), 25);
+        assert!(rewind.rewind(&mut cpu));
+        assert_eq!(cpu.snapshot(), states[24]);
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_to_fit() {
+        let mut cpu = machine();
+        let mut by_length = RewindBuffer::new(1, usize::MAX);
+        let mut by_budget = RewindBuffer::new(10, 200);
+        for _ in 0..100 {
+            cpu.run_frame(7).unwrap();
+            by_length.push(&cpu);
+            by_budget.push(&cpu);
+        }
+        assert_eq!(by_length.len(), FRAME_RATE as usize);
+        assert!(!by_budget.is_empty() && by_budget.len() < 99);
+        assert!(by_budget.memory_used() - cpu.save_state().len() <= 200);
+
+        // what is left still rewinds, and then runs out
+        let len = by_budget.len();
+        for _ in 0..len {
+            assert!(by_budget.rewind(&mut cpu));
+        }
+        assert!(!by_budget.rewind(&mut cpu));
+        assert_eq!(by_budget.memory_used(), cpu.save_state().len());
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// SHA-1 of a ROM image, identifying the game in save states, movies and the
+/// ROM database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct RomHash(pub [u8; 20]);
+
+impl RomHash {
+    pub fn of(rom: &[u8]) -> Self {
+        RomHash(sha1_smol::Sha1::from(rom).digest().bytes())
+    }
+
+    /// Parses 40 hex digits, in either case.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 40 || !hex.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(RomHash(bytes))
+    }
+
+    /// Lowercase hex, as used by the community CHIP-8 database.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl fmt::Display for RomHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
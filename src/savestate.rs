@@ -0,0 +1,341 @@
+use std::fmt;
+use std::io;
+
+use crate::bytes::{ByteReader, ByteWriter};
+use crate::core::{DISPLAY_BUFFER, STACK_SIZE};
+use crate::platform::Platform;
+use crate::rom::RomHash;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+/// Version written by [`Snapshot::to_bytes`]. Older versions are rejected.
+pub const STATE_VERSION: u16 = 2;
+
+/// A copy of everything that makes up the running machine.
+///
+/// Taken with [`CPU::snapshot`](crate::CPU::snapshot) and put back with
+/// [`CPU::restore`](crate::CPU::restore). Quirks and the audio sink are
+/// configuration, not state, and are not included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) platform: Platform,
+    pub(crate) rom_hash: RomHash,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) pc: usize,
+    pub(crate) stack: [usize; STACK_SIZE],
+    pub(crate) sp: usize,
+    pub(crate) index_register: u16,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) general_registers: [u8; 16],
+    pub(crate) display: [u8; DISPLAY_BUFFER],
+    pub(crate) keys: [u8; 16],
+    pub(crate) hires: bool,
+    pub(crate) flags: [u8; 16],
+    pub(crate) halted: bool,
+    pub(crate) frame: u64,
+    pub(crate) plane_mask: u8,
+    pub(crate) audio_pattern: Option<[u8; 16]>,
+    pub(crate) pitch: u8,
+    pub(crate) waiting_for_vblank: bool,
+    pub(crate) rng_seed: u64,
+    pub(crate) rng_position: u128,
+}
+
+/// Why a save state could not be loaded.
+#[derive(Debug)]
+pub enum StateError {
+    /// The data is not a save state.
+    BadMagic,
+    /// The save state was written by an incompatible version of the emulator.
+    UnsupportedVersion(u16),
+    /// The save state belongs to a different ROM.
+    RomMismatch { expected: RomHash, found: RomHash },
+    /// The save state was made on a different platform.
+    PlatformMismatch { expected: Platform, found: Platform },
+    /// The data ends early or contains values out of range.
+    Corrupt,
+    Io(io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => {
+                write!(f, "save state version {} is not supported (expected {})", v, STATE_VERSION)
+            },
+            StateError::RomMismatch { expected, found } => {
+                write!(f, "save state is for ROM {} but {} is loaded", found, expected)
+            },
+            StateError::PlatformMismatch { expected, found } => {
+                write!(f, "save state is for {:?} but the machine is {:?}", found, expected)
+            },
+            StateError::Corrupt => write!(f, "save state is truncated or corrupt"),
+            StateError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> Self {
+        StateError::Io(err)
+    }
+}
+
+impl Snapshot {
+    /// The ROM that was loaded when the snapshot was taken.
+    pub fn rom_hash(&self) -> RomHash {
+        self.rom_hash
+    }
+
+    /// The frame the snapshot was taken at.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Serializes the snapshot into the versioned save state format.
+    ///
+    /// The header is the magic `C8ST`, the format version and the SHA-1 of
+    /// the ROM, followed by the machine state. The display is packed four
+    /// pixels to a byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = ByteWriter::new();
+        w.slice(MAGIC);
+        w.u16(STATE_VERSION);
+        w.slice(&self.rom_hash.0);
+        w.u8(platform_id(self.platform));
+        w.u32(self.pc as u32);
+        w.u8(self.sp as u8);
+        for address in self.stack {
+            w.u32(address as u32);
+        }
+        w.u16(self.index_register);
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        w.slice(&self.general_registers);
+        w.slice(&self.keys);
+        w.u8(self.hires as u8);
+        w.slice(&self.flags);
+        w.u8(self.halted as u8);
+        w.u64(self.frame);
+        w.u8(self.plane_mask);
+        match self.audio_pattern {
+            Some(pattern) => {
+                w.u8(1);
+                w.slice(&pattern);
+            },
+            None => w.u8(0),
+        }
+        w.u8(self.pitch);
+        w.u8(self.waiting_for_vblank as u8);
+        w.u64(self.rng_seed);
+        w.slice(&self.rng_position.to_le_bytes());
+        w.u32(self.memory.len() as u32);
+        w.slice(&self.memory);
+        for pixels in self.display.chunks(4) {
+            w.u8(pixels.iter().enumerate().fold(0, |b, (i, p)| b | (p & 0x3) << (i * 2)));
+        }
+        w.bytes
+    }
+
+    /// Parses a save state written by [`Snapshot::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut r = ByteReader::new(bytes);
+        if r.slice(MAGIC.len()) != Some(MAGIC.as_slice()) {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16().ok_or(StateError::Corrupt)?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Self::read_body(&mut r).ok_or(StateError::Corrupt)
+    }
+
+    fn read_body(r: &mut ByteReader) -> Option<Self> {
+        let rom_hash = RomHash(r.array()?);
+        let platform = platform_from_id(r.u8()?)?;
+        let pc = r.u32()? as usize;
+        let sp = r.u8()? as usize;
+        let mut stack = [0; STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = r.u32()? as usize;
+        }
+        let index_register = r.u16()?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let general_registers = r.array()?;
+        let keys = r.array()?;
+        let hires = r.u8()? != 0;
+        let flags = r.array()?;
+        let halted = r.u8()? != 0;
+        let frame = r.u64()?;
+        let plane_mask = r.u8()?;
+        let audio_pattern = match r.u8()? {
+            0 => None,
+            _ => Some(r.array()?),
+        };
+        let pitch = r.u8()?;
+        let waiting_for_vblank = r.u8()? != 0;
+        let rng_seed = r.u64()?;
+        let rng_position = u128::from_le_bytes(r.array()?);
+        let memory_len = r.u32()? as usize;
+        if memory_len != platform.memory_size() || sp > STACK_SIZE || pc >= memory_len {
+            return None;
+        }
+        let memory = r.slice(memory_len)?.to_vec();
+        let mut display = [0; DISPLAY_BUFFER];
+        let packed = r.slice(DISPLAY_BUFFER / 4)?;
+        for (i, pixel) in display.iter_mut().enumerate() {
+            *pixel = (packed[i / 4] >> ((i % 4) * 2)) & 0x3;
+        }
+        if !r.is_empty() {
+            return None;
+        }
+        Some(Snapshot {
+            platform,
+            rom_hash,
+            memory,
+            pc,
+            stack,
+            sp,
+            index_register,
+            delay_timer,
+            sound_timer,
+            general_registers,
+            display,
+            keys,
+            hires,
+            flags,
+            halted,
+            frame,
+            plane_mask,
+            audio_pattern,
+            pitch,
+            waiting_for_vblank,
+            rng_seed,
+            rng_position,
+        })
+    }
+}
+
+pub(crate) fn platform_id(platform: Platform) -> u8 {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+pub(crate) fn platform_from_id(id: u8) -> Option<Platform> {
+    match id {
+        0 => Some(Platform::Chip8),
+        1 => Some(Platform::SuperChip),
+        2 => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::CPU;
+
+    // 200: V0 = random, I = font for V0, draw it, jump back
+    const ROM: [u8; 8] = [0xC0, 0x0F, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+    // where the platform id and stack depth are in a save state
+    const PLATFORM_OFFSET: usize = 26;
+    const SP_OFFSET: usize = 31;
+
+    fn machine(platform: Platform, rom: &[u8]) -> CPU {
+        let mut cpu = CPU::with_platform(platform, platform.default_quirks());
+        cpu.seed_rng(7);
+        cpu.load(rom.to_vec()).unwrap();
+        cpu
+    }
+
+    fn running(frames: usize) -> CPU {
+        let mut cpu = machine(Platform::Chip8, &ROM);
+        cpu.set_key(3, true);
+        for _ in 0..frames {
+            cpu.run_frame(7).unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let saved = running(5);
+        let bytes = saved.save_state();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot, saved.snapshot());
+        assert_eq!(snapshot.to_bytes(), bytes);
+
+        // restoring rewinds the machine, random number generator included
+        let mut cpu = running(5);
+        for _ in 0..10 {
+            cpu.run_frame(7).unwrap();
+        }
+        cpu.load_state(&bytes).unwrap();
+        assert_eq!(cpu.snapshot(), saved.snapshot());
+        let mut saved = saved;
+        for _ in 0..10 {
+            cpu.run_frame(7).unwrap();
+            saved.run_frame(7).unwrap();
+        }
+        assert_eq!(cpu.snapshot(), saved.snapshot());
+    }
+
+    #[test]
+    fn rejects_other_roms_and_platforms_untouched() {
+        let bytes = running(5).save_state();
+
+        let mut other_rom = machine(Platform::Chip8, &[0x12, 0x00]);
+        let before = other_rom.snapshot();
+        assert!(matches!(
+            other_rom.load_state(&bytes),
+            Err(StateError::RomMismatch { expected, found }) if expected == before.rom_hash && found == RomHash::of(&ROM)
+        ));
+        assert_eq!(other_rom.snapshot(), before);
+
+        let mut other_platform = machine(Platform::SuperChip, &ROM);
+        let before = other_platform.snapshot();
+        assert!(matches!(
+            other_platform.load_state(&bytes),
+            Err(StateError::PlatformMismatch { expected: Platform::SuperChip, found: Platform::Chip8 })
+        ));
+        assert_eq!(other_platform.snapshot(), before);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = running(1).save_state();
+        // version 1 states were written before the random number generator was saved
+        for version in [1, STATE_VERSION + 1] {
+            bytes[4..6].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(Snapshot::from_bytes(&bytes), Err(StateError::UnsupportedVersion(v)) if v == version));
+        }
+        assert!(matches!(Snapshot::from_bytes(b"C8MV\x02\x00"), Err(StateError::BadMagic)));
+    }
+
+    #[test]
+    fn rejects_truncated_and_corrupt_states() {
+        let mut cpu = running(3);
+        let before = cpu.snapshot();
+        let bytes = cpu.save_state();
+        for len in 4..bytes.len() {
+            assert!(matches!(cpu.load_state(&bytes[..len]), Err(StateError::Corrupt)), "{} bytes", len);
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        let mut bad_platform = bytes.clone();
+        bad_platform[PLATFORM_OFFSET] = 9;
+        let mut deep_stack = bytes.clone();
+        deep_stack[SP_OFFSET] = STACK_SIZE as u8 + 1;
+        for corrupt in [longer, bad_platform, deep_stack] {
+            assert!(matches!(cpu.load_state(&corrupt), Err(StateError::Corrupt)));
+        }
+        assert_eq!(cpu.snapshot(), before);
+    }
+}
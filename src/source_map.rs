@@ -0,0 +1,157 @@
+//! Maps between ROM addresses and the assembler source lines they came from,
+//! so debuggers can set breakpoints on lines and show where the machine is.
+//!
+//! The text form has one instruction per line, an address followed by the
+//! file and line that produced it. Relative paths are relative to the map:
+//!
+//! ```text
+//! # address  source
+//! 0x0200     game.8o:12
+//! 0x0202     game.8o:13
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One instruction's source location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub address: usize,
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// Source locations for a ROM, in address order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: Vec<SourceLine>,
+}
+
+/// Why a source map could not be loaded.
+#[derive(Debug)]
+pub enum SourceMapError {
+    /// Line `line` of the map is not an address and a location.
+    Syntax { line: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceMapError::Syntax { line } => write!(f, "line {} should be ADDRESS FILE:LINE", line),
+            SourceMapError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SourceMapError {}
+
+impl From<io::Error> for SourceMapError {
+    fn from(err: io::Error) -> Self {
+        SourceMapError::Io(err)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a map, resolving relative paths against its directory.
+    pub fn load(path: &Path) -> Result<Self, SourceMapError> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Parses the text form, resolving relative paths against `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<Self, SourceMapError> {
+        let mut map = SourceMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let syntax = || SourceMapError::Syntax { line: n + 1 };
+            let (address, location) = line.split_once(char::is_whitespace).ok_or_else(syntax)?;
+            let address = address.strip_prefix("0x").unwrap_or(address);
+            let address = usize::from_str_radix(address, 16).map_err(|_| syntax())?;
+            let (path, number) = location.trim().rsplit_once(':').ok_or_else(syntax)?;
+            let number = number.parse().map_err(|_| syntax())?;
+            map.insert(address, base.join(path), number);
+        }
+        Ok(map)
+    }
+
+    /// Records that the instruction at `address` came from `line` of `path`.
+    pub fn insert<P: Into<PathBuf>>(&mut self, address: usize, path: P, line: usize) {
+        let entry = SourceLine { address, path: path.into(), line };
+        match self.lines.binary_search_by_key(&address, |l| l.address) {
+            Ok(i) => self.lines[i] = entry,
+            Err(i) => self.lines.insert(i, entry),
+        }
+    }
+
+    pub fn lines(&self) -> &[SourceLine] {
+        &self.lines
+    }
+
+    /// Where the instruction at `address` came from.
+    pub fn location(&self, address: usize) -> Option<&SourceLine> {
+        self.lines.binary_search_by_key(&address, |l| l.address).ok().map(|i| &self.lines[i])
+    }
+
+    /// The first instruction on `line` of `path`, or on the next line that
+    /// has one, as breakpoints on blank lines and comments usually mean.
+    pub fn address_of(&self, path: &Path, line: usize) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .filter(|l| l.line >= line && same_file(&l.path, path))
+            .min_by_key(|l| (l.line, l.address))
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{:#06x} {}:{}", line.address, line.path.display(), line.line)?;
+        }
+        Ok(())
+    }
+}
+
+// Editors send absolute paths, which may not be spelled like the ones in the map.
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_looks_up_lines() {
+        let text = "# game\n0x0200 game.8o:3\n202 game.8o:4\n0x0206 game.8o:7\n0x0208 lib.8o:1\n";
+        let map = SourceMap::parse(text, Path::new("/src")).unwrap();
+        assert_eq!(map.lines().len(), 4);
+        assert_eq!(map.location(0x202).map(|l| l.line), Some(4));
+        assert_eq!(map.location(0x204), None);
+
+        let game = Path::new("/src/game.8o");
+        assert_eq!(map.address_of(game, 4).map(|l| l.address), Some(0x202));
+        assert_eq!(map.address_of(game, 5).map(|l| (l.address, l.line)), Some((0x206, 7)));
+        assert_eq!(map.address_of(game, 8), None);
+        assert_eq!(SourceMap::parse(&map.to_string(), Path::new("/")).unwrap(), map);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for text in ["0x200", "0x200 game.8o", "zz game.8o:1", "0x200 game.8o:x"] {
+            assert!(matches!(SourceMap::parse(text, Path::new("")), Err(SourceMapError::Syntax { line: 1 })), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Rate of the CHIP-8 timers and of the display, in frames per second.
+pub const FRAME_RATE: u32 = 60;
+
+/// Converts host time into a whole number of 60 Hz emulated frames.
+///
+/// The frontend reports how much wall-clock time has passed and runs
+/// [`CPU::run_frame`](crate::CPU::run_frame) once per frame returned, so the
+/// emulation speed depends only on the frame count and not on how often the
+/// host loop happens to update.
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    frame_duration: Duration,
+    pending: Duration,
+    max_frames: u32,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameClock {
+    /// A clock running at [`FRAME_RATE`] that catches up at most four frames at once.
+    pub fn new() -> Self {
+        FrameClock {
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            pending: Duration::ZERO,
+            max_frames: 4,
+        }
+    }
+
+    /// Limits how many frames a single [`FrameClock::advance`] may return.
+    /// Time beyond that is dropped, so a stalled host (a dragged window, a
+    /// breakpoint) doesn't make the game run fast afterwards.
+    pub fn with_max_frames(mut self, max_frames: u32) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    /// Adds `elapsed` host time and returns how many frames are now due.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.pending += elapsed;
+        let mut frames = 0;
+        while self.pending >= self.frame_duration {
+            self.pending -= self.frame_duration;
+            frames += 1;
+        }
+        if frames > self.max_frames {
+            self.pending = Duration::ZERO;
+            frames = self.max_frames;
+        }
+        frames
+    }
+}
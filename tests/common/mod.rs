@@ -0,0 +1,196 @@
+//! Shared harness for the conformance tests: runs a ROM headless for a number
+//! of frames and compares the final screen with a checked-in golden file.
+//!
+//! Golden files live in `tests/golden/<name>.txt`, one line per display row,
+//! using `.` for unlit pixels and `#` for lit ones (XO-CHIP screens use `1`,
+//! `2` and `3` for the plane bits). Run the tests with `UPDATE_GOLDEN=1` to
+//! write the current output as the new golden.
+
+#![allow(dead_code)]
+
+use rust_chip8::{Platform, Quirks, RomHash, CPU};
+use std::{env, fs, path::PathBuf};
+
+/// A ROM, the machine to run it on and the input to feed it.
+pub struct Case {
+    name: String,
+    rom: Vec<u8>,
+    platform: Platform,
+    quirks: Quirks,
+    frames: u64,
+    cycles_per_frame: usize,
+    keys: Vec<(u64, u16)>,
+}
+
+impl Case {
+    /// Loads `tests/roms/<file>`; the golden is named after the file stem.
+    pub fn rom(file: &str) -> Self {
+        let path = fixture_dir("roms").join(file);
+        let rom = fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+        let name = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+        Case::bytes(name, rom)
+    }
+
+    /// A ROM assembled inline in the test.
+    pub fn bytes(name: &str, rom: Vec<u8>) -> Self {
+        Case {
+            name: name.to_string(),
+            rom,
+            platform: Platform::Chip8,
+            quirks: Platform::Chip8.default_quirks(),
+            frames: 60,
+            cycles_per_frame: 11,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Sets the platform along with its default quirks.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self.quirks = platform.default_quirks();
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Names the golden file, for ROMs checked under several configurations.
+    pub fn golden(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn frames(mut self, frames: u64) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub fn cycles_per_frame(mut self, cycles: usize) -> Self {
+        self.cycles_per_frame = cycles;
+        self
+    }
+
+    /// Holds exactly the keys in the `keys` bitmask from `frame` onwards.
+    pub fn keys(mut self, frame: u64, keys: u16) -> Self {
+        self.keys.push((frame, keys));
+        self
+    }
+
+    /// Runs the ROM and returns the machine, panicking if it faults.
+    pub fn run(&self) -> CPU {
+        let mut cpu = CPU::with_platform(self.platform, self.quirks);
+        cpu.seed_rng(0);
+        cpu.load(self.rom.clone()).expect("ROM too large");
+        for frame in 0..self.frames {
+            for &(_, keys) in self.keys.iter().filter(|(at, _)| *at == frame) {
+                for key in 0..16 {
+                    cpu.set_key(key, keys & (1 << key) != 0);
+                }
+            }
+            if let Err(err) = cpu.run_frame(self.cycles_per_frame) {
+                panic!("{}: {} at frame {}", self.name, err, frame);
+            }
+        }
+        cpu
+    }
+
+    /// Runs the ROM and compares the screen with `tests/golden/<name>.txt`.
+    pub fn check(&self) {
+        let actual = screen_to_ascii(&self.run());
+        let path = fixture_dir("golden").join(format!("{}.txt", self.name));
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, &actual).unwrap_or_else(|err| panic!("Could not write {}: {}", path.display(), err));
+            return;
+        }
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Could not read {} ({}); run with UPDATE_GOLDEN=1 to create it", path.display(), err));
+        if let Some(diff) = screen_diff(&expected, &actual) {
+            panic!("{}: screen does not match {}\n{}", self.name, path.display(), diff);
+        }
+    }
+
+    /// Runs the ROM and compares the SHA-1 of the framebuffer with `expected`.
+    pub fn check_hash(&self, expected: &str) {
+        let cpu = self.run();
+        let actual = screen_hash(&cpu);
+        if actual != expected {
+            panic!("{}: screen hash {} does not match {}\n{}", self.name, actual, expected, screen_to_ascii(&cpu));
+        }
+    }
+}
+
+/// Renders the visible display as one line of text per row.
+pub fn screen_to_ascii(cpu: &CPU) -> String {
+    let width = cpu.display_width();
+    let xo = cpu.platform() == Platform::XoChip;
+    let mut out = String::new();
+    for row in cpu.framebuffer().chunks(width) {
+        for &pixel in row {
+            out.push(match pixel {
+                0 => '.',
+                1 if !xo => '#',
+                p => char::from_digit(p as u32, 10).unwrap_or('?'),
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn screen_hash(cpu: &CPU) -> String {
+    RomHash::of(cpu.framebuffer()).to_hex()
+}
+
+/// Describes how `actual` differs from `expected`, or `None` if they match.
+///
+/// Matching pixels are drawn as in the golden; `+` marks a pixel lit only in
+/// the actual screen, `-` one lit only in the golden and `*` one lit in both
+/// but with a different plane value.
+pub fn screen_diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let expected_rows: Vec<&str> = expected.lines().collect();
+    let actual_rows: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    if expected_rows.len() != actual_rows.len() || expected_rows.first().map(|r| r.len()) != actual_rows.first().map(|r| r.len()) {
+        out.push_str(&format!(
+            "size differs: expected {}x{}, got {}x{}\n",
+            expected_rows.first().map_or(0, |r| r.len()),
+            expected_rows.len(),
+            actual_rows.first().map_or(0, |r| r.len()),
+            actual_rows.len()
+        ));
+    }
+    let mut mismatched = 0;
+    for y in 0..expected_rows.len().max(actual_rows.len()) {
+        let e: Vec<char> = expected_rows.get(y).map_or(Vec::new(), |r| r.chars().collect());
+        let a: Vec<char> = actual_rows.get(y).map_or(Vec::new(), |r| r.chars().collect());
+        let mut line = String::new();
+        let mut row_differs = false;
+        for x in 0..e.len().max(a.len()) {
+            let ep = e.get(x).copied().unwrap_or('.');
+            let ap = a.get(x).copied().unwrap_or('.');
+            line.push(match (ep, ap) {
+                (ep, ap) if ep == ap => ep,
+                ('.', _) => '+',
+                (_, '.') => '-',
+                _ => '*',
+            });
+            if ep != ap {
+                row_differs = true;
+                mismatched += 1;
+            }
+        }
+        out.push_str(if row_differs {"> "} else {"  "});
+        out.push_str(&line);
+        out.push('\n');
+    }
+    Some(format!("{} pixel(s) differ (+ unexpected, - missing, * wrong plane):\n{}", mismatched, out))
+}
+
+fn fixture_dir(kind: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join(kind)
+}
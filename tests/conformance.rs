@@ -0,0 +1,66 @@
+//! Golden-screen conformance tests. The fixtures in `tests/roms` are
+//! hand-assembled; their listings are in `tests/roms/README.md`.
+
+mod common;
+
+use common::Case;
+use rust_chip8::{Platform, Quirks};
+
+#[test]
+fn font() {
+    Case::rom("font.ch8").check();
+}
+
+#[test]
+fn bcd() {
+    Case::rom("bcd.ch8").check_hash("f213cf2c7c1d3df072845d1b6fed96bd8bdc6999");
+}
+
+#[test]
+fn flags() {
+    Case::rom("flags.ch8").check();
+}
+
+#[test]
+fn memory() {
+    Case::rom("memory.ch8").check();
+}
+
+#[test]
+fn keypad() {
+    Case::rom("keypad.ch8")
+        .keys(5, 1 << 0x5)
+        .keys(6, 0)
+        .keys(10, 1 << 0xA)
+        .keys(11, 0)
+        .frames(20)
+        .check();
+}
+
+#[test]
+fn quirks_vip() {
+    Case::rom("quirks.ch8").golden("quirks_vip").quirks(Quirks::vip()).check();
+}
+
+#[test]
+fn quirks_schip() {
+    Case::rom("quirks.ch8").golden("quirks_schip").quirks(Quirks::schip()).check();
+}
+
+#[test]
+fn schip_hires() {
+    Case::rom("hires.sc8").platform(Platform::SuperChip).check();
+}
+
+#[test]
+fn xochip_planes() {
+    Case::rom("planes.xo8").platform(Platform::XoChip).check();
+}
+
+#[test]
+fn diff_marks_changed_pixels() {
+    let diff = common::screen_diff("#.\n..\n", "..\n.#\n").expect("screens differ");
+    assert!(diff.contains("> -."));
+    assert!(diff.contains("> .+"));
+    assert!(common::screen_diff("#.\n", "#.\n").is_none());
+}
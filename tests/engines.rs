@@ -0,0 +1,96 @@
+//! Differential tests: the block engine must leave the machine in exactly
+//! the state the interpreter does, frame by frame.
+
+use proptest::prelude::*;
+use rust_chip8::{Engine, ExecError, Platform, Quirks, CPU};
+use std::fs;
+use std::path::PathBuf;
+
+fn machine(platform: Platform, quirks: Quirks, engine: Engine, rom: &[u8]) -> CPU {
+    let mut cpu = CPU::with_platform(platform, quirks);
+    cpu.set_engine(engine);
+    cpu.seed_rng(7);
+    cpu.load(rom.to_vec()).expect("ROM too large");
+    cpu
+}
+
+/// Runs `rom` on both engines in lock step, pressing `keys` (a bitmask) from
+/// the given frames, and fails at the first frame where they differ. Returns
+/// the fault that stopped both, if any.
+fn run_both(platform: Platform, quirks: Quirks, rom: &[u8], frames: u64, cycles: usize, keys: &[(u64, u16)]) -> Option<ExecError> {
+    let mut interpreter = machine(platform, quirks, Engine::Interpreter, rom);
+    let mut blocks = machine(platform, quirks, Engine::Blocks, rom);
+    for frame in 0..frames {
+        for &(_, held) in keys.iter().filter(|(at, _)| *at == frame) {
+            for key in 0..16 {
+                interpreter.set_key(key, held & (1 << key) != 0);
+                blocks.set_key(key, held & (1 << key) != 0);
+            }
+        }
+        let expected = interpreter.run_frame(cycles);
+        let actual = blocks.run_frame(cycles);
+        assert_eq!(actual, expected, "result differs at frame {}", frame);
+        assert!(blocks.snapshot() == interpreter.snapshot(), "state differs at frame {}", frame);
+        if expected.is_err() {
+            return expected.err();
+        }
+    }
+    None
+}
+
+fn rom(file: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(file);
+    fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err))
+}
+
+#[test]
+fn test_roms_match() {
+    for file in ["font.ch8", "bcd.ch8", "flags.ch8", "memory.ch8", "keypad.ch8", "quirks.ch8"] {
+        for quirks in [Quirks::vip(), Quirks::schip(), Quirks::default()] {
+            assert_eq!(run_both(Platform::Chip8, quirks, &rom(file), 120, 11, &[(5, 1 << 0x5), (6, 0), (10, 1 << 0xA)]), None, "{}", file);
+        }
+    }
+    assert_eq!(run_both(Platform::SuperChip, Quirks::schip(), &rom("hires.sc8"), 120, 30, &[]), None);
+    assert_eq!(run_both(Platform::XoChip, Quirks::xochip(), &rom("planes.xo8"), 120, 100, &[]), None);
+}
+
+#[test]
+fn code_written_by_the_program_matches() {
+    let program = [
+        0x70, 0x01, // V0 += 1
+        0x60, 0x71, // V0 = 0x71
+        0x61, 0x05, // V1 = 5
+        0xA2, 0x00, // I = 0x200
+        0xF1, 0x55, // store V0-V1 over the first instruction: V1 += 5
+        0x12, 0x00, // jump 200
+    ];
+    // an odd number of cycles stops frames part way through blocks
+    assert_eq!(run_both(Platform::Chip8, Quirks::default(), &program, 20, 5, &[]), None);
+}
+
+#[test]
+fn faults_match() {
+    // runs off the end of memory
+    assert!(run_both(Platform::Chip8, Quirks::default(), &[0x1F, 0xFE], 2, 11, &[]).is_some());
+    // 0x8009 is not an instruction
+    assert!(run_both(Platform::Chip8, Quirks::default(), &[0x60, 0x01, 0x80, 0x09], 2, 11, &[]).is_some());
+}
+
+proptest! {
+    // Random code, mostly arithmetic and branches within the program, with
+    // stores that rewrite it as it runs.
+    #[test]
+    fn random_programs_match(
+        rom in prop::collection::vec(any::<u8>(), 2..96),
+        platform in prop_oneof![Just(Platform::Chip8), Just(Platform::SuperChip), Just(Platform::XoChip)],
+        cycles in 1usize..40,
+        keys in any::<u16>(),
+    ) {
+        // point addresses back into the program so more of it runs
+        let rom: Vec<u8> = rom.chunks(2).flat_map(|word| match word {
+            [hi, lo] if matches!(hi >> 4, 0x1 | 0x2 | 0xA | 0xB) => vec![hi & 0xF0 | 0x2, (*lo % rom.len() as u8) & 0xFE],
+            _ => word.to_vec(),
+        }).collect();
+        run_both(platform, platform.default_quirks(), &rom, 30, cycles, &[(10, keys)]);
+    }
+}
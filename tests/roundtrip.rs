@@ -0,0 +1,43 @@
+//! Disassembling each ROM in `tests/roms` and assembling the result must give
+//! back the same bytes.
+
+use rust_chip8::{assemble, disassemble, Platform};
+use std::fs;
+use std::path::Path;
+
+fn platform_for(path: &Path) -> Platform {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("sc8") => Platform::SuperChip,
+        Some("xo8") => Platform::XoChip,
+        _ => Platform::Chip8,
+    }
+}
+
+#[test]
+fn disassembly_reassembles_to_the_same_rom() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let mut checked = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "md") {
+            continue;
+        }
+        let rom = fs::read(&path).unwrap();
+        let source = disassemble(&rom, platform_for(&path)).unwrap();
+        let assembly = assemble(&source).unwrap_or_else(|err| panic!("{}: {}\n{}", path.display(), err, source));
+        assert_eq!(assembly.rom, rom, "{} changed:\n{}", path.display(), source);
+        checked += 1;
+    }
+    assert!(checked >= 8);
+}
+
+#[test]
+fn data_that_looks_like_code_survives() {
+    // every word of this is data: 0x00xx words that are not instructions,
+    // skips with non-zero low nibbles, and a lone trailing byte
+    let rom = [0x12, 0x08, 0x01, 0xE0, 0x51, 0x2F, 0x00, 0x00, 0xA2, 0x02, 0x12, 0x08, 0xFF];
+    for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+        let source = disassemble(&rom, platform).unwrap();
+        assert_eq!(assemble(&source).unwrap().rom, rom, "{:?}:\n{}", platform, source);
+    }
+}